@@ -1,21 +1,319 @@
-use crate::util::{runtime_error, value_to_py};
+use crate::game_library::GameLibrary;
+use crate::http::{ClientOptions, HttpClient, HttpError, NetworkError};
+use crate::util::{extract_serde, runtime_error, value_to_py, Fields};
 use crate::json_result;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use futures::stream::{self, StreamExt};
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
 use pyo3::types::PyList;
 use pyo3::Py;
-use reqwest::{header::USER_AGENT, redirect::Policy, Client};
+use reqwest::header::{
+    HeaderMap, HeaderName, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE, LOCATION, RANGE,
+    USER_AGENT,
+};
+use reqwest::{Response, StatusCode};
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, RwLock,
-};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use url::Url;
+
+create_exception!(
+    vn_core,
+    DlsiteSessionExpired,
+    PyRuntimeError,
+    "The DLsite session is no longer valid; the user has to log in again."
+);
+create_exception!(
+    vn_core,
+    DlsiteNotPurchased,
+    PyRuntimeError,
+    "The work is not among the account's purchases."
+);
+create_exception!(
+    vn_core,
+    DlsiteBrowserOnly,
+    PyRuntimeError,
+    "The work can only be used in the DLsite browser viewer and has no download."
+);
+create_exception!(
+    vn_core,
+    DlsiteInvalidCredentials,
+    PyRuntimeError,
+    "DLsite rejected the login ID or password."
+);
+create_exception!(
+    vn_core,
+    DlsiteCaptchaRequired,
+    PyRuntimeError,
+    "DLsite wants a captcha solved before it accepts the login."
+);
+create_exception!(
+    vn_core,
+    DlsiteVerificationRequired,
+    PyRuntimeError,
+    "DLsite asks for an extra verification step, e.g. a code sent by mail."
+);
+create_exception!(
+    vn_core,
+    DlsiteMaintenance,
+    PyRuntimeError,
+    "DLsite is down for maintenance."
+);
+
+/// Store answers the caller has to tell apart. Carried inside `anyhow` errors and
+/// mapped to their own Python exception by `py_error`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum DlsiteError {
+    SessionExpired,
+    NotPurchased(String),
+    BrowserOnly(String),
+    InvalidCredentials,
+    CaptchaRequired,
+    VerificationRequired,
+    Maintenance,
+}
+
+impl fmt::Display for DlsiteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DlsiteError::SessionExpired => write!(f, "DLsite session expired"),
+            DlsiteError::NotPurchased(id) => write!(f, "{} has not been purchased", id),
+            DlsiteError::BrowserOnly(id) => {
+                write!(f, "{} is browser-only and cannot be downloaded", id)
+            }
+            DlsiteError::InvalidCredentials => {
+                write!(f, "DLsite rejected the login ID or password")
+            }
+            DlsiteError::CaptchaRequired => write!(
+                f,
+                "DLsite wants a captcha solved; log in once on the DLsite website and try again"
+            ),
+            DlsiteError::VerificationRequired => write!(
+                f,
+                "DLsite asks for additional verification; confirm the login on the DLsite \
+                 website and try again"
+            ),
+            DlsiteError::Maintenance => write!(f, "DLsite is under maintenance; try again later"),
+        }
+    }
+}
+
+impl std::error::Error for DlsiteError {}
+
+fn dlsite_error(err: &anyhow::Error) -> Option<&DlsiteError> {
+    err.chain().find_map(|cause| cause.downcast_ref::<DlsiteError>())
+}
+
+/// Age rating as the store files it; `product.json` uses 1–3, the purchase list names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AgeRating {
+    AllAges,
+    R15,
+    Adult,
+    #[default]
+    Unknown,
+}
+
+impl AgeRating {
+    fn from_value(value: &Value) -> Self {
+        let code = match value {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.trim().to_lowercase(),
+            _ => return AgeRating::Unknown,
+        };
+        match code.as_str() {
+            "1" | "all" | "all_ages" | "general" | "gen" => AgeRating::AllAges,
+            "2" | "r15" | "r-15" => AgeRating::R15,
+            "3" | "adult" | "r18" | "r-18" | "18" => AgeRating::Adult,
+            _ => AgeRating::Unknown,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            AgeRating::AllAges => "all_ages",
+            AgeRating::R15 => "r15",
+            AgeRating::Adult => "adult",
+            AgeRating::Unknown => "unknown",
+        }
+    }
+
+    /// The purchase list's own vocabulary, kept in `age_category`.
+    fn category(self) -> &'static str {
+        match self {
+            AgeRating::AllAges => "all",
+            AgeRating::R15 => "r15",
+            AgeRating::Adult => "adult",
+            AgeRating::Unknown => "",
+        }
+    }
+}
+
+/// Kind of work, from the `work_type` code and, for unknown codes, the file formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WorkCategory {
+    Game,
+    /// Voice dramas, ASMR and music.
+    Voice,
+    /// Manga, webtoons and novels.
+    Comic,
+    Cg,
+    /// Tools and image or audio material.
+    Tool,
+    Other,
+}
+
+impl WorkCategory {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "game" | "games" => Some(WorkCategory::Game),
+            "voice" | "asmr" | "audio" => Some(WorkCategory::Voice),
+            "comic" | "manga" => Some(WorkCategory::Comic),
+            "cg" => Some(WorkCategory::Cg),
+            "tool" | "tools" => Some(WorkCategory::Tool),
+            "other" => Some(WorkCategory::Other),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            WorkCategory::Game => "game",
+            WorkCategory::Voice => "voice",
+            WorkCategory::Comic => "comic",
+            WorkCategory::Cg => "cg",
+            WorkCategory::Tool => "tool",
+            WorkCategory::Other => "other",
+        }
+    }
+
+    fn from_work_type(code: &str) -> Option<Self> {
+        match code.trim().to_uppercase().as_str() {
+            "ACN" | "QIZ" | "ADV" | "RPG" | "TBL" | "DNV" | "SLN" | "TYP" | "STG" | "PZL"
+            | "ETC" => Some(WorkCategory::Game),
+            "SOU" | "MUS" => Some(WorkCategory::Voice),
+            "MNG" | "WBT" | "SCM" | "NRE" => Some(WorkCategory::Comic),
+            "ICG" => Some(WorkCategory::Cg),
+            "TOL" | "IMT" | "AMT" => Some(WorkCategory::Tool),
+            "MOV" | "ET3" => Some(WorkCategory::Other),
+            _ => None,
+        }
+    }
+
+    fn from_formats(formats: &[String]) -> Option<Self> {
+        let matches = |needles: &[&str]| formats_contain(formats, needles);
+        if matches(EXECUTABLE_FORMATS) {
+            Some(WorkCategory::Game)
+        } else if matches(&["mp3", "wav", "flac", "音声", "audio"]) {
+            Some(WorkCategory::Voice)
+        } else if matches(&["pdf", "マンガ", "comic", "電子書籍", "ebook"]) {
+            Some(WorkCategory::Comic)
+        } else if matches(&["jpeg", "jpg", "png", "画像", "image"]) {
+            Some(WorkCategory::Cg)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether a work can be installed and played on the Deck.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DeckVerdict {
+    /// An executable for Windows (run through Proton) or Linux.
+    Runnable,
+    /// Only playable in the DLsite browser viewer.
+    BrowserOnly,
+    /// Built for platforms the Deck cannot run, such as phones.
+    Unsupported,
+    NotAGame,
+    Unknown,
+}
+
+impl DeckVerdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeckVerdict::Runnable => "runnable",
+            DeckVerdict::BrowserOnly => "browser_only",
+            DeckVerdict::Unsupported => "unsupported",
+            DeckVerdict::NotAGame => "not_a_game",
+            DeckVerdict::Unknown => "unknown",
+        }
+    }
+}
+
+const EXECUTABLE_FORMATS: &[&str] = &["exe", "msi", "アプリケーション", "application"];
+
+fn formats_contain(formats: &[String], needles: &[&str]) -> bool {
+    formats.iter().any(|format| {
+        let format = format.to_lowercase();
+        needles.iter().any(|needle| format.contains(needle))
+    })
+}
+
+/// The same work published for another language under its own product ID.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct LanguageEdition {
+    product_id: String,
+    lang: String,
+    label: String,
+}
+
+/// A serial number or activation code the store issued for a purchased work.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct SerialKey {
+    pub code: String,
+    /// What the code is for, e.g. "Activation key", when the store says.
+    pub label: Option<String>,
+}
+
+impl SerialKey {
+    /// Entries are either bare codes or objects naming the code and what it is for.
+    fn from_value(value: &Value) -> Option<Self> {
+        let (code, label) = match value {
+            Value::String(code) => (code.clone(), None),
+            Value::Object(map) => {
+                let mut fields = Fields::new(map);
+                (
+                    fields.string(&["serial_number", "serial", "code", "key"])?,
+                    fields.string(&["label", "name", "type"]),
+                )
+            }
+            _ => return None,
+        };
+        let code = code.trim().to_string();
+        (!code.is_empty()).then_some(Self { code, label })
+    }
+
+    fn list_from(data: &Value) -> Vec<Self> {
+        let entries = match data {
+            Value::Array(entries) => Some(entries),
+            _ => ["serial_numbers", "serials", "items"]
+                .iter()
+                .find_map(|key| data.get(key).and_then(Value::as_array)),
+        };
+        let mut keys: Vec<Self> = Vec::new();
+        for key in entries.into_iter().flatten().filter_map(Self::from_value) {
+            if !keys.iter().any(|known| known.code == key.code) {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
 
 #[pyclass(module = "vn_core")]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct DlsiteProduct {
     #[pyo3(get)]
     pub id: String,
@@ -31,9 +329,17 @@ pub struct DlsiteProduct {
     pub work_type: String,
     #[pyo3(get)]
     pub age_category: String,
+    /// Current price, in yen; below `official_price` during a sale.
     #[pyo3(get)]
     pub price: i64,
     #[pyo3(get)]
+    pub official_price: i64,
+    /// Sale discount in percent.
+    #[pyo3(get)]
+    pub discount_rate: Option<u32>,
+    #[pyo3(get)]
+    pub sale_ends_at: Option<String>,
+    #[pyo3(get)]
     pub file_size: i64,
     #[pyo3(get)]
     pub release_date: String,
@@ -43,461 +349,1933 @@ pub struct DlsiteProduct {
     pub description: String,
     #[pyo3(get)]
     pub tags: Vec<String>,
+    #[pyo3(get)]
+    pub voice_actors: Vec<String>,
+    #[pyo3(get)]
+    pub scenario: Vec<String>,
+    #[pyo3(get)]
+    pub illustrators: Vec<String>,
+    /// Normalised like Hikari platforms: `windows`, `macos`, `android`, `ios`, `browser`.
+    #[pyo3(get)]
+    pub os: Vec<String>,
+    #[pyo3(get)]
+    pub file_formats: Vec<String>,
+    #[pyo3(get)]
+    pub series: Option<String>,
+    #[pyo3(get)]
+    pub sample_images: Vec<String>,
+    /// Average of the user ratings, 0–5.
+    #[pyo3(get)]
+    pub rating: Option<f64>,
+    #[pyo3(get)]
+    pub rating_count: Option<u64>,
+    #[pyo3(get)]
+    pub page_count: Option<u64>,
+    #[pyo3(get)]
+    pub duration_secs: Option<u64>,
+    age_rating: AgeRating,
+    language_editions: Vec<LanguageEdition>,
 }
 
 impl DlsiteProduct {
+    /// Built from a purchase list or search entry as well as from a `product.json` item;
+    /// fields missing from the source stay empty.
     fn from_value(data: &Value) -> Option<Self> {
+        let mut fields = Fields::new(data.as_object()?);
+        let id = fields.string(&["id", "workno", "product_id"])?;
+        let circle = fields.object(&["circle"]).map(Fields::new);
+        let (circle_id, circle_name) = match circle {
+            Some(mut circle) => (circle.string(&["id"]), circle.string(&["name"])),
+            None => (None, None),
+        };
+        let creators = fields.object(&["creaters", "creators"]);
+        let credits = |fields: &mut Fields, keys: &[&'static str]| {
+            let names = fields.strings(keys);
+            match creators {
+                Some(creators) if names.is_empty() => Fields::new(creators).strings(keys),
+                _ => names,
+            }
+        };
+        let voice_actors = credits(&mut fields, &["voice_actors", "voice_by"]);
+        let scenario = credits(&mut fields, &["scenario", "scenario_by"]);
+        let illustrators = credits(&mut fields, &["illustrators", "illust_by"]);
+
+        let age_value = fields.get(&["age_category", "age_rating"]);
+        let age_rating = age_value.map(AgeRating::from_value).unwrap_or_default();
+        let age_category = match age_value {
+            Some(Value::String(raw)) => raw.clone(),
+            _ => age_rating.category().to_string(),
+        };
+        let rating = fields.f64(&["rate_average_2dp", "rate_average", "rating"]).or_else(|| {
+            // The star average comes in tenths: 45 is 4.5 stars
+            fields.f64(&["rate_average_star"]).map(|stars| stars / 10.0)
+        });
+
+        let price = fields.u64(&["price", "official_price"]) as i64;
+        let official_price = match fields.u64(&["official_price", "regular_price"]) as i64 {
+            0 => price,
+            official => official,
+        };
+
         Some(Self {
-            id: data.get("id")?.as_str()?.to_string(),
-            title: data
-                .get("title")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            thumbnail: data
-                .get("thumbnail_url")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            group_id: data
-                .get("circle")
-                .and_then(Value::as_object)
-                .and_then(|obj| obj.get("id"))
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            group_name: data
-                .get("circle")
-                .and_then(Value::as_object)
-                .and_then(|obj| obj.get("name"))
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            work_type: data
-                .get("work_type")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            age_category: data
-                .get("age_category")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            price: data.get("price").and_then(Value::as_i64).unwrap_or(0),
-            file_size: data.get("file_size").and_then(Value::as_i64).unwrap_or(0),
-            release_date: data
-                .get("regist_date")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            purchased_at: data
-                .get("purchased_at")
-                .and_then(Value::as_str)
-                .map(|s| s.to_string()),
-            description: data
-                .get("description")
-                .and_then(Value::as_str)
-                .unwrap_or_default()
-                .to_string(),
-            tags: data
-                .get("genre")
+            id,
+            title: fields
+                .get(&["title", "work_name", "name"])
+                .and_then(localized)
+                .unwrap_or_default(),
+            thumbnail: fields
+                .get(&["thumbnail_url", "image_main", "work_image", "image_thum"])
+                .and_then(image_url)
+                .unwrap_or_default(),
+            group_id: circle_id
+                .or_else(|| fields.string(&["maker_id", "circle_id"]))
+                .unwrap_or_default(),
+            group_name: circle_name
+                .or_else(|| fields.string(&["maker_name", "circle_name"]))
+                .unwrap_or_default(),
+            work_type: fields.string(&["work_type"]).unwrap_or_default(),
+            age_category,
+            price,
+            official_price,
+            discount_rate: Some(fields.u64(&["discount_rate", "discount"]) as u32)
+                .filter(|rate| *rate > 0),
+            sale_ends_at: fields.string(&["campaign_end_date", "discount_end_date", "sale_end"]),
+            file_size: fields.u64(&["file_size", "content_length"]) as i64,
+            release_date: fields
+                .string(&["regist_date", "release_date", "sales_date"])
+                .unwrap_or_default(),
+            purchased_at: fields.string(&["purchased_at", "sales_date_purchased"]),
+            description: fields
+                .string(&["description", "intro_s", "intro"])
+                .unwrap_or_default(),
+            tags: fields.strings(&["genre", "genres", "tags"]),
+            voice_actors,
+            scenario,
+            illustrators,
+            os: supported_os(&mut fields),
+            file_formats: fields.strings(&["file_formats", "file_type_string", "file_type"]),
+            series: fields.string(&["series", "series_name", "title_name"]),
+            sample_images: fields
+                .get(&["image_samples", "sample_images", "samples"])
                 .and_then(Value::as_array)
-                .map(|arr| {
-                    arr.iter()
-                        .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                        .collect::<Vec<_>>()
-                })
+                .map(|images| images.iter().filter_map(image_url).collect())
                 .unwrap_or_default(),
+            rating,
+            rating_count: Some(fields.u64(&["rate_count", "rating_count"])).filter(|n| *n > 0),
+            page_count: Some(fields.u64(&["page_count", "page_number", "pages"]))
+                .filter(|n| *n > 0),
+            duration_secs: Some(fields.u64(&["duration_secs", "duration"])).filter(|n| *n > 0),
+            age_rating,
+            language_editions: fields
+                .objects(&["language_editions"])
+                .into_iter()
+                .filter_map(|edition| {
+                    let mut edition = Fields::new(edition);
+                    Some(LanguageEdition {
+                        product_id: edition.string(&["workno", "product_id", "id"])?,
+                        lang: edition.string(&["lang", "language"]).unwrap_or_default(),
+                        label: edition.string(&["label", "name"]).unwrap_or_default(),
+                    })
+                })
+                .collect(),
         })
     }
-}
-
-#[pyclass(module = "vn_core")]
-pub struct DlsiteClient {
-    client: Client,
-    base_url: String,
-    login_url: String,
-    play_api: String,
-    maniax_api: String,
-    user_agent: String,
-    cookie_store: Arc<CookieStoreMutex>,
-    logged_in: Arc<AtomicBool>,
-    // Simple in-memory cache for library calls
-    cached_products: Arc<RwLock<Option<(i64, Vec<DlsiteProduct>)>>>,
-}
-
-impl DlsiteClient {
-    fn build_client(user_agent: &str, cookie_store: &Arc<CookieStoreMutex>) -> Result<Client> {
-        Client::builder()
-            .user_agent(user_agent)
-            .cookie_provider(Arc::clone(cookie_store))
-            .redirect(Policy::none())
-            .build()
-            .context("failed to create DLsite HTTP client")
-    }
 
-    fn parse_product_list(data: &Value) -> Vec<DlsiteProduct> {
-        data.get("products")
-            .and_then(Value::as_array)
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(DlsiteProduct::from_value)
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default()
+    fn on_sale(&self) -> bool {
+        self.discount_rate.is_some() || (self.price > 0 && self.price < self.official_price)
     }
 
-    fn parse_search_results(data: &Value) -> Vec<DlsiteProduct> {
-        data.get("items")
-            .and_then(Value::as_array)
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(DlsiteProduct::from_value)
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default()
+    fn category(&self) -> WorkCategory {
+        WorkCategory::from_work_type(&self.work_type)
+            .or_else(|| WorkCategory::from_formats(&self.file_formats))
+            .unwrap_or(WorkCategory::Other)
     }
 
-    fn to_py_product_list(py: Python<'_>, products: Vec<DlsiteProduct>) -> PyResult<PyObject> {
-        let list = PyList::empty(py);
-        for product in products {
-            list.append(Py::new(py, product)?.into_py(py))?;
+    /// Executables win over everything else: a game that also has a browser version
+    /// still installs fine.
+    fn deck_verdict(&self) -> DeckVerdict {
+        if self.category() != WorkCategory::Game {
+            return DeckVerdict::NotAGame;
+        }
+        let desktop = self.os.iter().any(|os| os == "windows" || os == "linux");
+        let browser = (!self.os.is_empty() && self.os.iter().all(|os| os == "browser"))
+            || formats_contain(&self.file_formats, &["ブラウザ", "browser", "html"]);
+        if desktop || formats_contain(&self.file_formats, EXECUTABLE_FORMATS) {
+            DeckVerdict::Runnable
+        } else if browser {
+            DeckVerdict::BrowserOnly
+        } else if !self.os.is_empty() {
+            DeckVerdict::Unsupported
+        } else {
+            DeckVerdict::Unknown
         }
-        Ok(list.into())
     }
 
-    pub fn logged_in_sync(&self) -> bool {
-        self.logged_in.load(Ordering::SeqCst)
+    /// Fill whatever this entry lacks from `details` of the same work. Purchase data
+    /// such as `purchased_at` is only ever taken from the purchase list.
+    fn merge(&mut self, details: &DlsiteProduct) {
+        fn fill<T: Clone>(field: &mut T, other: &T, empty: impl Fn(&T) -> bool) {
+            if empty(field) && !empty(other) {
+                *field = other.clone();
+            }
+        }
+        fill(&mut self.title, &details.title, String::is_empty);
+        fill(&mut self.thumbnail, &details.thumbnail, String::is_empty);
+        fill(&mut self.group_id, &details.group_id, String::is_empty);
+        fill(&mut self.group_name, &details.group_name, String::is_empty);
+        fill(&mut self.work_type, &details.work_type, String::is_empty);
+        fill(&mut self.age_category, &details.age_category, String::is_empty);
+        fill(&mut self.price, &details.price, |price| *price == 0);
+        fill(&mut self.official_price, &details.official_price, |price| *price == 0);
+        fill(&mut self.discount_rate, &details.discount_rate, Option::is_none);
+        fill(&mut self.sale_ends_at, &details.sale_ends_at, Option::is_none);
+        fill(&mut self.file_size, &details.file_size, |size| *size == 0);
+        fill(&mut self.release_date, &details.release_date, String::is_empty);
+        fill(&mut self.description, &details.description, String::is_empty);
+        fill(&mut self.tags, &details.tags, Vec::is_empty);
+        fill(&mut self.voice_actors, &details.voice_actors, Vec::is_empty);
+        fill(&mut self.scenario, &details.scenario, Vec::is_empty);
+        fill(&mut self.illustrators, &details.illustrators, Vec::is_empty);
+        fill(&mut self.os, &details.os, Vec::is_empty);
+        fill(&mut self.file_formats, &details.file_formats, Vec::is_empty);
+        fill(&mut self.series, &details.series, Option::is_none);
+        fill(&mut self.sample_images, &details.sample_images, Vec::is_empty);
+        fill(&mut self.rating, &details.rating, Option::is_none);
+        fill(&mut self.rating_count, &details.rating_count, Option::is_none);
+        fill(&mut self.page_count, &details.page_count, Option::is_none);
+        fill(&mut self.duration_secs, &details.duration_secs, Option::is_none);
+        fill(&mut self.age_rating, &details.age_rating, |age| *age == AgeRating::Unknown);
+        fill(&mut self.language_editions, &details.language_editions, Vec::is_empty);
     }
 }
 
 #[pymethods]
-impl DlsiteClient {
-    #[new]
-    pub fn new() -> PyResult<Self> {
-        let user_agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36".to_string();
-        let cookie_store = Arc::new(CookieStoreMutex::new(CookieStore::default()));
-        let client = Self::build_client(&user_agent, &cookie_store)
-            .map_err(|err| runtime_error(err.to_string()))?;
-
-        Ok(Self {
-            client,
-            base_url: "https://www.dlsite.com".to_string(),
-            login_url: "https://login.dlsite.com".to_string(),
-            play_api: "https://play.dlsite.com/api".to_string(),
-            maniax_api: "https://www.dlsite.com/maniax/api".to_string(),
-            user_agent,
-            cookie_store,
-            logged_in: Arc::new(AtomicBool::new(false)),
-            cached_products: Arc::new(RwLock::new(None)),
-        })
+impl DlsiteProduct {
+    /// `"all_ages"`, `"r15"`, `"adult"` or `"unknown"`.
+    #[getter]
+    fn age_rating(&self) -> &'static str {
+        self.age_rating.as_str()
     }
 
-    pub fn initialize<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let user_agent = self.user_agent.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            // touch client to ensure it works
-            let _ = client
-                .get("https://www.dlsite.com")
-                .header(USER_AGENT, user_agent)
-                .send()
-                .await;
-            Ok(())
-        })
+    /// `{product_id, lang, label}` dicts, one per translated edition.
+    #[getter]
+    fn language_editions(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &serde_json::json!(self.language_editions))
     }
 
-    pub fn cleanup<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        self.logout(py)
+    #[getter(on_sale)]
+    fn py_on_sale(&self) -> bool {
+        self.on_sale()
     }
 
-    pub fn login<'py>(
-        &'py self,
-        py: Python<'py>,
-        username: String,
-        password: String,
-    ) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let base_url = self.base_url.clone();
-        let login_url = self.login_url.clone();
-        let cookie_store = Arc::clone(&self.cookie_store);
-        let logged_in = Arc::clone(&self.logged_in);
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            client
-                .get(format!("{}/maniax/login/=/skip_register/1", base_url))
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Initial cookie request failed: {}", err)))?;
-
-            let login_page = client
-                .get(format!("{}/login", login_url))
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to access login page: {}", err)))?;
-
-            let csrf_token = login_page
-                .cookies()
-                .find(|cookie| cookie.name() == "XSRF-TOKEN")
-                .map(|cookie| cookie.value().to_string())
-                .ok_or_else(|| runtime_error("CSRF token not found"))?;
-
-            let params = [
-                ("login_id", username),
-                ("password", password),
-                ("_token", csrf_token),
-            ];
-
-            let response = client
-                .post(format!("{}/login", login_url))
-                .form(&params)
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Login request failed: {}", err)))?;
+    /// `"game"`, `"voice"`, `"comic"`, `"cg"`, `"tool"` or `"other"`.
+    #[getter(category)]
+    fn py_category(&self) -> &'static str {
+        self.category().as_str()
+    }
 
-            let status = response.status();
-            if !(status.is_success() || status.is_redirection()) {
-                return Err(runtime_error(format!(
-                    "Invalid credentials (status {})",
-                    status
-                )));
-            }
+    /// `"runnable"`, `"browser_only"`, `"unsupported"`, `"not_a_game"` or `"unknown"`.
+    #[getter(deck_verdict)]
+    fn py_deck_verdict(&self) -> &'static str {
+        self.deck_verdict().as_str()
+    }
 
-            logged_in.store(true, Ordering::SeqCst);
-            if let Ok(store) = cookie_store.lock() {
-                if store.iter_any().count() == 0 {
-                    return Err(runtime_error("Login cookies not stored"));
-                }
-            }
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let mut value = serde_json::json!(self);
+        value["category"] = self.category().as_str().into();
+        value["deck_verdict"] = self.deck_verdict().as_str().into();
+        value["on_sale"] = self.on_sale().into();
+        value_to_py(py, &value)
+    }
+}
 
-            json_result!({
-                "success": true,
-                "message": "Login successful"
-            })
+/// Category names as passed from Python; `None` or an empty list means all of them.
+fn parse_categories(names: Option<Vec<String>>) -> Result<Option<Vec<WorkCategory>>> {
+    let names = names.unwrap_or_default();
+    if names.is_empty() {
+        return Ok(None);
+    }
+    names
+        .iter()
+        .map(|name| {
+            WorkCategory::from_str(name).ok_or_else(|| anyhow!("Invalid work category: {}", name))
         })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+fn filter_categories(
+    products: Vec<DlsiteProduct>,
+    categories: Option<&[WorkCategory]>,
+) -> Vec<DlsiteProduct> {
+    match categories {
+        Some(categories) => products
+            .into_iter()
+            .filter(|product| categories.contains(&product.category()))
+            .collect(),
+        None => products,
     }
+}
 
-    pub fn logout<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let cookie_store = Arc::clone(&self.cookie_store);
-        let logged_in = Arc::clone(&self.logged_in);
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            logged_in.store(false, Ordering::SeqCst);
-            if let Ok(mut store) = cookie_store.lock() {
-                store.clear();
-            }
-            Ok(true)
-        })
+/// A plain string, or the Japanese (else English, else any) text of a localised object.
+fn localized(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(s) => Some(s.as_str()),
+        Value::Object(map) => ["ja_JP", "ja", "en_US", "en"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(Value::as_str))
+            .or_else(|| map.values().find_map(Value::as_str)),
+        _ => None,
+    }?;
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// Image URL from a string or an `{url: ...}` object; protocol-relative URLs get https.
+fn image_url(value: &Value) -> Option<String> {
+    let url = match value {
+        Value::String(s) => s.as_str(),
+        Value::Object(map) => map.get("url").and_then(Value::as_str)?,
+        _ => return None,
+    }
+    .trim();
+    if url.is_empty() {
+        None
+    } else if url.starts_with("//") {
+        Some(format!("https:{}", url))
+    } else {
+        Some(url.to_string())
     }
+}
 
-    pub fn is_logged_in<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let logged = self.logged_in.load(Ordering::SeqCst);
-        pyo3_asyncio::tokio::future_into_py(py, async move { Ok(logged) })
+/// Operating systems from an `os`/`platform` list and the `#`-joined `options` codes,
+/// which also carry non-OS flags that are skipped here.
+fn supported_os(fields: &mut Fields) -> Vec<String> {
+    let mut codes = fields.strings(&["os", "platform", "supported_os"]);
+    if let Some(options) = fields.string(&["options"]) {
+        codes.extend(options.split('#').map(str::to_string));
+    }
+    let mut out: Vec<String> = Vec::new();
+    for os in codes.iter().filter_map(|code| normalize_os(code)) {
+        if !out.iter().any(|known| known == os) {
+            out.push(os.to_string());
+        }
     }
+    out
+}
 
-    pub fn test_authentication<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let play_api = self.play_api.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let resp = client
-                .get(format!("{}/product_count", play_api))
-                .send()
-                .await;
-            Ok(matches!(resp, Ok(ref response) if response.status().is_success()))
-        })
+fn normalize_os(code: &str) -> Option<&'static str> {
+    match code.trim().to_lowercase().as_str() {
+        "win" | "win32" | "win64" | "windows" | "pc" => Some("windows"),
+        "mac" | "macos" | "osx" => Some("macos"),
+        "and" | "android" => Some("android"),
+        "ios" | "iphone" => Some("ios"),
+        "linux" => Some("linux"),
+        "browser" | "web" | "html5" => Some("browser"),
+        _ => None,
     }
+}
 
-    pub fn get_library<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let play_api = self.play_api.clone();
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let count_resp = client
-                .get(format!("{}/product_count", play_api))
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to get product count: {}", err)))?;
+/// Results per search page unless the query asks otherwise, and the most the API serves.
+const SEARCH_PAGE_SIZE: u32 = 50;
+const MAX_SEARCH_PAGE_SIZE: u32 = 100;
 
-            if !count_resp.status().is_success() {
-                return Ok(Python::with_gil(|py| PyList::empty(py).into()));
-            }
+/// Sort orders the search API understands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum SearchOrder {
+    #[default]
+    Trend,
+    Newest,
+    Oldest,
+    PriceLow,
+    PriceHigh,
+    Rating,
+    Downloads,
+}
 
-            let count_json: Value = count_resp
-                .json()
-                .await
-                .map_err(|err| runtime_error(format!("Invalid count response: {}", err)))?;
-
-            let total_products = count_json
-                .get("product_count")
-                .and_then(Value::as_i64)
-                .unwrap_or(0);
-            if total_products <= 0 {
-                return Ok(Python::with_gil(|py| PyList::empty(py).into()));
-            }
-
-            let mut collected = Vec::new();
-            let page_limit = 50;
-            let total_pages = ((total_products as f64) / page_limit as f64).ceil() as i64;
-
-            for page in 1..=total_pages {
-                let response = client
-                    .get(format!("{}/purchases", play_api))
-                    .query(&[("page", page)])
-                    .send()
-                    .await;
-
-                let response = match response {
-                    Ok(resp) => resp,
-                    Err(_) => continue,
-                };
+impl SearchOrder {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "trend" | "popular" => Some(SearchOrder::Trend),
+            "newest" | "release_desc" => Some(SearchOrder::Newest),
+            "oldest" | "release_asc" => Some(SearchOrder::Oldest),
+            "price_asc" | "cheapest" => Some(SearchOrder::PriceLow),
+            "price_desc" => Some(SearchOrder::PriceHigh),
+            "rating" => Some(SearchOrder::Rating),
+            "downloads" => Some(SearchOrder::Downloads),
+            _ => None,
+        }
+    }
 
-                if !response.status().is_success() {
-                    continue;
-                }
+    fn as_param(self) -> &'static str {
+        match self {
+            SearchOrder::Trend => "trend",
+            SearchOrder::Newest => "release_d",
+            SearchOrder::Oldest => "release",
+            SearchOrder::PriceLow => "price",
+            SearchOrder::PriceHigh => "price_d",
+            SearchOrder::Rating => "rate_d",
+            SearchOrder::Downloads => "dl_d",
+        }
+    }
+}
 
-                let page_json: Value = match response.json().await {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                };
+/// Store search as passed from Python: a keyword plus optional filters. Dates are
+/// `YYYY-MM-DD`, prices in yen, `page` starts at 1.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SearchQuery {
+    keyword: String,
+    category: Option<String>,
+    genres: Vec<u64>,
+    /// Circle (maker) ID such as `RG10001`.
+    circle: Option<String>,
+    age_category: Option<String>,
+    min_price: Option<u64>,
+    max_price: Option<u64>,
+    released_after: Option<String>,
+    released_before: Option<String>,
+    language: Option<String>,
+    order: Option<String>,
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
 
-                collected.extend(Self::parse_product_list(&page_json));
-            }
+impl SearchQuery {
+    fn page(&self) -> u32 {
+        self.page.unwrap_or(1)
+    }
 
-            Python::with_gil(|py| Self::to_py_product_list(py, collected))
-        })
+    fn per_page(&self) -> u32 {
+        self.per_page.unwrap_or(SEARCH_PAGE_SIZE)
     }
 
-    /// Cached library with optional force refresh and TTL.
-    /// Default TTL = 45 seconds when not provided.
-    pub fn get_library_cached<'py>(
-        &'py self,
-        py: Python<'py>,
-        force_refresh: Option<bool>,
-        ttl_seconds: Option<i64>,
-    ) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let play_api = self.play_api.clone();
-        let cache = Arc::clone(&self.cached_products);
-        let ttl = ttl_seconds.unwrap_or(45);
-        let force = force_refresh.unwrap_or(false);
+    /// Query string for the search API, rejecting filters that cannot be right.
+    fn params(&self) -> Result<Vec<(&'static str, String)>> {
+        let order = match self.order.as_deref() {
+            Some(order) => SearchOrder::from_str(order)
+                .ok_or_else(|| anyhow!("Invalid search order: {}", order))?,
+            None => SearchOrder::default(),
+        };
+        if self.page() == 0 {
+            bail!("Search pages start at 1");
+        }
+        if !(1..=MAX_SEARCH_PAGE_SIZE).contains(&self.per_page()) {
+            bail!("per_page must be between 1 and {}", MAX_SEARCH_PAGE_SIZE);
+        }
 
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            // Use cache if valid and not forced
-            if !force {
-                if let Ok(guard) = cache.read() {
-                    if let Some((ts, ref items)) = *guard {
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs() as i64;
-                        if now - ts <= ttl {
-                            // Return cached copy
-                            return Python::with_gil(|py| {
-                                Self::to_py_product_list(py, items.clone())
-                            });
-                        }
-                    }
-                }
+        let mut params = vec![
+            ("keyword", self.keyword.trim().to_string()),
+            ("order", order.as_param().to_string()),
+            ("page", self.page().to_string()),
+            ("per_page", self.per_page().to_string()),
+            ("category", self.category.clone().unwrap_or_else(|| "all".to_string())),
+        ];
+        if !self.genres.is_empty() {
+            let genres: Vec<String> = self.genres.iter().map(u64::to_string).collect();
+            params.push(("genre", genres.join(",")));
+        }
+        if let Some(circle) = self.circle.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+            params.push(("maker_id", circle.to_string()));
+        }
+        if let Some(age) = self.age_category.as_deref() {
+            let code = match AgeRating::from_value(&Value::String(age.to_string())) {
+                AgeRating::AllAges => 1,
+                AgeRating::R15 => 2,
+                AgeRating::Adult => 3,
+                AgeRating::Unknown => bail!("Invalid age category: {}", age),
+            };
+            params.push(("age_category", code.to_string()));
+        }
+        if let (Some(min), Some(max)) = (self.min_price, self.max_price) {
+            if min > max {
+                bail!("min_price {} is above max_price {}", min, max);
             }
-
-            // Fetch count
-            let count_resp = client
-                .get(format!("{}/product_count", play_api))
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to get product count: {}", err)))?;
-
-            if !count_resp.status().is_success() {
-                return Python::with_gil(|py| Ok(PyList::empty(py).into()));
+        }
+        if let Some(min) = self.min_price {
+            params.push(("price_low", min.to_string()));
+        }
+        if let Some(max) = self.max_price {
+            params.push(("price_high", max.to_string()));
+        }
+        let after = self.released_after.as_deref().map(search_date).transpose()?;
+        let before = self.released_before.as_deref().map(search_date).transpose()?;
+        if let (Some(after), Some(before)) = (after, before) {
+            if after > before {
+                bail!("released_after {} is later than released_before {}", after, before);
             }
+        }
+        if let Some(after) = after {
+            params.push(("regist_date_start", after.to_string()));
+        }
+        if let Some(before) = before {
+            params.push(("regist_date_end", before.to_string()));
+        }
+        if let Some(language) = self.language.as_deref().map(str::trim).filter(|l| !l.is_empty()) {
+            params.push(("language", language.to_string()));
+        }
+        Ok(params)
+    }
+}
 
-            let count_json: Value = count_resp
-                .json()
-                .await
-                .map_err(|err| runtime_error(format!("Invalid count response: {}", err)))?;
-
-            let total_products = count_json
-                .get("product_count")
-                .and_then(Value::as_i64)
-                .unwrap_or(0);
-            if total_products <= 0 {
-                return Python::with_gil(|py| Ok(PyList::empty(py).into()));
-            }
-
-            let mut collected = Vec::new();
-            let page_limit = 50;
-            let total_pages = ((total_products as f64) / page_limit as f64).ceil() as i64;
-
-            for page in 1..=total_pages {
-                let response = client
-                    .get(format!("{}/purchases", play_api))
-                    .query(&[("page", page)])
-                    .send()
-                    .await;
-
-                let response = match response {
-                    Ok(resp) => resp,
-                    Err(_) => continue,
-                };
-
-                if !response.status().is_success() {
-                    continue;
-                }
+fn search_date(value: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid date {}: expected YYYY-MM-DD", value))
+}
 
-                let page_json: Value = match response.json().await {
-                    Ok(value) => value,
-                    Err(_) => continue,
-                };
+/// One page of search results.
+#[pyclass(module = "vn_core")]
+#[derive(Clone, Debug)]
+pub struct DlsiteSearchPage {
+    #[pyo3(get)]
+    pub items: Vec<DlsiteProduct>,
+    /// Matches across all pages.
+    #[pyo3(get)]
+    pub total: u64,
+    #[pyo3(get)]
+    pub page: u32,
+    #[pyo3(get)]
+    pub per_page: u32,
+}
+
+impl DlsiteSearchPage {
+    /// A response without `items` is an error, not an empty page; a missing count is
+    /// estimated from the items on this page.
+    fn from_value(data: &Value, page: u32, per_page: u32) -> Result<Self> {
+        let items: Vec<DlsiteProduct> = data
+            .get("items")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Unexpected search response: no items"))?
+            .iter()
+            .filter_map(DlsiteProduct::from_value)
+            .collect();
+        let total = ["count", "total", "total_count"]
+            .iter()
+            .find_map(|key| data.get(*key).and_then(Value::as_u64))
+            .unwrap_or((page as u64 - 1) * per_page as u64 + items.len() as u64);
+        Ok(Self {
+            items,
+            total,
+            page,
+            per_page,
+        })
+    }
+}
+
+#[pymethods]
+impl DlsiteSearchPage {
+    #[getter]
+    fn has_next(&self) -> bool {
+        (self.page as u64) * (self.per_page as u64) < self.total
+    }
+
+    fn __len__(&self) -> usize {
+        self.items.len()
+    }
+}
+
+fn now_secs() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn purchased_at(product: &DlsiteProduct) -> Option<DateTime<FixedOffset>> {
+    product
+        .purchased_at
+        .as_deref()
+        .and_then(|at| DateTime::parse_from_rfc3339(at).ok())
+}
+
+/// Works asked for per `product.json` request when enriching products.
+const PRODUCT_DETAILS_BATCH: usize = 50;
+
+/// Upper bound on wishlist pages, in case the API keeps reporting more.
+const MAX_WISHLIST_PAGES: u64 = 100;
+
+/// Products per page of the purchases API.
+const LIBRARY_PAGE_SIZE: i64 = 50;
+/// Purchase pages fetched at the same time during a sync.
+const LIBRARY_PAGE_CONCURRENCY: usize = 4;
+/// Tries per purchase page before it is reported missing.
+const LIBRARY_PAGE_ATTEMPTS: u32 = 3;
+const LIBRARY_PAGE_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// The library as of the last sync, and the base of the next incremental one.
+#[derive(Clone, Debug)]
+struct LibrarySnapshot {
+    products: Vec<DlsiteProduct>,
+    fetched_at: i64,
+    /// Newest purchase seen by a sync that got every page. Only purchases after it are
+    /// fetched next time; `None` means the next sync has to be a full one.
+    watermark: Option<DateTime<FixedOffset>>,
+    /// Purchase pages that still failed after retrying.
+    missing_pages: Vec<i64>,
+    incremental: bool,
+    /// Products that were not in the previous snapshot.
+    new_products: usize,
+}
+
+impl LibrarySnapshot {
+    fn empty() -> Self {
+        Self {
+            products: Vec::new(),
+            fetched_at: now_secs(),
+            watermark: None,
+            missing_pages: Vec::new(),
+            incremental: false,
+            new_products: 0,
+        }
+    }
+
+    fn complete(&self) -> bool {
+        self.missing_pages.is_empty()
+    }
+
+    /// A complete snapshot younger than `ttl` seconds.
+    fn fresh(&self, ttl: i64) -> bool {
+        self.complete() && now_secs() - self.fetched_at <= ttl
+    }
+
+    fn report(&self) -> Value {
+        serde_json::json!({
+            "total": self.products.len(),
+            "new": self.new_products,
+            "incremental": self.incremental,
+            "complete": self.complete(),
+            "missing_pages": self.missing_pages,
+            "synced_at": self.fetched_at,
+        })
+    }
+}
+
+/// Store endpoints. All of them can be overridden, e.g. to point the client at a mirror
+/// or at a local test server.
+#[derive(Clone, Debug)]
+struct DlsiteEndpoints {
+    base_url: String,
+    login_url: String,
+    play_api: String,
+    maniax_api: String,
+    download_api: String,
+}
+
+impl Default for DlsiteEndpoints {
+    fn default() -> Self {
+        Self {
+            base_url: "https://www.dlsite.com".to_string(),
+            login_url: "https://login.dlsite.com".to_string(),
+            play_api: "https://play.dlsite.com/api".to_string(),
+            maniax_api: "https://www.dlsite.com/maniax/api".to_string(),
+            download_api: "https://play.dl.dlsite.com/api".to_string(),
+        }
+    }
+}
+
+fn trim_endpoint(url: String) -> String {
+    url.trim_end_matches('/').to_string()
+}
+
+/// Cookie jar saved after login, in the plugin data dir.
+const COOKIE_FILE_NAME: &str = "dlsite_cookies.json";
+
+/// Where the session stands, as far as the client knows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AuthState {
+    LoggedOut,
+    /// Cookies restored from disk that the store has not confirmed yet.
+    Restored,
+    /// The store accepted the cookies on the last check or login.
+    Verified,
+}
+
+impl AuthState {
+    fn as_str(self) -> &'static str {
+        match self {
+            AuthState::LoggedOut => "logged_out",
+            AuthState::Restored => "restored",
+            AuthState::Verified => "verified",
+        }
+    }
+}
+
+/// Saved cookie jar, including session cookies. `None` if missing or unreadable.
+fn load_cookies(path: &Path) -> Option<CookieStore> {
+    let file = std::fs::File::open(path).ok()?;
+    CookieStore::load_json_all(std::io::BufReader::new(file)).ok()
+}
+
+/// Redirect hops followed before giving up on a download link.
+const MAX_DOWNLOAD_REDIRECTS: usize = 10;
+
+/// Text on the download page of works that are only readable in the browser viewer.
+const BROWSER_ONLY_MARKERS: &[&str] = &["ブラウザ視聴", "ブラウザ専用", "browser_only"];
+
+/// Redirects followed after posting the login form.
+const MAX_LOGIN_REDIRECTS: usize = 5;
+/// Signs, in a URL or page, of the extra steps DLsite sometimes puts in front of a login.
+const CAPTCHA_MARKERS: &[&str] = &["captcha"];
+const VERIFICATION_MARKERS: &[&str] = &[
+    "two_factor",
+    "two-factor",
+    "2fa",
+    "/verify",
+    "verification_code",
+    "確認コード",
+    "認証コード",
+    "2段階認証",
+];
+const MAINTENANCE_MARKERS: &[&str] = &["maintenance", "メンテナンス"];
+
+fn has_marker(text: &str, markers: &[&str]) -> bool {
+    markers.iter().any(|marker| text.contains(marker))
+}
+
+/// Extra step a login landed on, judging by the URL alone.
+fn login_step(url: &Url) -> Option<DlsiteError> {
+    let url = url.as_str().to_lowercase();
+    if has_marker(&url, CAPTCHA_MARKERS) {
+        Some(DlsiteError::CaptchaRequired)
+    } else if has_marker(&url, VERIFICATION_MARKERS) {
+        Some(DlsiteError::VerificationRequired)
+    } else {
+        None
+    }
+}
+
+/// Why a login was turned down, from where it ended and the page shown there. Without
+/// any sign of an extra step or maintenance, the credentials were wrong.
+fn login_failure(url: &Url, body: &str) -> DlsiteError {
+    login_obstacle(url, body).unwrap_or(DlsiteError::InvalidCredentials)
+}
+
+/// A captcha, extra verification step or maintenance notice standing in the way of
+/// a login, whatever the credentials.
+fn login_obstacle(url: &Url, body: &str) -> Option<DlsiteError> {
+    let body = body.to_lowercase();
+    login_step(url).or(if has_marker(&body, CAPTCHA_MARKERS) {
+        Some(DlsiteError::CaptchaRequired)
+    } else if has_marker(&body, VERIFICATION_MARKERS) {
+        Some(DlsiteError::VerificationRequired)
+    } else if has_marker(&body, MAINTENANCE_MARKERS) {
+        Some(DlsiteError::Maintenance)
+    } else {
+        None
+    })
+}
+
+fn is_maintenance(response: &Response) -> bool {
+    response.status() == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// One file of a purchased work, with the final URL `DownloadManager` can fetch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct DownloadPart {
+    /// 1-based; works that are not split have a single part 1.
+    part: u32,
+    url: String,
+    filename: String,
+    size: Option<u64>,
+}
+
+impl DownloadPart {
+    /// Built from the response to the one-byte range request at the end of the chain.
+    fn from_response(part: u32, url: &Url, response: &Response) -> Self {
+        let headers = response.headers();
+        let filename = header_str(headers, CONTENT_DISPOSITION)
+            .and_then(disposition_filename)
+            .or_else(|| {
+                url.path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .filter(|name| !name.is_empty())
+                    .map(|name| {
+                        percent_encoding::percent_decode_str(name)
+                            .decode_utf8_lossy()
+                            .into_owned()
+                    })
+            })
+            .unwrap_or_else(|| format!("part{}", part));
+        // Servers that ignore the range send the whole file with its full length
+        let size = header_str(headers, CONTENT_RANGE)
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.trim().parse().ok())
+            .or_else(|| {
+                (response.status() == StatusCode::OK)
+                    .then(|| response.content_length())
+                    .flatten()
+            });
+        Self {
+            part,
+            url: url.to_string(),
+            filename,
+            size,
+        }
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn is_html(response: &Response) -> bool {
+    header_str(response.headers(), CONTENT_TYPE)
+        .map(|value| value.trim_start().to_ascii_lowercase().starts_with("text/html"))
+        .unwrap_or(false)
+}
+
+/// `filename*=UTF-8''…` wins over a plain `filename=`, as in browsers.
+fn disposition_filename(value: &str) -> Option<String> {
+    let mut plain = None;
+    for param in value.split(';') {
+        let Some((key, raw)) = param.split_once('=') else {
+            continue;
+        };
+        let raw = raw.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                if let Some(encoded) = raw.splitn(3, '\'').nth(2) {
+                    let name = percent_encoding::percent_decode_str(encoded).decode_utf8_lossy();
+                    if !name.is_empty() {
+                        return Some(name.into_owned());
+                    }
+                }
+            }
+            "filename" => plain = Some(raw.trim_matches('"').to_string()),
+            _ => {}
+        }
+    }
+    plain.filter(|name| !name.is_empty())
+}
+
+/// Links to the numbered parts on the listing DLsite shows for split works, ordered by
+/// part number and resolved against the listing URL.
+fn split_part_links(html: &str, product_id: &str, page: &Url) -> Vec<(u32, Url)> {
+    let mut parts: Vec<(u32, Url)> = Vec::new();
+    for chunk in html.split("href=").skip(1) {
+        let href = match chunk.chars().next() {
+            Some(quote @ ('"' | '\'')) => chunk[1..].split(quote).next(),
+            _ => chunk.split(|c: char| c.is_whitespace() || c == '>').next(),
+        }
+        .unwrap_or_default()
+        .replace("&amp;", "&");
+        let Some(number) = part_number(&href, product_id) else {
+            continue;
+        };
+        if parts.iter().any(|(known, _)| *known == number) {
+            continue;
+        }
+        if let Ok(url) = page.join(&href) {
+            parts.push((number, url));
+        }
+    }
+    parts.sort_by_key(|(number, _)| *number);
+    parts
+}
+
+/// Part number of a `/download/=/number/{n}/product_id/{id}.html` link.
+fn part_number(href: &str, product_id: &str) -> Option<u32> {
+    if !href.contains("/download/") || !href.contains(&format!("/product_id/{}", product_id)) {
+        return None;
+    }
+    let (_, rest) = href.split_once("/number/")?;
+    rest.split('/').next()?.parse().ok()
+}
+
+/// Pass successful responses of account APIs (purchases, wishlist) through; a login
+/// redirect or an unauthorised status means the session is gone.
+fn checked_account_response(response: Response, what: &str) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else if matches!(status.as_u16(), 401 | 403) || status.is_redirection() {
+        Err(DlsiteError::SessionExpired.into())
+    } else {
+        Err(anyhow!("{} failed (status {})", what, status))
+    }
+}
+
+/// HTTP side of the client, cloned into the futures handed to Python. Returns plain
+/// `anyhow` errors so it can run without an interpreter.
+#[derive(Clone)]
+struct DlsiteSession {
+    http: HttpClient,
+    endpoints: Arc<DlsiteEndpoints>,
+    cookie_store: Arc<CookieStoreMutex>,
+    auth: Arc<RwLock<AuthState>>,
+    cookie_path: Option<PathBuf>,
+    /// Held for the whole of a sync, so concurrent syncs queue up instead of racing.
+    library: Arc<Mutex<Option<LibrarySnapshot>>>,
+}
+
+impl DlsiteSession {
+    /// With `cookie_path`, cookies saved by an earlier login are restored right away;
+    /// they still need `validate` before they can be trusted.
+    fn new(
+        endpoints: DlsiteEndpoints,
+        user_agent: &str,
+        cookie_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let restored = cookie_path
+            .as_deref()
+            .and_then(load_cookies)
+            .filter(|store| store.iter_unexpired().next().is_some());
+        let auth = if restored.is_some() {
+            AuthState::Restored
+        } else {
+            AuthState::LoggedOut
+        };
+        let cookie_store = Arc::new(CookieStoreMutex::new(restored.unwrap_or_default()));
+        // Redirects are inspected by hand, e.g. to tell a login redirect from a success
+        let http = HttpClient::new(ClientOptions {
+            user_agent: user_agent.to_string(),
+            cookies: Some(Arc::clone(&cookie_store)),
+            no_redirects: true,
+            ..ClientOptions::default()
+        })?;
+        Ok(Self {
+            http,
+            endpoints: Arc::new(endpoints),
+            cookie_store,
+            auth: Arc::new(RwLock::new(auth)),
+            cookie_path,
+            library: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    fn auth_state(&self) -> AuthState {
+        let state = self.auth.read().map(|guard| *guard).unwrap_or(AuthState::LoggedOut);
+        // Cookies can expire while the client sits idle
+        if state != AuthState::LoggedOut && !self.has_cookies() {
+            return AuthState::LoggedOut;
+        }
+        state
+    }
+
+    fn set_auth(&self, state: AuthState) {
+        if let Ok(mut guard) = self.auth.write() {
+            *guard = state;
+        }
+    }
+
+    fn logged_in(&self) -> bool {
+        self.auth_state() != AuthState::LoggedOut
+    }
+
+    fn has_cookies(&self) -> bool {
+        self.cookie_store
+            .lock()
+            .map(|store| store.iter_unexpired().next().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Save the cookie jar, session cookies included, readable by the user only.
+    async fn persist_cookies(&self) -> Result<()> {
+        let Some(path) = self.cookie_path.as_ref() else {
+            return Ok(());
+        };
+        let mut payload = Vec::new();
+        {
+            let store = self
+                .cookie_store
+                .lock()
+                .map_err(|_| anyhow!("cookie store lock poisoned"))?;
+            store
+                .save_incl_expired_and_nonpersistent_json(&mut payload)
+                .map_err(|err| anyhow!("Failed to encode DLsite cookies: {}", err))?;
+        }
+
+        let tmp = path.with_extension("json.tmp");
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Created private from the start; a leftover temp file could carry other modes
+        let _ = tokio::fs::remove_file(&tmp).await;
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp).await?;
+        file.write_all(&payload).await?;
+        file.flush().await?;
+        drop(file);
+        tokio::fs::rename(&tmp, path)
+            .await
+            .context("Failed to save DLsite cookies")
+    }
+
+    /// Drop the session: cookies in memory, the saved jar and the synced library.
+    async fn logout(&self) {
+        self.set_auth(AuthState::LoggedOut);
+        *self.library.lock().await = None;
+        if let Ok(mut store) = self.cookie_store.lock() {
+            store.clear();
+        }
+        if let Some(path) = self.cookie_path.as_ref() {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    /// Log in and confirm the new session with the store. A failed attempt leaves the
+    /// client logged out, whatever cookies the store handed out on the way.
+    async fn login(&self, username: String, password: String) -> Result<()> {
+        let result = self.try_login(username, password).await;
+        if result.is_err() {
+            self.logout().await;
+        }
+        result
+    }
+
+    async fn try_login(&self, username: String, password: String) -> Result<()> {
+        let endpoints = &self.endpoints;
+        let request = self
+            .http
+            .get(format!("{}/maniax/login/=/skip_register/1", endpoints.base_url));
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Initial cookie request failed")?;
+        if is_maintenance(&response) {
+            return Err(DlsiteError::Maintenance.into());
+        }
+
+        let form_url = format!("{}/login", endpoints.login_url);
+        let request = self.http.get(&form_url);
+        let login_page = self
+            .http
+            .send(request)
+            .await
+            .context("Failed to access login page")?;
+        if is_maintenance(&login_page) {
+            return Err(DlsiteError::Maintenance.into());
+        }
+
+        let csrf_token = login_page
+            .cookies()
+            .find(|cookie| cookie.name() == "XSRF-TOKEN")
+            .map(|cookie| cookie.value().to_string())
+            .ok_or_else(|| anyhow!("CSRF token not found"))?;
+
+        let params = [
+            ("login_id", username),
+            ("password", password),
+            ("_token", csrf_token),
+        ];
+
+        let request = self.http.post(&form_url).form(&params);
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Login request failed")?;
+        let form_url = Url::parse(&form_url).context("Invalid login URL")?;
+        self.check_login_response(&form_url, response).await?;
+        self.confirm_login().await
+    }
+
+    /// Follow where the login form post leads. Landing back on the form, or the post
+    /// being rejected with 401/422, is a failed login. Other refusals of the post, such
+    /// as an expired form token, say nothing about the credentials.
+    async fn check_login_response(&self, form_url: &Url, mut response: Response) -> Result<()> {
+        let posted = response.status();
+        let rejected = matches!(
+            posted,
+            StatusCode::UNAUTHORIZED | StatusCode::UNPROCESSABLE_ENTITY
+        );
+        let mut url = form_url.clone();
+        let mut redirected = false;
+        for _ in 0..MAX_LOGIN_REDIRECTS {
+            if is_maintenance(&response) {
+                return Err(DlsiteError::Maintenance.into());
+            }
+            if !response.status().is_redirection() {
+                let body = response.text().await.unwrap_or_default();
+                // The post itself answers from the form's URL; only a redirect lands back
+                let back_on_form = redirected && url.path() == form_url.path();
+                let on_form = back_on_form || body.contains("name=\"login_id\"");
+                if rejected || on_form {
+                    return Err(login_failure(&url, &body).into());
+                }
+                if posted.is_client_error() {
+                    return match login_obstacle(&url, &body) {
+                        Some(obstacle) => Err(obstacle.into()),
+                        None => bail!("DLsite refused the login (status {}), try again", posted),
+                    };
+                }
+                return match login_step(&url) {
+                    Some(step) => Err(step.into()),
+                    None => Ok(()),
+                };
+            }
+            let location = header_str(response.headers(), LOCATION)
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Login redirect without a location"))?;
+            url = url.join(&location).context("Invalid login redirect")?;
+            redirected = true;
+            if let Some(step) = login_step(&url) {
+                return Err(step.into());
+            }
+            let request = self.http.get(url.clone());
+            response = self
+                .http
+                .send(request)
+                .await
+                .context("Following the login redirect failed")?;
+        }
+        bail!("Too many login redirects")
+    }
+
+    /// Ask an endpoint that needs a session whether the login took.
+    async fn confirm_login(&self) -> Result<()> {
+        if !self.has_cookies() {
+            bail!("Login cookies not stored");
+        }
+        let request = self.http.get(format!("{}/product_count", self.endpoints.play_api));
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Login check failed")?;
+        if is_maintenance(&response) {
+            return Err(DlsiteError::Maintenance.into());
+        }
+        if !response.status().is_success() {
+            bail!("DLsite did not accept the login (status {})", response.status());
+        }
+        self.set_auth(AuthState::Verified);
+        let _ = self.persist_cookies().await;
+        Ok(())
+    }
+
+    /// Ask the store whether the current cookies are still a valid session. A rejection
+    /// ends the session and deletes the saved jar; a network failure leaves it alone.
+    async fn validate(&self) -> Result<bool> {
+        if !self.has_cookies() {
+            self.set_auth(AuthState::LoggedOut);
+            return Ok(false);
+        }
+        let request = self.http.get(format!("{}/product_count", self.endpoints.play_api));
+        let status = self
+            .http
+            .send(request)
+            .await
+            .context("Session check failed")?
+            .status();
+
+        if status.is_success() {
+            self.set_auth(AuthState::Verified);
+            // The store may have rotated cookies on the way
+            let _ = self.persist_cookies().await;
+            Ok(true)
+        } else if matches!(status.as_u16(), 401 | 403) || status.is_redirection() {
+            self.logout().await;
+            Ok(false)
+        } else {
+            Err(anyhow!("Session check failed (status {})", status))
+        }
+    }
+
+    async fn is_authenticated(&self) -> bool {
+        self.validate().await.unwrap_or(false)
+    }
+
+    /// Sync the library and keep the result as the new snapshot. Unless `full` is set,
+    /// only purchases newer than the last complete sync are fetched and merged into it.
+    /// Pages that keep failing are listed in `missing_pages` instead of failing the sync.
+    async fn sync_library(&self, full: bool) -> Result<LibrarySnapshot> {
+        let mut guard = self.library.lock().await;
+        if !self.has_cookies() {
+            return Ok(LibrarySnapshot::empty());
+        }
+        match self.sync_purchases(guard.as_ref(), full).await {
+            Ok(snapshot) => {
+                *guard = Some(snapshot.clone());
+                Ok(snapshot)
+            }
+            Err(err) => {
+                drop(guard);
+                if dlsite_error(&err) == Some(&DlsiteError::SessionExpired) {
+                    self.logout().await;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Last synced library, if any.
+    async fn library_snapshot(&self) -> Option<LibrarySnapshot> {
+        self.library.lock().await.clone()
+    }
+
+    async fn sync_purchases(
+        &self,
+        previous: Option<&LibrarySnapshot>,
+        full: bool,
+    ) -> Result<LibrarySnapshot> {
+        let since = if full {
+            None
+        } else {
+            previous.and_then(|snapshot| snapshot.watermark)
+        };
+        let count = self.purchase_count(since).await?;
+        let pages = (count + LIBRARY_PAGE_SIZE - 1) / LIBRARY_PAGE_SIZE;
+
+        let results: Vec<(i64, Result<Vec<DlsiteProduct>>)> = stream::iter(1..=pages)
+            .map(|page| async move { (page, self.purchase_page(page, since).await) })
+            .buffered(LIBRARY_PAGE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut fetched = Vec::new();
+        let mut missing_pages = Vec::new();
+        for (page, result) in results {
+            match result {
+                Ok(products) => fetched.extend(products),
+                Err(err) if dlsite_error(&err).is_some() => return Err(err),
+                Err(_) => missing_pages.push(page),
+            }
+        }
+        // Don't rely on the API honouring `last`
+        if let Some(since) = since {
+            fetched.retain(|product| purchased_at(product).is_none_or(|at| at > since));
+        }
+
+        let mut seen = HashSet::new();
+        let mut products: Vec<DlsiteProduct> = fetched
+            .into_iter()
+            .filter(|product| seen.insert(product.id.clone()))
+            .collect();
+        // Details are a bonus; the purchase list alone is a usable library
+        let _ = self.enrich(&mut products).await;
+        let known: HashSet<&str> = previous
+            .map(|snapshot| snapshot.products.iter().map(|p| p.id.as_str()).collect())
+            .unwrap_or_default();
+        let new_products = products
+            .iter()
+            .filter(|product| !known.contains(product.id.as_str()))
+            .count();
+        if since.is_some() {
+            if let Some(previous) = previous {
+                products.extend(
+                    previous
+                        .products
+                        .iter()
+                        .filter(|product| !seen.contains(&product.id))
+                        .cloned(),
+                );
+            }
+        }
+
+        // A gap may hide newer purchases, so the watermark only moves after a complete sync
+        let watermark = if missing_pages.is_empty() {
+            products.iter().filter_map(purchased_at).max().or(since)
+        } else {
+            since
+        };
+        Ok(LibrarySnapshot {
+            products,
+            fetched_at: now_secs(),
+            watermark,
+            missing_pages,
+            incremental: since.is_some(),
+            new_products,
+        })
+    }
+
+    /// `last` asks the purchases API for purchases after `since` only, in milliseconds.
+    fn purchases_request(
+        &self,
+        path: &str,
+        since: Option<DateTime<FixedOffset>>,
+    ) -> reqwest::RequestBuilder {
+        let request = self.http.get(format!("{}/{}", self.endpoints.play_api, path));
+        match since {
+            Some(since) => request.query(&[("last", since.timestamp_millis())]),
+            None => request,
+        }
+    }
+
+    async fn purchase_count(&self, since: Option<DateTime<FixedOffset>>) -> Result<i64> {
+        let response = self
+            .http
+            .send(self.purchases_request("product_count", since))
+            .await
+            .context("Failed to get product count")?;
+        let count_json: Value = checked_account_response(response, "Purchases API")?
+            .json()
+            .await
+            .context("Invalid count response")?;
+        Ok(count_json
+            .get("product_count")
+            .and_then(Value::as_i64)
+            .unwrap_or(0)
+            .max(0))
+    }
+
+    /// One page of purchases, tried a few times. A rejected session is not retried.
+    async fn purchase_page(
+        &self,
+        page: i64,
+        since: Option<DateTime<FixedOffset>>,
+    ) -> Result<Vec<DlsiteProduct>> {
+        let mut attempt = 1;
+        loop {
+            match self.try_purchase_page(page, since).await {
+                Ok(products) => return Ok(products),
+                Err(err) if dlsite_error(&err).is_some() || attempt >= LIBRARY_PAGE_ATTEMPTS => {
+                    return Err(err.context(format!("Purchase page {} failed", page)))
+                }
+                Err(_) => {
+                    tokio::time::sleep(LIBRARY_PAGE_RETRY_DELAY * attempt).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn try_purchase_page(
+        &self,
+        page: i64,
+        since: Option<DateTime<FixedOffset>>,
+    ) -> Result<Vec<DlsiteProduct>> {
+        let request = self.purchases_request("purchases", since).query(&[("page", page)]);
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Purchases request failed")?;
+        let page_json: Value = checked_account_response(response, "Purchases API")?
+            .json()
+            .await
+            .context("Invalid purchases response")?;
+        Ok(DlsiteClient::parse_product_list(&page_json))
+    }
+
+    fn download_page_url(&self, product_id: &str) -> String {
+        format!(
+            "{}/maniax/download/=/product_id/{}.html",
+            self.endpoints.base_url, product_id
+        )
+    }
+
+    /// Where a redirect out of the download flow lands instead of the file, if anywhere.
+    fn download_detour(&self, product_id: &str, target: &Url) -> Option<DlsiteError> {
+        let endpoints = &self.endpoints;
+        let play_site = endpoints
+            .play_api
+            .strip_suffix("/api")
+            .unwrap_or(&endpoints.play_api);
+        if target.as_str().starts_with(&endpoints.login_url) || target.path().contains("/login") {
+            Some(DlsiteError::SessionExpired)
+        } else if target.as_str().starts_with(play_site) || target.path().contains("/viewer") {
+            Some(DlsiteError::BrowserOnly(product_id.to_string()))
+        } else if target.path().contains("/work/=/product_id/") {
+            Some(DlsiteError::NotPurchased(product_id.to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// Follow a download link hop by hop, so detours to the login page, the work page
+    /// or the browser viewer can be told apart from the file. Each hop asks for a single
+    /// byte, which is enough to learn the file name and size.
+    async fn follow_download(&self, product_id: &str, start: Url) -> Result<(Url, Response)> {
+        let mut url = start;
+        for _ in 0..=MAX_DOWNLOAD_REDIRECTS {
+            let request = self.http.get(url.clone()).header(RANGE, "bytes=0-0");
+            let response = self
+                .http
+                .send(request)
+                .await
+                .context("Download request failed")?;
+            let status = response.status();
+
+            if status.is_redirection() {
+                let location = header_str(response.headers(), LOCATION).ok_or_else(|| {
+                    anyhow!("Download redirect without a location (status {})", status)
+                })?;
+                let next = url.join(location).context("Invalid download redirect")?;
+                if let Some(detour) = self.download_detour(product_id, &next) {
+                    return Err(detour.into());
+                }
+                url = next;
+                continue;
+            }
+            return match status.as_u16() {
+                401 => Err(DlsiteError::SessionExpired.into()),
+                403 | 404 => Err(DlsiteError::NotPurchased(product_id.to_string()).into()),
+                _ if status.is_success() => Ok((url, response)),
+                _ => Err(anyhow!("Download request failed (status {})", status)),
+            };
+        }
+        bail!("Too many download redirects for {}", product_id)
+    }
+
+    /// Final file URLs for a purchased work. Most works are a single file; split works
+    /// land on a listing whose numbered parts are each followed to their file. An
+    /// expired session is logged out, like a rejected `validate`.
+    async fn resolve_download(&self, product_id: &str) -> Result<Vec<DownloadPart>> {
+        let result = if self.has_cookies() {
+            self.resolve_parts(product_id).await
+        } else {
+            Err(DlsiteError::SessionExpired.into())
+        };
+        self.logout_if_expired(result).await
+    }
+
+    /// Pass `result` through, logging out first when it says the session expired.
+    async fn logout_if_expired<T>(&self, result: Result<T>) -> Result<T> {
+        if let Err(err) = &result {
+            if dlsite_error(err) == Some(&DlsiteError::SessionExpired) {
+                self.logout().await;
+            }
+        }
+        result
+    }
+
+    async fn resolve_parts(&self, product_id: &str) -> Result<Vec<DownloadPart>> {
+        let start =
+            Url::parse(&self.download_page_url(product_id)).context("Invalid download URL")?;
+        let (url, response) = self.follow_download(product_id, start).await?;
+        if !is_html(&response) {
+            return Ok(vec![DownloadPart::from_response(1, &url, &response)]);
+        }
+
+        let html = response
+            .text()
+            .await
+            .context("Failed to read download page")?;
+        let links = split_part_links(&html, product_id, &url);
+        if links.is_empty() {
+            if BROWSER_ONLY_MARKERS.iter().any(|marker| html.contains(marker)) {
+                return Err(DlsiteError::BrowserOnly(product_id.to_string()).into());
+            }
+            bail!("DLsite returned a page instead of the download for {}", product_id);
+        }
+
+        futures::future::try_join_all(links.into_iter().map(|(number, link)| async move {
+            let (url, response) = self.follow_download(product_id, link).await?;
+            if is_html(&response) {
+                bail!("Part {} of {} led to a page instead of a file", number, product_id);
+            }
+            Ok(DownloadPart::from_response(number, &url, &response))
+        }))
+        .await
+    }
+
+    /// Signed cookie info for streaming voice comics; `None` when the API refuses.
+    async fn sign_cookie(&self, product_id: String) -> Result<Option<Value>> {
+        let request = self
+            .http
+            .get(format!("{}/download/sign/cookie", self.endpoints.download_api))
+            .query(&[("workno", product_id)]);
+        let resp = self
+            .http
+            .send(request)
+            .await
+            .context("Voice comic request failed")?;
+
+        if !resp.status().is_success() {
+            return Ok(None);
+        }
+        resp.json()
+            .await
+            .map(Some)
+            .context("Invalid voice comic response")
+    }
+
+    async fn product_metadata(&self, product_id: String) -> Result<Option<Value>> {
+        let request = self
+            .http
+            .get(format!("{}/=/product.json", self.endpoints.maniax_api))
+            .query(&[("workno", product_id)]);
+        let resp = self
+            .http
+            .send(request)
+            .await
+            .context("Product metadata request failed")?;
+
+        if !resp.status().is_success() {
+            return Ok(None);
+        }
+        resp.json()
+            .await
+            .map(Some)
+            .context("Invalid metadata response")
+    }
+
+    /// Serial numbers the store issued for a purchased work; empty for works without DRM.
+    /// An expired session is logged out.
+    async fn serial_keys(&self, product_id: &str) -> Result<Vec<SerialKey>> {
+        let result = if self.has_cookies() {
+            self.fetch_serial_keys(product_id).await
+        } else {
+            Err(DlsiteError::SessionExpired.into())
+        };
+        self.logout_if_expired(result).await
+    }
+
+    async fn fetch_serial_keys(&self, product_id: &str) -> Result<Vec<SerialKey>> {
+        let request = self
+            .http
+            .get(format!("{}/serial_numbers", self.endpoints.play_api))
+            .query(&[("workno", product_id)]);
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Serial number request failed")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(DlsiteError::NotPurchased(product_id.to_string()).into());
+        }
+        let data: Value = checked_account_response(response, "Serial number API")?
+            .json()
+            .await
+            .context("Invalid serial number response")?;
+        Ok(SerialKey::list_from(&data))
+    }
+
+    /// Every wishlist entry, page by page, with `product.json` details filled in. An
+    /// expired session is logged out.
+    async fn wishlist(&self) -> Result<Vec<DlsiteProduct>> {
+        let result = if self.has_cookies() {
+            self.wishlist_items().await
+        } else {
+            Err(DlsiteError::SessionExpired.into())
+        };
+        let mut items = self.logout_if_expired(result).await?;
+        // Details are a bonus, as in the library sync
+        let _ = self.enrich(&mut items).await;
+        Ok(items)
+    }
+
+    async fn wishlist_items(&self) -> Result<Vec<DlsiteProduct>> {
+        let mut items = Vec::new();
+        let mut page = 1;
+        loop {
+            let data = self.wishlist_page(page).await?;
+            let batch = DlsiteClient::parse_product_list(&data);
+            let count = data.get("count").and_then(Value::as_u64).unwrap_or(0);
+            let limit = data
+                .get("limit")
+                .and_then(Value::as_u64)
+                .unwrap_or(batch.len() as u64);
+            let empty = batch.is_empty();
+            items.extend(batch);
+            if empty || limit == 0 || page * limit >= count || page >= MAX_WISHLIST_PAGES {
+                break;
+            }
+            page += 1;
+        }
+
+        let mut seen = HashSet::new();
+        items.retain(|product| seen.insert(product.id.clone()));
+        Ok(items)
+    }
+
+    async fn wishlist_page(&self, page: u64) -> Result<Value> {
+        let request = self
+            .http
+            .get(format!("{}/=/wishlist.json", self.endpoints.maniax_api))
+            .query(&[("page", page)]);
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Wishlist request failed")?;
+        checked_account_response(response, "Wishlist")?
+            .json()
+            .await
+            .context("Invalid wishlist response")
+    }
+
+    /// Add to (`add`) or remove from the wishlist. The store answers `result: false`
+    /// with a message when it refuses, e.g. for an unknown work.
+    async fn update_wishlist(&self, product_id: &str, add: bool) -> Result<()> {
+        let result = if self.has_cookies() {
+            self.post_wishlist_update(product_id, add).await
+        } else {
+            Err(DlsiteError::SessionExpired.into())
+        };
+        self.logout_if_expired(result).await
+    }
+
+    async fn post_wishlist_update(&self, product_id: &str, add: bool) -> Result<()> {
+        let action = if add { "add" } else { "remove" };
+        let request = self
+            .http
+            .post(format!("{}/=/wishlist/{}.json", self.endpoints.maniax_api, action))
+            .form(&[("workno", product_id)]);
+        let response = self
+            .http
+            .send(request)
+            .await
+            .context("Wishlist update failed")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            bail!("No DLsite work {}", product_id);
+        }
+        let response = checked_account_response(response, "Wishlist update")?;
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if body.get("result").and_then(Value::as_bool) == Some(false) {
+            let message = body
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or("refused by the store");
+            bail!("Could not {} {}: {}", action, product_id, message);
+        }
+        Ok(())
+    }
+
+    /// `product.json` entries for `ids`, requested in batches.
+    async fn product_details(&self, ids: &[String]) -> Result<Vec<DlsiteProduct>> {
+        let batches: Vec<String> = ids
+            .chunks(PRODUCT_DETAILS_BATCH)
+            .map(|batch| batch.join(","))
+            .collect();
+        let responses: Vec<Result<Option<Value>>> = stream::iter(batches)
+            .map(|worknos| self.product_metadata(worknos))
+            .buffered(LIBRARY_PAGE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut details = Vec::new();
+        for response in responses {
+            match response? {
+                Some(Value::Array(items)) => {
+                    details.extend(items.iter().filter_map(DlsiteProduct::from_value))
+                }
+                Some(item) => details.extend(DlsiteProduct::from_value(&item)),
+                None => {}
+            }
+        }
+        Ok(details)
+    }
+
+    /// Fill in what the purchase list leaves out from `product.json`, for all products
+    /// at once.
+    async fn enrich(&self, products: &mut [DlsiteProduct]) -> Result<()> {
+        if products.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<String> = products.iter().map(|product| product.id.clone()).collect();
+        let details: HashMap<String, DlsiteProduct> = self
+            .product_details(&ids)
+            .await?
+            .into_iter()
+            .map(|details| (details.id.clone(), details))
+            .collect();
+        for product in products.iter_mut() {
+            if let Some(details) = details.get(&product.id) {
+                product.merge(details);
+            }
+        }
+        Ok(())
+    }
+
+    /// One page of results. Unlike the library calls, a failed search is an error.
+    async fn search(&self, query: &SearchQuery) -> Result<DlsiteSearchPage> {
+        let params = query.params()?;
+        let request = self
+            .http
+            .get(format!("{}/search", self.endpoints.maniax_api))
+            .query(&params);
+        let resp = self
+            .http
+            .send(request)
+            .await
+            .context("Search request failed")?;
+        if !resp.status().is_success() {
+            bail!("Search failed (status {})", resp.status());
+        }
+        let data: Value = resp.json().await.context("Invalid search response")?;
+        DlsiteSearchPage::from_value(&data, query.page(), query.per_page())
+    }
+}
+
+#[pyclass(module = "vn_core")]
+pub struct DlsiteClient {
+    session: DlsiteSession,
+    user_agent: String,
+}
+
+impl DlsiteClient {
+    fn parse_product_list(data: &Value) -> Vec<DlsiteProduct> {
+        data.get("products")
+            .and_then(Value::as_array)
+            .map(|arr| {
+                arr.iter()
+                    .filter_map(DlsiteProduct::from_value)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    }
+
+    fn to_py_product_list(py: Python<'_>, products: Vec<DlsiteProduct>) -> PyResult<PyObject> {
+        let list = PyList::empty(py);
+        for product in products {
+            list.append(Py::new(py, product)?.into_py(py))?;
+        }
+        Ok(list.into())
+    }
+
+    pub fn logged_in_sync(&self) -> bool {
+        self.session.logged_in()
+    }
+}
+
+/// Store answers map to their `Dlsite*` exception, network failures to `NetworkError`,
+/// everything else to `RuntimeError`.
+fn py_error(err: anyhow::Error) -> PyErr {
+    let message = format!("{:#}", err);
+    if let Some(kind) = dlsite_error(&err) {
+        return match kind {
+            DlsiteError::SessionExpired => DlsiteSessionExpired::new_err(message),
+            DlsiteError::NotPurchased(_) => DlsiteNotPurchased::new_err(message),
+            DlsiteError::BrowserOnly(_) => DlsiteBrowserOnly::new_err(message),
+            DlsiteError::InvalidCredentials => DlsiteInvalidCredentials::new_err(message),
+            DlsiteError::CaptchaRequired => DlsiteCaptchaRequired::new_err(message),
+            DlsiteError::VerificationRequired => DlsiteVerificationRequired::new_err(message),
+            DlsiteError::Maintenance => DlsiteMaintenance::new_err(message),
+        };
+    }
+    if err.chain().any(|cause| cause.is::<HttpError>()) {
+        NetworkError::new_err(message)
+    } else {
+        runtime_error(message)
+    }
+}
+
+#[pymethods]
+impl DlsiteClient {
+    /// Every endpoint defaults to the live store; pass a URL to override it. With
+    /// `data_dir` the login survives restarts: the cookie jar is saved there and restored
+    /// here, pending `test_authentication`.
+    #[new]
+    pub fn new(
+        base_url: Option<String>,
+        login_url: Option<String>,
+        play_api: Option<String>,
+        maniax_api: Option<String>,
+        download_api: Option<String>,
+        data_dir: Option<String>,
+    ) -> PyResult<Self> {
+        let user_agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36".to_string();
+        let defaults = DlsiteEndpoints::default();
+        let endpoints = DlsiteEndpoints {
+            base_url: trim_endpoint(base_url.unwrap_or(defaults.base_url)),
+            login_url: trim_endpoint(login_url.unwrap_or(defaults.login_url)),
+            play_api: trim_endpoint(play_api.unwrap_or(defaults.play_api)),
+            maniax_api: trim_endpoint(maniax_api.unwrap_or(defaults.maniax_api)),
+            download_api: trim_endpoint(download_api.unwrap_or(defaults.download_api)),
+        };
+        let cookie_path = data_dir.map(|dir| PathBuf::from(dir).join(COOKIE_FILE_NAME));
+        let session = DlsiteSession::new(endpoints, &user_agent, cookie_path).map_err(py_error)?;
+
+        Ok(Self {
+            session,
+            user_agent,
+        })
+    }
+
+    pub fn initialize<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let http = self.session.http.clone();
+        let base_url = self.session.endpoints.base_url.clone();
+        let user_agent = self.user_agent.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            // touch client to ensure it works
+            let _ = http
+                .send(http.get(base_url).header(USER_AGENT, user_agent))
+                .await;
+            Ok(())
+        })
+    }
+
+    pub fn cleanup<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        self.logout(py)
+    }
+
+    pub fn login<'py>(
+        &'py self,
+        py: Python<'py>,
+        username: String,
+        password: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            session.login(username, password).await.map_err(py_error)?;
+
+            json_result!({
+                "success": true,
+                "message": "Login successful"
+            })
+        })
+    }
+
+    pub fn logout<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            session.logout().await;
+            Ok(true)
+        })
+    }
+
+    pub fn is_logged_in<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let logged = self.logged_in_sync();
+        pyo3_asyncio::tokio::future_into_py(py, async move { Ok(logged) })
+    }
+
+    /// `"logged_out"`, `"restored"` (saved cookies not yet checked) or `"verified"`.
+    pub fn session_state(&self) -> &'static str {
+        self.session.auth_state().as_str()
+    }
+
+    /// Check the session against the store; a rejected session is logged out.
+    pub fn test_authentication<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Ok(session.is_authenticated().await)
+        })
+    }
 
-                collected.extend(Self::parse_product_list(&page_json));
-            }
+    /// Full library sync; see `sync_library` for the report on missing pages. With
+    /// `categories` (e.g. `["game"]`) only works of those categories are returned.
+    pub fn get_library<'py>(
+        &'py self,
+        py: Python<'py>,
+        categories: Option<Vec<String>>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        let categories = parse_categories(categories).map_err(py_error)?;
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let snapshot = session.sync_library(true).await.map_err(py_error)?;
+            let products = filter_categories(snapshot.products, categories.as_deref());
+            Python::with_gil(|py| Self::to_py_product_list(py, products))
+        })
+    }
+
+    /// Library from the last sync while it is complete and younger than `ttl_seconds`
+    /// (default 45). Otherwise new purchases are synced first, or everything with
+    /// `force_refresh`. `categories` filters like in `get_library`.
+    pub fn get_library_cached<'py>(
+        &'py self,
+        py: Python<'py>,
+        force_refresh: Option<bool>,
+        ttl_seconds: Option<i64>,
+        categories: Option<Vec<String>>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        let categories = parse_categories(categories).map_err(py_error)?;
+        let ttl = ttl_seconds.unwrap_or(45);
+        let force = force_refresh.unwrap_or(false);
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let cached = session
+                .library_snapshot()
+                .await
+                .filter(|snapshot| !force && snapshot.fresh(ttl));
+            let snapshot = match cached {
+                Some(snapshot) => snapshot,
+                None => session.sync_library(force).await.map_err(py_error)?,
+            };
+            let products = filter_categories(snapshot.products, categories.as_deref());
+            Python::with_gil(|py| Self::to_py_product_list(py, products))
+        })
+    }
+
+    /// Sync the library now and report on it: `total`, `new`, `incremental`, `complete`,
+    /// `missing_pages` and `synced_at`.
+    pub fn sync_library<'py>(
+        &'py self,
+        py: Python<'py>,
+        full: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let snapshot = session
+                .sync_library(full.unwrap_or(false))
+                .await
+                .map_err(py_error)?;
+            Python::with_gil(|py| value_to_py(py, &snapshot.report()))
+        })
+    }
 
-            // Update cache
-            if let Ok(mut guard) = cache.write() {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs() as i64;
-                *guard = Some((now, collected.clone()));
+    /// Report of the last sync, as returned by `sync_library`, or `None`.
+    pub fn last_library_sync<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            match session.library_snapshot().await {
+                Some(snapshot) => Python::with_gil(|py| value_to_py(py, &snapshot.report())),
+                None => Python::with_gil(|py| Ok(py.None())),
             }
+        })
+    }
 
-            Python::with_gil(|py| Self::to_py_product_list(py, collected))
+    /// Every file of a purchased work as `{part, url, filename, size}` dicts, ordered by
+    /// part. Raises `DlsiteNotPurchased`, `DlsiteBrowserOnly` or `DlsiteSessionExpired`
+    /// when there is nothing to download. Only single-part works can go straight to
+    /// `DownloadManager`; split works are multi-volume archives it cannot install.
+    pub fn resolve_download<'py>(
+        &'py self,
+        py: Python<'py>,
+        product_id: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let parts = session
+                .resolve_download(&product_id)
+                .await
+                .map_err(py_error)?;
+            let value =
+                serde_json::to_value(parts).map_err(|err| runtime_error(err.to_string()))?;
+            Python::with_gil(|py| value_to_py(py, &value))
         })
     }
 
-    pub fn get_download_urls<'py>(
+    /// Serial numbers and activation codes of a purchased work as `{code, label}` dicts.
+    /// With a `library`, they are also kept in the game's `metadata.json` under
+    /// `serial_keys`. Raises `DlsiteNotPurchased` or `DlsiteSessionExpired`.
+    pub fn get_serial_keys<'py>(
         &'py self,
         py: Python<'py>,
         product_id: String,
+        library: Option<PyRef<'py, GameLibrary>>,
     ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        let games_dir = library.map(|library| library.games_dir().to_path_buf());
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let url = format!(
-                "https://www.dlsite.com/maniax/download/=/product_id/{}.html",
-                product_id
-            );
-            Ok(vec![url])
+            let keys = session.serial_keys(&product_id).await.map_err(py_error)?;
+            let value = serde_json::to_value(keys).map_err(|err| runtime_error(err.to_string()))?;
+            if let Some(games_dir) = games_dir {
+                GameLibrary::record_serial_keys(&games_dir, &product_id, &value)
+                    .map_err(|err| runtime_error(err.to_string()))?;
+            }
+            Python::with_gil(|py| value_to_py(py, &value))
         })
     }
 
-    pub fn get_voice_comic_info<'py>(
+    /// Final URLs of every part; see `resolve_download`.
+    pub fn get_download_urls<'py>(
         &'py self,
         py: Python<'py>,
         product_id: String,
     ) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
+        let session = self.session.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let url = "https://play.dl.dlsite.com/api/download/sign/cookie";
-            let resp = client
-                .get(url)
-                .query(&[("workno", product_id)])
-                .send()
+            let parts = session
+                .resolve_download(&product_id)
                 .await
-                .map_err(|err| runtime_error(format!("Voice comic request failed: {}", err)))?;
+                .map_err(py_error)?;
+            Ok(parts.into_iter().map(|part| part.url).collect::<Vec<_>>())
+        })
+    }
 
-            if resp.status().is_success() {
-                let value: Value = resp.json().await.map_err(|err| {
-                    runtime_error(format!("Invalid voice comic response: {}", err))
-                })?;
-                Python::with_gil(|py| value_to_py(py, &value))
-            } else {
-                Python::with_gil(|py| Ok(py.None()))
+    pub fn get_voice_comic_info<'py>(
+        &'py self,
+        py: Python<'py>,
+        product_id: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            match session.sign_cookie(product_id).await.map_err(py_error)? {
+                Some(value) => Python::with_gil(|py| value_to_py(py, &value)),
+                None => Python::with_gil(|py| Ok(py.None())),
             }
         })
     }
@@ -507,58 +2285,1179 @@ impl DlsiteClient {
         py: Python<'py>,
         product_id: String,
     ) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let maniax_api = self.maniax_api.clone();
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            match session.product_metadata(product_id).await.map_err(py_error)? {
+                Some(value) => Python::with_gil(|py| value_to_py(py, &value)),
+                None => Python::with_gil(|py| Ok(py.None())),
+            }
+        })
+    }
+
+    /// Typed `product.json` details of one work, or `None` if the store has no such work.
+    pub fn get_product<'py>(
+        &'py self,
+        py: Python<'py>,
+        product_id: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let url = format!("{}/=/product.json", maniax_api);
-            let resp = client
-                .get(&url)
-                .query(&[("workno", product_id)])
-                .send()
+            let details = session
+                .product_details(std::slice::from_ref(&product_id))
                 .await
-                .map_err(|err| {
-                    runtime_error(format!("Product metadata request failed: {}", err))
-                })?;
+                .map_err(py_error)?;
+            let product = details.into_iter().find(|product| product.id == product_id);
+            Python::with_gil(|py| match product {
+                Some(product) => Ok(Py::new(py, product)?.into_py(py)),
+                None => Ok(py.None()),
+            })
+        })
+    }
 
-            if resp.status().is_success() {
-                let value: Value = resp
-                    .json()
-                    .await
-                    .map_err(|err| runtime_error(format!("Invalid metadata response: {}", err)))?;
-                Python::with_gil(|py| value_to_py(py, &value))
-            } else {
-                Python::with_gil(|py| Ok(py.None()))
+    /// The given products with the gaps filled from `product.json`, in one batched call.
+    pub fn enrich_products<'py>(
+        &'py self,
+        py: Python<'py>,
+        products: Vec<DlsiteProduct>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut products = products;
+            session.enrich(&mut products).await.map_err(py_error)?;
+            Python::with_gil(|py| Self::to_py_product_list(py, products))
+        })
+    }
+
+    /// Wishlist entries as products with `price`, `official_price`, `discount_rate` and
+    /// `sale_ends_at`; with `on_sale_only` just the discounted ones.
+    pub fn get_wishlist<'py>(
+        &'py self,
+        py: Python<'py>,
+        on_sale_only: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut items = session.wishlist().await.map_err(py_error)?;
+            if on_sale_only.unwrap_or(false) {
+                items.retain(DlsiteProduct::on_sale);
             }
+            Python::with_gil(|py| Self::to_py_product_list(py, items))
+        })
+    }
+
+    pub fn add_to_wishlist<'py>(
+        &'py self,
+        py: Python<'py>,
+        product_id: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            session
+                .update_wishlist(&product_id, true)
+                .await
+                .map_err(py_error)?;
+            Ok(true)
+        })
+    }
+
+    pub fn remove_from_wishlist<'py>(
+        &'py self,
+        py: Python<'py>,
+        product_id: String,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            session
+                .update_wishlist(&product_id, false)
+                .await
+                .map_err(py_error)?;
+            Ok(true)
         })
     }
 
+    /// Search the store. `query` is a keyword, or a dict with `keyword`, `category`,
+    /// `genres`, `circle`, `age_category`, `min_price`, `max_price`, `released_after`,
+    /// `released_before`, `language`, `order`, `page` and `per_page`; `category`
+    /// overrides the one in the dict. Returns a `DlsiteSearchPage`.
     pub fn search_products<'py>(
         &'py self,
         py: Python<'py>,
-        query: String,
+        query: &PyAny,
         category: Option<String>,
     ) -> PyResult<&'py PyAny> {
-        let client = self.client.clone();
-        let maniax_api = self.maniax_api.clone();
+        let mut query = match query.extract::<String>() {
+            Ok(keyword) => SearchQuery {
+                keyword,
+                ..SearchQuery::default()
+            },
+            Err(_) => extract_serde::<SearchQuery>(query)?,
+        };
+        if category.is_some() {
+            query.category = category;
+        }
+        let session = self.session.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let url = format!("{}/search", maniax_api);
-            let mut params = HashMap::new();
-            params.insert("keyword", query);
-            params.insert("order", "trend".to_string());
-            params.insert("per_page", "50".to_string());
-            params.insert("category", category.unwrap_or_else(|| "all".to_string()));
-
-            let response = client.get(&url).query(&params).send().await;
-            if let Ok(resp) = response {
-                if resp.status().is_success() {
-                    if let Ok(data) = resp.json::<Value>().await {
-                        let products = Self::parse_search_results(&data);
-                        return Python::with_gil(|py| Self::to_py_product_list(py, products));
-                    }
-                }
+            let page = session.search(&query).await.map_err(py_error)?;
+            Python::with_gil(|py| Ok(Py::new(py, page)?.into_py(py)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn session_for(server: &MockServer) -> DlsiteSession {
+        session_with_jar(server, None)
+    }
+
+    fn session_with_jar(server: &MockServer, cookie_path: Option<PathBuf>) -> DlsiteSession {
+        let url = server.url();
+        let endpoints = DlsiteEndpoints {
+            base_url: url.clone(),
+            login_url: format!("{}/login-site", url),
+            play_api: format!("{}/play/api", url),
+            maniax_api: format!("{}/maniax/api", url),
+            download_api: format!("{}/dl/api", url),
+        };
+        DlsiteSession::new(endpoints, "vn-core-tests", cookie_path).unwrap()
+    }
+
+    /// Login flow as the store runs it: anonymous session cookie, CSRF cookie on the
+    /// login page, then a redirect that swaps in the authenticated session cookie.
+    fn mount_login(server: &MockServer) {
+        server.respond(
+            "GET",
+            "/maniax/login/=/skip_register/1",
+            MockResponse::status(200).header("set-cookie", "__DLsite_SID=anonymous; Path=/"),
+        );
+        server.respond(
+            "GET",
+            "/login-site/login",
+            MockResponse::status(200)
+                .header("set-cookie", "XSRF-TOKEN=csrf-123; Path=/")
+                .body("<form></form>"),
+        );
+        server.on("POST", "/login-site/login", |req| {
+            let form = req.form();
+            let valid = form.get("_token").map(String::as_str) == Some("csrf-123")
+                && form.get("login_id").map(String::as_str) == Some("reader")
+                && form.get("password").map(String::as_str) == Some("secret");
+            if valid {
+                MockResponse::status(302)
+                    .header("location", "/")
+                    .header("set-cookie", "__DLsite_SID=authenticated; Path=/")
+            } else {
+                MockResponse::status(422).body("login_id or password is wrong")
             }
+        });
+        server.respond(
+            "GET",
+            "/",
+            MockResponse::status(200).body("<html>My page</html>"),
+        );
+        server.on("GET", "/play/api/product_count", |req| {
+            if req.has_cookie("__DLsite_SID", "authenticated") {
+                MockResponse::fixture("dlsite/product_count.json")
+            } else {
+                MockResponse::status(401)
+            }
+        });
+    }
 
-            Python::with_gil(|py| Ok(PyList::empty(py).into()))
-        })
+    #[tokio::test]
+    async fn login_posts_csrf_token_and_keeps_session_cookie() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let session = session_for(&server);
+
+        session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap();
+        assert!(session.logged_in());
+        assert!(session.is_authenticated().await);
+
+        let posted = &server.requests_to("/login-site/login")[1];
+        assert_eq!(posted.method, "POST");
+        assert!(posted.has_cookie("XSRF-TOKEN", "csrf-123"));
+    }
+
+    #[tokio::test]
+    async fn login_rejects_bad_credentials() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let session = session_for(&server);
+
+        let err = session
+            .login("reader".to_string(), "wrong".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::InvalidCredentials));
+        assert!(!session.logged_in());
+    }
+
+    /// Answer the login post by redirecting to `location` instead of the real checks.
+    fn redirect_login_post(server: &MockServer, location: &str) {
+        server.respond(
+            "POST",
+            "/login-site/login",
+            MockResponse::status(302)
+                .header("location", location)
+                .header("set-cookie", "__DLsite_SID=half-done; Path=/"),
+        );
+    }
+
+    #[tokio::test]
+    async fn redirect_back_to_the_form_is_a_failed_login() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        redirect_login_post(&server, "/login-site/login");
+        let session = session_for(&server);
+
+        let err = session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::InvalidCredentials));
+        // Cookies from the attempt do not count as a session
+        assert!(!session.logged_in());
+        assert_eq!(server.requests_to("/login-site/login").len(), 3);
+        assert!(server.requests_to("/play/api/product_count").is_empty());
+    }
+
+    #[tokio::test]
+    async fn login_failures_are_classified() {
+        let cases: [(&str, Option<MockResponse>, DlsiteError); 4] = [
+            (
+                "/login-site/login?error=1",
+                Some(MockResponse::status(200).body(
+                    r#"<form><input name="login_id"><div class="g-recaptcha"></div></form>"#,
+                )),
+                DlsiteError::CaptchaRequired,
+            ),
+            (
+                "/login-site/login/verify?method=mail",
+                None,
+                DlsiteError::VerificationRequired,
+            ),
+            (
+                "/login-site/login",
+                Some(MockResponse::status(200).body(
+                    "<form><input name=\"login_id\"><p>確認コードを入力してください</p></form>",
+                )),
+                DlsiteError::VerificationRequired,
+            ),
+            (
+                "/maintenance",
+                Some(MockResponse::status(503).body("<html>maintenance</html>")),
+                DlsiteError::Maintenance,
+            ),
+        ];
+        for (location, page, expected) in cases {
+            let server = MockServer::start().await;
+            mount_login(&server);
+            redirect_login_post(&server, location);
+            if let Some(page) = page {
+                // Pages on the form's own path still hand out the CSRF cookie
+                let page = page.header("set-cookie", "XSRF-TOKEN=csrf-123; Path=/");
+                let path = location.split('?').next().unwrap();
+                server.respond("GET", path, page);
+            }
+            let session = session_for(&server);
+
+            let err = session
+                .login("reader".to_string(), "secret".to_string())
+                .await
+                .unwrap_err();
+            assert_eq!(dlsite_error(&err), Some(&expected), "{}", location);
+            assert!(!session.logged_in());
+        }
+    }
+
+    #[tokio::test]
+    async fn refused_form_posts_are_not_bad_credentials() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        // Laravel's answer to an expired CSRF token
+        server.respond(
+            "POST",
+            "/login-site/login",
+            MockResponse::status(419).body("<html>Page Expired</html>"),
+        );
+        let session = session_for(&server);
+
+        let err = session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(dlsite_error(&err), None);
+        assert!(err.to_string().contains("status 419"), "{}", err);
+        assert!(!session.logged_in());
+    }
+
+    #[tokio::test]
+    async fn login_is_confirmed_with_the_store() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        redirect_login_post(&server, "/");
+        let session = session_for(&server);
+
+        // The store sent the browser on, but the cookie it set is not a session
+        let err = session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("did not accept the login"), "{}", err);
+        assert!(!session.logged_in());
+
+        server.respond(
+            "GET",
+            "/maniax/login/=/skip_register/1",
+            MockResponse::status(503),
+        );
+        let err = session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::Maintenance));
+    }
+
+    #[tokio::test]
+    async fn login_needs_csrf_cookie() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond("GET", "/login-site/login", MockResponse::status(200));
+        let session = session_for(&server);
+
+        let err = session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "CSRF token not found");
+        assert_eq!(server.requests_to("/login-site/login").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn logout_drops_session_cookies() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let session = session_for(&server);
+        session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap();
+
+        session.logout().await;
+        assert!(!session.logged_in());
+        assert!(!session.is_authenticated().await);
+    }
+
+    fn temp_jar() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("vn_core_dlsite_{}", uuid::Uuid::new_v4()))
+            .join(COOKIE_FILE_NAME)
+    }
+
+    async fn logged_in_with_jar(server: &MockServer, jar: &Path) {
+        let session = session_with_jar(server, Some(jar.to_path_buf()));
+        session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn saved_cookies_restore_the_session() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let jar = temp_jar();
+        logged_in_with_jar(&server, &jar).await;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&jar).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let restored = session_with_jar(&server, Some(jar.clone()));
+        assert_eq!(restored.auth_state(), AuthState::Restored);
+        assert!(restored.logged_in());
+        assert!(restored.validate().await.unwrap());
+        assert_eq!(restored.auth_state(), AuthState::Verified);
+
+        let checks = server.requests_to("/play/api/product_count");
+        assert!(checks
+            .last()
+            .unwrap()
+            .has_cookie("__DLsite_SID", "authenticated"));
+        let _ = std::fs::remove_dir_all(jar.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn rejected_saved_session_is_wiped() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let jar = temp_jar();
+        logged_in_with_jar(&server, &jar).await;
+        // The store invalidated the session in the meantime
+        server.respond("GET", "/play/api/product_count", MockResponse::status(401));
+
+        let restored = session_with_jar(&server, Some(jar.clone()));
+        assert!(!restored.validate().await.unwrap());
+        assert_eq!(restored.auth_state(), AuthState::LoggedOut);
+        assert!(!jar.exists());
+        let _ = std::fs::remove_dir_all(jar.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn server_errors_keep_restored_session() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let jar = temp_jar();
+        logged_in_with_jar(&server, &jar).await;
+        server.respond("GET", "/play/api/product_count", MockResponse::status(500));
+
+        let restored = session_with_jar(&server, Some(jar.clone()));
+        assert!(restored.validate().await.is_err());
+        assert_eq!(restored.auth_state(), AuthState::Restored);
+        assert!(jar.exists());
+        let _ = std::fs::remove_dir_all(jar.parent().unwrap());
+    }
+
+    #[tokio::test]
+    async fn logout_deletes_saved_cookies() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let jar = temp_jar();
+        let session = session_with_jar(&server, Some(jar.clone()));
+        session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap();
+        assert!(jar.exists());
+
+        session.logout().await;
+        assert!(!jar.exists());
+        assert!(!session_with_jar(&server, Some(jar.clone())).logged_in());
+        let _ = std::fs::remove_dir_all(jar.parent().unwrap());
+    }
+
+    async fn logged_in_session(server: &MockServer) -> DlsiteSession {
+        let session = session_for(server);
+        session
+            .login("reader".to_string(), "secret".to_string())
+            .await
+            .unwrap();
+        session
+    }
+
+    #[tokio::test]
+    async fn library_sync_retries_pages_and_reports_missing_ones() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.on("GET", "/play/api/purchases", |req| match req.query("page") {
+            Some("1") => MockResponse::fixture("dlsite/purchases_page1.json"),
+            Some("2") => MockResponse::fixture("dlsite/purchases_page2.json"),
+            _ => MockResponse::status(500),
+        });
+        let session = logged_in_session(&server).await;
+
+        let snapshot = session.sync_library(true).await.unwrap();
+        let ids: Vec<_> = snapshot.products.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["RJ01000001", "RJ01000002", "RJ01000003"]);
+        assert_eq!(snapshot.missing_pages, [3]);
+        assert!(!snapshot.complete());
+        // An incomplete full sync leaves nothing to sync incrementally from
+        assert!(snapshot.watermark.is_none());
+
+        // 120 products at 50 per page; the failing page is tried again
+        let mut pages: Vec<_> = server
+            .requests_to("/play/api/purchases")
+            .iter()
+            .filter_map(|req| req.query("page").map(str::to_string))
+            .collect();
+        pages.sort();
+        assert_eq!(pages, ["1", "2", "3", "3", "3"]);
+
+        let first = &snapshot.products[0];
+        assert_eq!(first.group_name, "Circle Sakura");
+        assert_eq!(first.tags, ["純愛", "学園もの"]);
+        assert_eq!(first.purchased_at.as_deref(), Some("2023-01-04T10:12:00+09:00"));
+        assert_eq!(first.file_size, 734003200);
+    }
+
+    #[tokio::test]
+    async fn incremental_sync_fetches_only_newer_purchases() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond(
+            "GET",
+            "/play/api/product_count",
+            MockResponse::json(&serde_json::json!({ "product_count": 3 })),
+        );
+        server.respond(
+            "GET",
+            "/play/api/purchases",
+            MockResponse::fixture("dlsite/purchases_page1.json"),
+        );
+        let session = logged_in_session(&server).await;
+        let first = session.sync_library(false).await.unwrap();
+        assert!(!first.incremental);
+        assert_eq!(first.new_products, 2);
+        let watermark = first.watermark.unwrap();
+
+        // The API answers with one new purchase and, ignoring `last`, an old one again
+        let mut page = crate::mock_server::fixture("dlsite/purchases_page1.json");
+        let mut newer = page["products"][0].clone();
+        newer["id"] = "RJ01000009".into();
+        newer["purchased_at"] = "2030-05-01T12:00:00+09:00".into();
+        page["products"].as_array_mut().unwrap().insert(0, newer);
+        server.respond(
+            "GET",
+            "/play/api/product_count",
+            MockResponse::json(&serde_json::json!({ "product_count": 1 })),
+        );
+        server.respond("GET", "/play/api/purchases", MockResponse::json(&page));
+
+        let second = session.sync_library(false).await.unwrap();
+        assert!(second.incremental);
+        assert_eq!(second.new_products, 1);
+        let ids: Vec<_> = second.products.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["RJ01000009", "RJ01000001", "RJ01000002"]);
+        assert!(second.watermark.unwrap() > watermark);
+
+        let last = server.requests_to("/play/api/purchases").pop().unwrap();
+        assert_eq!(last.query("last"), Some(watermark.timestamp_millis().to_string().as_str()));
+        assert_eq!(
+            server.requests_to("/play/api/product_count").last().unwrap().query("last"),
+            last.query("last")
+        );
+    }
+
+    #[tokio::test]
+    async fn rejected_session_during_sync_logs_out() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond("GET", "/play/api/purchases", MockResponse::status(401));
+        let session = logged_in_session(&server).await;
+
+        let err = session.sync_library(true).await.unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::SessionExpired));
+        assert_eq!(session.auth_state(), AuthState::LoggedOut);
+        assert!(session.library_snapshot().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn wishlist_is_paged_and_carries_sale_prices() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.on("GET", "/maniax/api/=/wishlist.json", |req| {
+            match req.query("page") {
+                Some("1") => MockResponse::fixture("dlsite/wishlist_page1.json"),
+                Some("2") => MockResponse::fixture("dlsite/wishlist_page2.json"),
+                _ => MockResponse::status(500),
+            }
+        });
+        let session = logged_in_session(&server).await;
+
+        let items = session.wishlist().await.unwrap();
+        let ids: Vec<_> = items.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["RJ01000010", "RJ01000011", "RJ01000012"]);
+        assert_eq!(server.requests_to("/maniax/api/=/wishlist.json").len(), 2);
+
+        let discounted = &items[0];
+        assert_eq!((discounted.price, discounted.official_price), (1320, 2200));
+        assert_eq!(discounted.discount_rate, Some(40));
+        assert_eq!(discounted.sale_ends_at.as_deref(), Some("2026-11-01 23:59:59"));
+        assert!(discounted.on_sale());
+        assert!(!items[1].on_sale());
+        // A lower price than the regular one is a sale even without a rate
+        assert_eq!(items[2].discount_rate, None);
+        assert!(items[2].on_sale());
+
+        let request = &server.requests_to("/maniax/api/=/wishlist.json")[0];
+        assert!(request.has_cookie("__DLsite_SID", "authenticated"));
+    }
+
+    #[tokio::test]
+    async fn wishlist_updates_post_the_work() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond(
+            "POST",
+            "/maniax/api/=/wishlist/add.json",
+            MockResponse::json(&serde_json::json!({ "result": true })),
+        );
+        server.respond(
+            "POST",
+            "/maniax/api/=/wishlist/remove.json",
+            MockResponse::json(&serde_json::json!({
+                "result": false,
+                "message": "not in wishlist",
+            })),
+        );
+        let session = logged_in_session(&server).await;
+
+        session.update_wishlist("RJ01000010", true).await.unwrap();
+        let add = &server.requests_to("/maniax/api/=/wishlist/add.json")[0];
+        assert_eq!(add.form()["workno"], "RJ01000010");
+        assert!(add.has_cookie("__DLsite_SID", "authenticated"));
+
+        let err = session.update_wishlist("RJ01000010", false).await.unwrap_err();
+        assert!(err.to_string().contains("not in wishlist"), "{}", err);
+        // A refusal is not a session problem
+        assert_eq!(session.auth_state(), AuthState::Verified);
+    }
+
+    #[tokio::test]
+    async fn rejected_session_on_wishlist_logs_out() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond("GET", "/maniax/api/=/wishlist.json", MockResponse::status(401));
+        let session = logged_in_session(&server).await;
+
+        let err = session.wishlist().await.unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::SessionExpired));
+        assert_eq!(session.auth_state(), AuthState::LoggedOut);
+
+        let err = session.update_wishlist("RJ01000010", true).await.unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::SessionExpired));
+        assert!(server.requests_to("/maniax/api/=/wishlist/add.json").is_empty());
+    }
+
+    #[tokio::test]
+    async fn serial_keys_are_fetched_and_kept_in_game_metadata() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.on("GET", "/play/api/serial_numbers", |req| {
+            match req.query("workno") {
+                Some("RJ01000001") => MockResponse::fixture("dlsite/serial_numbers.json"),
+                _ => MockResponse::status(404),
+            }
+        });
+        let session = logged_in_session(&server).await;
+
+        let keys = session.serial_keys("RJ01000001").await.unwrap();
+        assert_eq!(
+            keys,
+            [
+                SerialKey {
+                    code: "HX7Q-29LM-A0ZP-44KD".to_string(),
+                    label: Some("Activation key".to_string()),
+                },
+                SerialKey {
+                    code: "PATCH-0711-BONUS".to_string(),
+                    label: None,
+                },
+            ]
+        );
+
+        let games_dir =
+            std::env::temp_dir().join(format!("vn_core_games_{}", uuid::Uuid::new_v4()));
+        let metadata_path = games_dir.join("game_RJ01000001/metadata.json");
+        std::fs::create_dir_all(metadata_path.parent().unwrap()).unwrap();
+        std::fs::write(&metadata_path, r#"{"user_tags": ["rpg"]}"#).unwrap();
+        let value = serde_json::to_value(&keys).unwrap();
+        GameLibrary::record_serial_keys(&games_dir, "RJ01000001", &value).unwrap();
+        let metadata: Value =
+            serde_json::from_str(&std::fs::read_to_string(&metadata_path).unwrap()).unwrap();
+        assert_eq!(metadata["serial_keys"][0]["code"], "HX7Q-29LM-A0ZP-44KD");
+        assert_eq!(metadata["user_tags"][0], "rpg");
+        std::fs::remove_dir_all(games_dir).ok();
+
+        let err = session.serial_keys("RJ09999999").await.unwrap_err();
+        assert_eq!(
+            dlsite_error(&err),
+            Some(&DlsiteError::NotPurchased("RJ09999999".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn library_is_empty_without_a_session() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let session = session_for(&server);
+
+        assert!(session.sync_library(true).await.unwrap().products.is_empty());
+        assert!(server.requests_to("/play/api/purchases").is_empty());
+    }
+
+    #[tokio::test]
+    async fn product_metadata_and_signed_cookie() {
+        let server = MockServer::start().await;
+        server.on("GET", "/maniax/api/=/product.json", |req| {
+            match req.query("workno") {
+                Some("RJ01000001") => MockResponse::fixture("dlsite/product_metadata.json"),
+                _ => MockResponse::status(404),
+            }
+        });
+        server.respond(
+            "GET",
+            "/dl/api/download/sign/cookie",
+            MockResponse::fixture("dlsite/sign_cookie.json"),
+        );
+        let session = session_for(&server);
+
+        let metadata = session
+            .product_metadata("RJ01000001".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata[0]["maker_name"], "Circle Sakura");
+        assert!(session
+            .product_metadata("RJ09999999".to_string())
+            .await
+            .unwrap()
+            .is_none());
+
+        let signed = session
+            .sign_cookie("RJ01000002".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(signed["cookies"]["CloudFront-Key-Pair-Id"], "K2ABCDEF");
+        assert_eq!(
+            server.requests_to("/dl/api/download/sign/cookie")[0].query("workno"),
+            Some("RJ01000002")
+        );
+    }
+
+    #[test]
+    fn product_json_builds_the_full_model() {
+        let data = crate::mock_server::fixture("dlsite/product_metadata.json");
+        let product = DlsiteProduct::from_value(&data[0]).unwrap();
+
+        assert_eq!(product.title, "夏の終わりのノベル");
+        assert_eq!(product.group_name, "Circle Sakura");
+        assert_eq!(product.age_rating, AgeRating::AllAges);
+        assert_eq!(product.age_category, "all");
+        assert_eq!(product.voice_actors, ["春野ひなた", "秋山みお"]);
+        assert_eq!(product.scenario, ["夏目蒼"]);
+        assert_eq!(product.illustrators, ["冬木しろ"]);
+        assert_eq!(product.os, ["windows", "macos"]);
+        assert_eq!(product.file_formats, ["アプリケーション"]);
+        assert_eq!(product.series.as_deref(), Some("季節のノベル"));
+        assert_eq!(product.rating, Some(4.52));
+        assert_eq!(product.rating_count, Some(128));
+        assert_eq!(product.tags, ["純愛", "学園もの"]);
+        assert!(product.thumbnail.starts_with("https://img.dlsite.jp/"));
+        assert_eq!(product.sample_images.len(), 2);
+        assert!(product.sample_images.iter().all(|url| url.starts_with("https://")));
+        assert_eq!(
+            product.language_editions[1],
+            LanguageEdition {
+                product_id: "RJ01100001".to_string(),
+                lang: "ENG".to_string(),
+                label: "English".to_string(),
+            }
+        );
+
+        let voice = DlsiteProduct::from_value(&data[1]).unwrap();
+        assert_eq!(voice.age_rating, AgeRating::Adult);
+        assert_eq!(voice.duration_secs, Some(5400));
+        assert_eq!(voice.rating, Some(4.0));
+        assert!(voice.os.is_empty());
+    }
+
+    fn work(work_type: &str, formats: &[&str], os: &[&str]) -> DlsiteProduct {
+        let mut product = DlsiteProduct::from_value(&serde_json::json!({
+            "id": "RJ01000099",
+            "work_type": work_type,
+        }))
+        .unwrap();
+        product.file_formats = formats.iter().map(|f| f.to_string()).collect();
+        product.os = os.iter().map(|o| o.to_string()).collect();
+        product
+    }
+
+    #[test]
+    fn works_are_classified_with_a_deck_verdict() {
+        let cases = [
+            (work("ADV", &["アプリケーション"], &["windows"]), "game", "runnable"),
+            (work("RPG", &[], &["windows", "macos"]), "game", "runnable"),
+            (work("SLN", &["ブラウザ専用"], &[]), "game", "browser_only"),
+            (work("ACN", &[], &["browser"]), "game", "browser_only"),
+            (work("PZL", &[], &["android", "ios"]), "game", "unsupported"),
+            (work("DNV", &[], &[]), "game", "unknown"),
+            (work("SOU", &["MP3"], &[]), "voice", "not_a_game"),
+            (work("MNG", &["PDF"], &[]), "comic", "not_a_game"),
+            (work("ICG", &["JPEG"], &[]), "cg", "not_a_game"),
+            (work("TOL", &["アプリケーション"], &["windows"]), "tool", "not_a_game"),
+            // Unknown codes fall back to the file formats
+            (work("XYZ", &["EXE"], &[]), "game", "runnable"),
+            (work("", &["WAV"], &[]), "voice", "not_a_game"),
+            (work("", &[], &[]), "other", "not_a_game"),
+        ];
+        for (product, category, verdict) in cases {
+            let label = format!("{} {:?}", product.work_type, product.file_formats);
+            assert_eq!(product.category().as_str(), category, "{}", label);
+            assert_eq!(product.deck_verdict().as_str(), verdict, "{}", label);
+        }
+    }
+
+    #[test]
+    fn library_filters_by_category() {
+        let products = vec![
+            work("ADV", &[], &["windows"]),
+            work("SOU", &[], &[]),
+            work("MNG", &[], &[]),
+        ];
+        let categories = parse_categories(Some(vec!["Game".into(), "asmr".into()]))
+            .unwrap()
+            .unwrap();
+        let kept: Vec<_> = filter_categories(products.clone(), Some(&categories))
+            .iter()
+            .map(|product| product.work_type.clone())
+            .collect();
+        assert_eq!(kept, ["ADV", "SOU"]);
+
+        assert!(parse_categories(Some(Vec::new())).unwrap().is_none());
+        assert_eq!(filter_categories(products, None).len(), 3);
+        let err = parse_categories(Some(vec!["novel-ish".into()])).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid work category: novel-ish");
+    }
+
+    #[tokio::test]
+    async fn library_entries_are_enriched_in_one_batch() {
+        let server = MockServer::start().await;
+        server.on("GET", "/maniax/api/=/product.json", |req| {
+            let wanted: Vec<&str> = req.query("workno").unwrap_or_default().split(',').collect();
+            let all = crate::mock_server::fixture("dlsite/product_metadata.json");
+            let found: Vec<Value> = all
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|item| wanted.contains(&item["workno"].as_str().unwrap()))
+                .cloned()
+                .collect();
+            MockResponse::json(&Value::Array(found))
+        });
+        let session = session_for(&server);
+        let page = crate::mock_server::fixture("dlsite/purchases_page1.json");
+        let mut products = DlsiteClient::parse_product_list(&page);
+        assert_eq!(products[0].age_rating, AgeRating::AllAges);
+        assert!(products[0].voice_actors.is_empty());
+
+        session.enrich(&mut products).await.unwrap();
+        let requests = server.requests_to("/maniax/api/=/product.json");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].query("workno"), Some("RJ01000001,RJ01000002"));
+
+        let novel = &products[0];
+        assert_eq!(novel.voice_actors, ["春野ひなた", "秋山みお"]);
+        assert_eq!(novel.os, ["windows", "macos"]);
+        // Purchase data wins over the store page
+        assert_eq!(novel.description, "A short summer visual novel.");
+        assert_eq!(novel.purchased_at.as_deref(), Some("2023-01-04T10:12:00+09:00"));
+        let radio = &products[1];
+        assert_eq!(radio.voice_actors, ["夜月ルカ"]);
+        assert_eq!(radio.file_formats, ["MP3"]);
+    }
+
+    #[tokio::test]
+    async fn search_sends_filters_and_returns_a_page() {
+        let server = MockServer::start().await;
+        let mut results = crate::mock_server::fixture("dlsite/search.json");
+        results["count"] = 120.into();
+        server.respond("GET", "/maniax/api/search", MockResponse::json(&results));
+        let session = session_for(&server);
+
+        let query = SearchQuery {
+            keyword: "rain".to_string(),
+            genres: vec![497, 66],
+            circle: Some("RG10004".to_string()),
+            age_category: Some("all_ages".to_string()),
+            min_price: Some(500),
+            max_price: Some(1500),
+            released_after: Some("2023-01-01".to_string()),
+            released_before: Some("2023-12-31".to_string()),
+            language: Some("JPN".to_string()),
+            order: Some("newest".to_string()),
+            page: Some(2),
+            ..SearchQuery::default()
+        };
+        let page = session.search(&query).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].title, "Rainy Station");
+        assert_eq!((page.total, page.page, page.per_page), (120, 2, 50));
+        assert!(page.has_next());
+
+        let sent = &server.requests_to("/maniax/api/search")[0];
+        let expected = [
+            ("keyword", "rain"),
+            ("order", "release_d"),
+            ("page", "2"),
+            ("per_page", "50"),
+            ("category", "all"),
+            ("genre", "497,66"),
+            ("maker_id", "RG10004"),
+            ("age_category", "1"),
+            ("price_low", "500"),
+            ("price_high", "1500"),
+            ("regist_date_start", "2023-01-01"),
+            ("regist_date_end", "2023-12-31"),
+            ("language", "JPN"),
+        ];
+        for (key, value) in expected {
+            assert_eq!(sent.query(key), Some(value), "{}", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn search_raises_instead_of_returning_nothing() {
+        let server = MockServer::start().await;
+        let session = session_for(&server);
+        let query = SearchQuery {
+            keyword: "rain".to_string(),
+            ..SearchQuery::default()
+        };
+
+        server.respond("GET", "/maniax/api/search", MockResponse::status(503));
+        let err = session.search(&query).await.unwrap_err();
+        assert!(err.to_string().contains("503"), "{}", err);
+
+        server.respond(
+            "GET",
+            "/maniax/api/search",
+            MockResponse::status(200).body("<html>maintenance</html>"),
+        );
+        let err = session.search(&query).await.unwrap_err();
+        assert_eq!(err.to_string(), "Invalid search response");
+
+        server.respond(
+            "GET",
+            "/maniax/api/search",
+            MockResponse::json(&serde_json::json!({ "error": "busy" })),
+        );
+        let err = session.search(&query).await.unwrap_err();
+        assert!(err.to_string().contains("no items"), "{}", err);
+
+        // An empty page is a valid answer
+        server.respond(
+            "GET",
+            "/maniax/api/search",
+            MockResponse::json(&serde_json::json!({ "count": 0, "items": [] })),
+        );
+        let page = session.search(&query).await.unwrap();
+        assert!(page.items.is_empty());
+        assert!(!page.has_next());
+    }
+
+    #[test]
+    fn search_query_rejects_impossible_filters() {
+        let reject = |edit: fn(&mut SearchQuery)| {
+            let mut query = SearchQuery::default();
+            edit(&mut query);
+            query.params().unwrap_err().to_string()
+        };
+        assert_eq!(reject(|q| q.order = Some("random".into())), "Invalid search order: random");
+        assert_eq!(reject(|q| q.page = Some(0)), "Search pages start at 1");
+        assert_eq!(
+            reject(|q| q.per_page = Some(500)),
+            "per_page must be between 1 and 100"
+        );
+        assert_eq!(
+            reject(|q| q.age_category = Some("teen".into())),
+            "Invalid age category: teen"
+        );
+        assert_eq!(
+            reject(|q| {
+                q.min_price = Some(2000);
+                q.max_price = Some(1000);
+            }),
+            "min_price 2000 is above max_price 1000"
+        );
+        assert_eq!(
+            reject(|q| q.released_after = Some("2023/01/01".into())),
+            "Invalid date 2023/01/01: expected YYYY-MM-DD"
+        );
+    }
+
+    #[tokio::test]
+    async fn download_follows_redirects_to_the_file() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.on("GET", "/maniax/download/=/product_id/RJ01000001.html", |req| {
+            if req.has_cookie("__DLsite_SID", "authenticated") {
+                MockResponse::status(302).header("location", "/get/=/type/work/file/RJ01000001.zip")
+            } else {
+                MockResponse::status(302).header("location", "/login-site/login")
+            }
+        });
+        let cdn = format!("{}/cdn/RJ01000001.zip?sig=abc", server.url());
+        server.respond(
+            "GET",
+            "/get/=/type/work/file/RJ01000001.zip",
+            MockResponse::status(302).header("location", &cdn),
+        );
+        server.respond(
+            "GET",
+            "/cdn/RJ01000001.zip",
+            MockResponse::status(206)
+                .header("content-range", "bytes 0-0/734003200")
+                .header("content-disposition", "attachment; filename=\"RJ01000001.zip\"")
+                .body("P"),
+        );
+        let session = logged_in_session(&server).await;
+
+        let parts = session.resolve_download("RJ01000001").await.unwrap();
+        assert_eq!(
+            parts,
+            [DownloadPart {
+                part: 1,
+                url: cdn,
+                filename: "RJ01000001.zip".to_string(),
+                size: Some(734003200),
+            }]
+        );
+        let fetched = &server.requests_to("/cdn/RJ01000001.zip")[0];
+        assert_eq!(fetched.header("range"), Some("bytes=0-0"));
+        assert_eq!(fetched.query("sig"), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn split_work_resolves_every_part() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond(
+            "GET",
+            "/maniax/download/=/product_id/RJ01000003.html",
+            MockResponse::html_fixture("dlsite/split_download.html"),
+        );
+        for (number, file) in [(1, "RJ01000003.part1.exe"), (2, "RJ01000003.part2.rar")] {
+            server.respond(
+                "GET",
+                &format!("/maniax/download/=/number/{}/product_id/RJ01000003.html", number),
+                MockResponse::status(302).header("location", &format!("/cdn/{}", file)),
+            );
+        }
+        server.respond(
+            "GET",
+            "/cdn/RJ01000003.part1.exe",
+            MockResponse::status(206)
+                .header("content-range", "bytes 0-0/2093796557")
+                .header(
+                    "content-disposition",
+                    "attachment; filename=\"part1.exe\"; filename*=UTF-8''%E9%9B%A8.part1.exe",
+                )
+                .body("M"),
+        );
+        // This host ignores the range and sends the whole file
+        server.respond(
+            "GET",
+            "/cdn/RJ01000003.part2.rar",
+            MockResponse::status(200).body("0123456789"),
+        );
+        let session = logged_in_session(&server).await;
+
+        let parts = session.resolve_download("RJ01000003").await.unwrap();
+        let summary: Vec<_> = parts
+            .iter()
+            .map(|part| (part.part, part.filename.as_str(), part.size))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, "雨.part1.exe", Some(2093796557)),
+                (2, "RJ01000003.part2.rar", Some(10)),
+            ]
+        );
+        assert_eq!(parts[1].url, format!("{}/cdn/RJ01000003.part2.rar", server.url()));
+    }
+
+    #[tokio::test]
+    async fn download_failures_are_told_apart() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        let page = |id: &str| format!("/maniax/download/=/product_id/{}.html", id);
+        server.respond(
+            "GET",
+            &page("RJ09999999"),
+            MockResponse::status(302)
+                .header("location", "/maniax/work/=/product_id/RJ09999999.html"),
+        );
+        server.respond("GET", &page("RJ09999998"), MockResponse::status(403));
+        server.respond(
+            "GET",
+            &page("RJ02000001"),
+            MockResponse::status(302)
+                .header("location", &format!("{}/play/work/RJ02000001", server.url())),
+        );
+        server.respond(
+            "GET",
+            &page("RJ02000002"),
+            MockResponse::status(200)
+                .header("content-type", "text/html")
+                .body("<p>この作品はブラウザ視聴専用です</p>"),
+        );
+        let session = logged_in_session(&server).await;
+
+        let kind = |result: Result<Vec<DownloadPart>>| dlsite_error(&result.unwrap_err()).cloned();
+        assert_eq!(
+            kind(session.resolve_download("RJ09999999").await),
+            Some(DlsiteError::NotPurchased("RJ09999999".to_string()))
+        );
+        assert_eq!(
+            kind(session.resolve_download("RJ09999998").await),
+            Some(DlsiteError::NotPurchased("RJ09999998".to_string()))
+        );
+        assert_eq!(
+            kind(session.resolve_download("RJ02000001").await),
+            Some(DlsiteError::BrowserOnly("RJ02000001".to_string()))
+        );
+        assert_eq!(
+            kind(session.resolve_download("RJ02000002").await),
+            Some(DlsiteError::BrowserOnly("RJ02000002".to_string()))
+        );
+        // None of these say anything about the session
+        assert_eq!(session.auth_state(), AuthState::Verified);
+    }
+
+    #[tokio::test]
+    async fn login_redirect_during_download_ends_the_session() {
+        let server = MockServer::start().await;
+        mount_login(&server);
+        server.respond(
+            "GET",
+            "/maniax/download/=/product_id/RJ01000001.html",
+            MockResponse::status(302).header(
+                "location",
+                &format!("{}/login-site/login?redirect=download", server.url()),
+            ),
+        );
+        let session = logged_in_session(&server).await;
+
+        let err = session.resolve_download("RJ01000001").await.unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::SessionExpired));
+        assert_eq!(session.auth_state(), AuthState::LoggedOut);
+
+        // Without a session nothing is requested at all
+        let err = session_for(&server)
+            .resolve_download("RJ01000001")
+            .await
+            .unwrap_err();
+        assert_eq!(dlsite_error(&err), Some(&DlsiteError::SessionExpired));
+        let page_requests = server.requests_to("/maniax/download/=/product_id/RJ01000001.html");
+        assert_eq!(page_requests.len(), 1);
+    }
+
+    #[test]
+    fn download_page_uses_configured_base_url() {
+        let session = DlsiteSession::new(
+            DlsiteEndpoints {
+                base_url: trim_endpoint("http://mirror.local/".to_string()),
+                ..DlsiteEndpoints::default()
+            },
+            "vn-core-tests",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            session.download_page_url("RJ01000001"),
+            "http://mirror.local/maniax/download/=/product_id/RJ01000001.html"
+        );
     }
 }
@@ -1,23 +1,48 @@
-use crate::util::{extract_zip, runtime_error, sha1_file, value_to_py};
+use crate::game_library::GameLibrary;
+use crate::integrity::{digests_match, HashAlgorithm, IntegritySpec, StreamHasher};
+use crate::util::{extract_serde, extract_value, extract_zip_inner, runtime_error, value_to_py};
 use crate::json_result;
 use anyhow::{anyhow, Context, Result};
-use futures::StreamExt;
 use pyo3::prelude::*;
-use reqwest::Client;
+use crate::http::{next_chunk, ClientOptions, HttpClient};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 use time::OffsetDateTime;
 use tokio::fs;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::RwLock;
 use tokio::time::Instant;
 
+#[cfg(not(test))]
 const DOWNLOAD_EVENT_NAME: &str = "visual_novel_manager/download-update";
 
-#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+/// Failures after which a source is no longer handed new chunks.
+const MAX_SOURCE_FAILURES: u32 = 3;
+/// Weight of the newest sample in a source's moving throughput/latency averages.
+const SPEED_SMOOTHING: f64 = 0.3;
+/// Completed chunks needed before a source's measured speed is trusted for ranking.
+const MIN_RANKING_SAMPLES: u32 = 2;
+/// A mirror must be this much faster than the active one before it is promoted.
+const PROMOTION_MARGIN: f64 = 1.25;
+/// Bytes of out-of-order chunks held in memory for the whole-file hash; past this they
+/// are read back from disk when the hash reaches them.
+const MAX_HASH_BUFFER_BYTES: usize = 64 * 1024 * 1024;
+/// Archives are extracted here first and only merged into the game directory once complete.
+const STAGING_DIR_NAME: &str = ".staging";
+pub(crate) const INSTALL_MARKER: &str = ".download_complete";
+const SOURCE_HISTORY_FILE: &str = "source_history.json";
+/// Extra space reserved on top of the archive for extracting it.
+const EXTRACTION_HEADROOM: f64 = 1.5;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// URL fragment naming the server a source is fetched from, e.g. `#via=203.0.113.7`.
+const PIN_FRAGMENT: &str = "via=";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum DownloadStatus {
     Pending,
@@ -56,6 +81,16 @@ struct DownloadSource {
     active_connections: u32,
     failures: u32,
     last_speed: f64,
+    /// Smoothed per-connection throughput in bytes per second.
+    #[serde(default)]
+    avg_speed: f64,
+    /// Smoothed time to first byte in milliseconds.
+    #[serde(default)]
+    avg_latency_ms: f64,
+    #[serde(default)]
+    samples: u32,
+    #[serde(default)]
+    bytes_downloaded: u64,
 }
 
 impl DownloadSource {
@@ -67,10 +102,137 @@ impl DownloadSource {
             active_connections: 0,
             failures: 0,
             last_speed: 0.0,
+            avg_speed: 0.0,
+            avg_latency_ms: 0.0,
+            samples: 0,
+            bytes_downloaded: 0,
+        }
+    }
+
+    fn is_usable(&self) -> bool {
+        self.failures < MAX_SOURCE_FAILURES
+    }
+
+    fn is_measured(&self) -> bool {
+        self.samples >= MIN_RANKING_SAMPLES
+    }
+
+    fn record_sample(&mut self, sample: &TransferSample) {
+        let secs = sample.elapsed.as_secs_f64().max(0.001);
+        let speed = sample.bytes as f64 / secs;
+        let latency_ms = sample.latency.as_secs_f64() * 1000.0;
+
+        if self.samples == 0 {
+            self.avg_speed = speed;
+            self.avg_latency_ms = latency_ms;
+        } else {
+            self.avg_speed = SPEED_SMOOTHING * speed + (1.0 - SPEED_SMOOTHING) * self.avg_speed;
+            self.avg_latency_ms =
+                SPEED_SMOOTHING * latency_ms + (1.0 - SPEED_SMOOTHING) * self.avg_latency_ms;
+        }
+        self.last_speed = speed;
+        self.samples += 1;
+        self.bytes_downloaded += sample.bytes;
+    }
+
+    fn stats(&self) -> SourceStats {
+        SourceStats {
+            url: self.url.clone(),
+            priority: self.priority,
+            active_connections: self.active_connections,
+            failures: self.failures,
+            speed: self.avg_speed,
+            latency_ms: self.avg_latency_ms,
+            bytes_downloaded: self.bytes_downloaded,
+        }
+    }
+}
+
+/// Timing of a single ranged request, used to rank sources.
+struct TransferSample {
+    bytes: u64,
+    latency: Duration,
+    elapsed: Duration,
+    /// Chunk bytes, kept only while the whole-file hash still needs them.
+    data: Option<Vec<u8>>,
+}
+
+/// Chunks taken out of the state to be folded into the whole-file hash, as
+/// `(start, size, data)`; chunks without data are read back from the temp file.
+struct HashWork {
+    hasher: StreamHasher,
+    chunks: Vec<(u64, u64, Option<Vec<u8>>)>,
+}
+
+impl HashWork {
+    async fn run(&mut self, temp_path: &Path) -> Result<()> {
+        let Self { hasher, chunks } = self;
+        let mut file: Option<fs::File> = None;
+        for (start, size, data) in chunks.iter() {
+            if let Some(data) = data {
+                hasher.update(data);
+                continue;
+            }
+            if file.is_none() {
+                file = Some(
+                    fs::File::open(temp_path)
+                        .await
+                        .with_context(|| format!("Failed to open {}", temp_path.display()))?,
+                );
+            }
+            let handle = file.as_mut().expect("file opened above");
+            handle.seek(tokio::io::SeekFrom::Start(*start)).await?;
+            let mut data = vec![0u8; *size as usize];
+            handle
+                .read_exact(&mut data)
+                .await
+                .with_context(|| format!("Failed to read {}", temp_path.display()))?;
+            hasher.update(&data);
         }
+        Ok(())
     }
 }
 
+/// Fold every completed chunk the whole-file hash can take into it. The hashing and
+/// any reads from disk happen without holding the state lock.
+async fn advance_file_hash(state: &RwLock<DownloadState>, temp_path: &Path) {
+    loop {
+        let Some(mut work) = state.write().await.take_hash_work() else {
+            return;
+        };
+        let result = work.run(temp_path).await;
+        state.write().await.finish_hash_work(work, result);
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SwitchReason {
+    Faster,
+    SourceFailed,
+    Manual,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SourceSwitch {
+    pub from: Option<String>,
+    pub to: String,
+    pub reason: SwitchReason,
+    pub detail: String,
+    pub at: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SourceStats {
+    pub url: String,
+    pub priority: u32,
+    pub active_connections: u32,
+    pub failures: u32,
+    pub speed: f64,
+    pub latency_ms: f64,
+    pub bytes_downloaded: u64,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct DownloadChunk {
     id: u32,
@@ -111,6 +273,9 @@ pub struct DownloadSnapshot {
     pub message: Option<String>,
     pub started_at: i64,
     pub updated_at: i64,
+    pub active_source: Option<String>,
+    pub sources: Vec<SourceStats>,
+    pub source_switches: Vec<SourceSwitch>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -129,11 +294,37 @@ struct DownloadState {
     #[serde(with = "time::serde::rfc3339")]
     updated_at: OffsetDateTime,
     integrity_hash: Option<String>,
+    #[serde(default)]
+    integrity: Option<IntegritySpec>,
     sources: Vec<DownloadSource>,
     chunks: Vec<DownloadChunk>,
     chunk_size: u64,
     max_concurrent_chunks: u32,
     task_id: String,
+    #[serde(default)]
+    active_source: Option<String>,
+    #[serde(default)]
+    source_switches: Vec<SourceSwitch>,
+    /// Fields merged into the game's metadata.json once installed, e.g. the store
+    /// build id and version used for update checks.
+    #[serde(default)]
+    install_metadata: Option<Value>,
+    /// Running whole-file hash, fed with chunks in file order as they complete.
+    #[serde(skip)]
+    file_hasher: Option<StreamHasher>,
+    #[serde(skip)]
+    hashed_chunks: usize,
+    #[serde(skip)]
+    hash_buffers: HashMap<usize, Vec<u8>>,
+    #[serde(skip)]
+    hash_buffer_bytes: usize,
+    /// Set while a chunk task has the hasher out to feed it without the state lock.
+    #[serde(skip)]
+    hashing: bool,
+    /// Why the running whole-file hash could not be advanced; it is then redone from
+    /// disk once the download is complete.
+    #[serde(skip)]
+    hash_error: Option<String>,
     #[serde(skip, default = "DownloadState::instant_now")]
     last_event_emit: Instant,
     #[serde(skip, default)]
@@ -148,16 +339,23 @@ impl DownloadState {
         game_name: String,
         total_size: u64,
         integrity_hash: Option<String>,
+        integrity: Option<IntegritySpec>,
         sources: Vec<String>,
         task_id: String,
     ) -> Self {
-        let download_sources = sources
+        let download_sources: Vec<DownloadSource> = sources
             .into_iter()
             .enumerate()
             .map(|(i, url)| DownloadSource::new(url, i as u32))
             .collect();
-
-        let chunk_size = 1024 * 1024; // 1MB chunks
+        let active_source = download_sources.first().map(|s| s.url.clone());
+
+        // Chunks must line up with the published chunk hashes when there are any
+        let chunk_size = integrity
+            .as_ref()
+            .filter(|spec| spec.has_chunk_hashes())
+            .and_then(|spec| spec.chunk_size)
+            .unwrap_or(1024 * 1024); // 1MB chunks
         let chunks = Self::create_chunks(total_size, chunk_size);
 
         Self {
@@ -173,11 +371,21 @@ impl DownloadState {
             created_at: OffsetDateTime::now_utc(),
             updated_at: OffsetDateTime::now_utc(),
             integrity_hash,
+            integrity,
             sources: download_sources,
             chunks,
             chunk_size,
             max_concurrent_chunks: 8,
             task_id,
+            active_source,
+            source_switches: Vec::new(),
+            install_metadata: None,
+            file_hasher: None,
+            hashed_chunks: 0,
+            hash_buffers: HashMap::new(),
+            hash_buffer_bytes: 0,
+            hashing: false,
+            hash_error: None,
             last_event_emit: Instant::now(),
             last_event_progress: -1.0,
             last_event_status: DownloadStatus::Pending,
@@ -229,16 +437,291 @@ impl DownloadState {
         chunks
     }
 
+    /// Desired connection count per source (same order as `sources`).
+    ///
+    /// Every unmeasured source gets one probe connection so it can be ranked; the rest
+    /// of the budget is split between measured sources in proportion to their speed.
+    /// Until anything has been measured, the budget goes to sources in priority order.
+    fn connection_targets(&self) -> Vec<u32> {
+        let mut targets = vec![0u32; self.sources.len()];
+        let mut budget = self.max_concurrent_chunks;
+
+        for (i, source) in self.sources.iter().enumerate() {
+            if budget > 0 && source.is_usable() && !source.is_measured() {
+                targets[i] = 1;
+                budget -= 1;
+            }
+        }
+
+        let total_speed: f64 = self
+            .sources
+            .iter()
+            .filter(|s| s.is_usable() && s.is_measured())
+            .map(|s| s.avg_speed)
+            .sum();
+
+        if total_speed > 0.0 {
+            let share_budget = budget;
+            for (i, source) in self.sources.iter().enumerate() {
+                if source.is_usable() && source.is_measured() {
+                    let share = (source.avg_speed / total_speed * share_budget as f64).floor() as u32;
+                    let share = share.min(source.max_connections).min(budget);
+                    targets[i] += share;
+                    budget -= share;
+                }
+            }
+        }
+
+        // Hand out what is left (rounding remainder or no measurements yet) by rank.
+        let mut order: Vec<usize> = (0..self.sources.len())
+            .filter(|&i| self.sources[i].is_usable())
+            .collect();
+        order.sort_by_key(|&i| self.sources[i].priority);
+        for i in order {
+            if budget == 0 {
+                break;
+            }
+            let room = self.sources[i].max_connections.saturating_sub(targets[i]);
+            let extra = room.min(budget);
+            targets[i] += extra;
+            budget -= extra;
+        }
+
+        targets
+    }
+
     fn select_best_source(&mut self) -> Option<&mut DownloadSource> {
-        self.sources
-            .iter_mut()
-            .filter(|s| s.active_connections < s.max_connections && s.failures < 3)
-            .min_by_key(|s| (s.priority, s.active_connections, s.failures))
+        let targets = self.connection_targets();
+        let best = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_usable() && s.active_connections < s.max_connections)
+            .max_by(|(a_idx, a), (b_idx, b)| {
+                let a_deficit = targets[*a_idx] as i64 - a.active_connections as i64;
+                let b_deficit = targets[*b_idx] as i64 - b.active_connections as i64;
+                a_deficit
+                    .cmp(&b_deficit)
+                    .then_with(|| b.priority.cmp(&a.priority))
+            })
+            .map(|(i, _)| i)?;
+        self.sources.get_mut(best)
+    }
+
+    /// Make `index` the primary source, shifting the others down one priority step.
+    fn promote_source(&mut self, index: usize, reason: SwitchReason, detail: String) {
+        let previous_priority = self.sources[index].priority;
+        for source in &mut self.sources {
+            if source.priority < previous_priority {
+                source.priority += 1;
+            }
+        }
+        self.sources[index].priority = 0;
+
+        let to = self.sources[index].url.clone();
+        if self.active_source.as_deref() != Some(to.as_str()) {
+            self.source_switches.push(SourceSwitch {
+                from: self.active_source.take(),
+                to: to.clone(),
+                reason,
+                detail,
+                at: OffsetDateTime::now_utc().unix_timestamp(),
+            });
+        }
+        self.active_source = Some(to);
+    }
+
+    /// Re-rank sources after new measurements and promote a mirror when the active one
+    /// has failed or a measured alternative is clearly faster.
+    fn review_active_source(&mut self) {
+        let current = self
+            .active_source
+            .as_ref()
+            .and_then(|url| self.sources.iter().position(|s| &s.url == url));
+
+        let fastest = self
+            .sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_usable() && s.is_measured())
+            .max_by(|(_, a), (_, b)| {
+                a.avg_speed
+                    .partial_cmp(&b.avg_speed)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i);
+
+        match current {
+            Some(idx) if !self.sources[idx].is_usable() => {
+                let replacement = fastest.or_else(|| {
+                    self.sources
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, s)| s.is_usable())
+                        .min_by_key(|(_, s)| s.priority)
+                        .map(|(i, _)| i)
+                });
+                if let Some(next) = replacement {
+                    let detail = format!(
+                        "{} failed {} times",
+                        self.sources[idx].url, self.sources[idx].failures
+                    );
+                    self.promote_source(next, SwitchReason::SourceFailed, detail);
+                }
+            }
+            Some(idx) => {
+                let Some(candidate) = fastest else { return };
+                if candidate == idx || !self.sources[idx].is_measured() {
+                    return;
+                }
+                let current_speed = self.sources[idx].avg_speed;
+                let candidate_speed = self.sources[candidate].avg_speed;
+                if candidate_speed > current_speed * PROMOTION_MARGIN {
+                    let detail = format!(
+                        "{:.0} KB/s vs {:.0} KB/s",
+                        candidate_speed / 1024.0,
+                        current_speed / 1024.0
+                    );
+                    self.promote_source(candidate, SwitchReason::Faster, detail);
+                }
+            }
+            None => {
+                if let Some(next) = fastest {
+                    self.active_source = Some(self.sources[next].url.clone());
+                }
+            }
+        }
+    }
+
+    fn needs_file_hash(&self) -> bool {
+        self.integrity
+            .as_ref()
+            .map(|spec| spec.hash.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Keep a completed chunk's bytes for the whole-file hash while the buffer has room.
+    fn buffer_for_hash(&mut self, index: usize, data: Vec<u8>) {
+        if index < self.hashed_chunks
+            || self.hash_buffer_bytes + data.len() > MAX_HASH_BUFFER_BYTES
+        {
+            return;
+        }
+        self.hash_buffer_bytes += data.len();
+        if let Some(replaced) = self.hash_buffers.insert(index, data) {
+            self.hash_buffer_bytes -= replaced.len();
+        }
+    }
+
+    /// Take the hasher and the completed chunks that directly follow the hash cursor,
+    /// so they can be hashed without holding the state lock. `None` when there is
+    /// nothing to do or another task is already hashing.
+    fn take_hash_work(&mut self) -> Option<HashWork> {
+        if self.hashing || self.hash_error.is_some() {
+            return None;
+        }
+        let algorithm = self.integrity.as_ref().filter(|spec| spec.hash.is_some())?.algorithm;
+        let mut chunks = Vec::new();
+        for index in self.hashed_chunks..self.chunks.len() {
+            let chunk = &self.chunks[index];
+            if chunk.status != DownloadStatus::Completed {
+                break;
+            }
+            let data = self.hash_buffers.remove(&index);
+            if let Some(data) = &data {
+                self.hash_buffer_bytes -= data.len();
+            }
+            chunks.push((chunk.start, chunk.size, data));
+        }
+        if chunks.is_empty() {
+            return None;
+        }
+        self.hashing = true;
+        Some(HashWork {
+            hasher: self.file_hasher.take().unwrap_or_else(|| algorithm.hasher()),
+            chunks,
+        })
+    }
+
+    /// Put the hasher back after `take_hash_work`. A failure is kept in `hash_error`
+    /// and the message instead of being dropped.
+    fn finish_hash_work(&mut self, work: HashWork, result: Result<()>) {
+        self.hashing = false;
+        match result {
+            Ok(()) => {
+                self.hashed_chunks += work.chunks.len();
+                self.file_hasher = Some(work.hasher);
+            }
+            Err(err) => {
+                let error = format!("{:#}", err);
+                self.message = Some(format!("Whole-file hash will be redone: {}", error));
+                self.hash_error = Some(error);
+                self.file_hasher = None;
+            }
+        }
+    }
+
+    /// Start the whole-file hash over, reading every chunk back from disk.
+    fn restart_file_hash(&mut self) {
+        self.file_hasher = None;
+        self.hashed_chunks = 0;
+        self.hash_error = None;
+        self.hash_buffers.clear();
+        self.hash_buffer_bytes = 0;
+    }
+
+    /// Throw away every downloaded byte so the next run fetches the whole file again,
+    /// e.g. after the assembled file failed its whole-file hash.
+    fn discard_chunks(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.status = DownloadStatus::Pending;
+            chunk.retry_count = 0;
+            chunk.downloaded = 0;
+        }
+        self.restart_file_hash();
+        self.downloaded_size = 0;
+        self.progress = 0.0;
+    }
+
+    /// Give failed chunks and sources a fresh retry budget.
+    fn reset_for_retry(&mut self) {
+        for chunk in &mut self.chunks {
+            if chunk.status == DownloadStatus::Failed {
+                chunk.status = DownloadStatus::Pending;
+                chunk.retry_count = 0;
+                chunk.downloaded = 0;
+            }
+        }
+        for source in &mut self.sources {
+            source.failures = 0;
+        }
+    }
+
+    /// Forget work that was in flight when the download task stopped: connection slots
+    /// are released and interrupted chunks go back to pending.
+    fn release_in_flight(&mut self) {
+        for source in &mut self.sources {
+            source.active_connections = 0;
+        }
+        for chunk in &mut self.chunks {
+            if chunk.status == DownloadStatus::Downloading {
+                chunk.status = DownloadStatus::Pending;
+                chunk.downloaded = 0;
+            }
+        }
+        self.hash_buffers.clear();
+        self.hash_buffer_bytes = 0;
+        // A task stopped while hashing took the hasher with it
+        if self.hashing {
+            self.hashing = false;
+            self.restart_file_hash();
+        }
     }
 
     async fn save_to_disk(&self, downloads_dir: &Path) -> Result<()> {
         let state_file = downloads_dir.join(format!("{}.json", self.game_id));
         let json = serde_json::to_string_pretty(self)?;
+        tokio::fs::create_dir_all(downloads_dir).await?;
         tokio::fs::write(state_file, json).await?;
         Ok(())
     }
@@ -249,10 +732,17 @@ impl DownloadState {
             return None;
         }
 
-        match tokio::fs::read_to_string(state_file).await {
-            Ok(content) => serde_json::from_str(&content).ok(),
-            Err(_) => None,
+        let mut state: Self = match tokio::fs::read_to_string(state_file).await {
+            Ok(content) => serde_json::from_str(&content).ok()?,
+            Err(_) => return None,
+        };
+        // State files written before typed integrity specs only carry the bare hash
+        if state.integrity.is_none() {
+            if let Some(legacy) = state.integrity_hash.as_deref() {
+                state.integrity = IntegritySpec::from_legacy(legacy).ok().flatten();
+            }
         }
+        Some(state)
     }
 
     fn snapshot(&self) -> DownloadSnapshot {
@@ -268,6 +758,9 @@ impl DownloadState {
             message: self.message.clone(),
             started_at: self.created_at.unix_timestamp(),
             updated_at: self.updated_at.unix_timestamp(),
+            active_source: self.active_source.clone(),
+            sources: self.sources.iter().map(DownloadSource::stats).collect(),
+            source_switches: self.source_switches.clone(),
         }
     }
 
@@ -283,6 +776,124 @@ impl DownloadState {
     }
 }
 
+fn game_dir_for(base_dir: &Path, game_id: &str) -> PathBuf {
+    base_dir.join(format!("game_{}", game_id))
+}
+
+/// `url` fetched from the server at `ip` instead of whatever its host name resolves to.
+/// The choice is kept in the fragment, which is never sent, so each server stays a
+/// source of its own while TLS still checks the original host name.
+pub(crate) fn pin_source(url: &str, ip: IpAddr) -> Option<String> {
+    let mut parsed = reqwest::Url::parse(url).ok()?;
+    parsed.host_str()?;
+    parsed.set_fragment(Some(&format!("{}{}", PIN_FRAGMENT, ip)));
+    Some(parsed.to_string())
+}
+
+/// Host name and address a source pinned by `pin_source` connects to.
+fn source_pin(url: &str) -> Option<(String, SocketAddr)> {
+    let parsed = reqwest::Url::parse(url).ok()?;
+    let ip = parsed.fragment()?.strip_prefix(PIN_FRAGMENT)?.parse::<IpAddr>().ok()?;
+    let port = parsed.port_or_known_default()?;
+    Some((parsed.host_str()?.to_string(), SocketAddr::new(ip, port)))
+}
+
+fn client_for_source(client: &HttpClient, url: &str) -> HttpClient {
+    source_pin(url)
+        .and_then(|(host, addr)| client.pinned(&host, addr).ok())
+        .unwrap_or_else(|| client.clone())
+}
+
+fn temp_path_for(game_dir: &Path, game_id: &str) -> PathBuf {
+    game_dir.join(format!("{}.tmp", game_id))
+}
+
+/// Move everything extracted into `staging` over the matching entries in `game_dir`.
+fn commit_staging(staging: &Path, game_dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(staging)
+        .with_context(|| format!("Failed to read {}", staging.display()))?
+    {
+        let entry = entry?;
+        let target = game_dir.join(entry.file_name());
+        if target.is_dir() {
+            std::fs::remove_dir_all(&target)
+                .with_context(|| format!("Failed to replace {}", target.display()))?;
+        } else if target.exists() {
+            std::fs::remove_file(&target)
+                .with_context(|| format!("Failed to replace {}", target.display()))?;
+        }
+        std::fs::rename(entry.path(), &target)
+            .with_context(|| format!("Failed to move {}", target.display()))?;
+    }
+    std::fs::remove_dir_all(staging)
+        .with_context(|| format!("Failed to remove {}", staging.display()))
+}
+
+/// Whether `game_dir` holds an earlier install. Games installed before the marker
+/// existed only have their metadata and extracted files to show for it.
+async fn has_previous_install(game_dir: &Path, partial: &[&Path]) -> bool {
+    if game_dir.join(INSTALL_MARKER).exists() || game_dir.join("metadata.json").exists() {
+        return true;
+    }
+    let Ok(mut entries) = fs::read_dir(game_dir).await else {
+        return false;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if entry.file_name() != STAGING_DIR_NAME && !partial.contains(&path.as_path()) {
+            return true;
+        }
+    }
+    false
+}
+
+/// Remove the leftovers of a cancelled download and report what was deleted.
+///
+/// The temp file and staging directory always go. The archive and the game directory
+/// itself are only removed when the directory did not hold a previous install, and
+/// the directory only when nothing else (e.g. user metadata) is left in it.
+async fn remove_partial_data(game_dir: &Path, game_id: &str, game_name: &str) -> Vec<String> {
+    let mut removed = Vec::new();
+    if !game_dir.exists() {
+        return removed;
+    }
+    let temp_path = temp_path_for(game_dir, game_id);
+    let zip_path = game_dir.join(format!("{}.zip", game_name));
+    let previous_install = has_previous_install(game_dir, &[&temp_path, &zip_path]).await;
+
+    let mut files = vec![temp_path];
+    if !previous_install {
+        files.push(zip_path);
+    }
+    for path in files {
+        if fs::remove_file(&path).await.is_ok() {
+            removed.push(path.to_string_lossy().to_string());
+        }
+    }
+
+    let staging = game_dir.join(STAGING_DIR_NAME);
+    if fs::remove_dir_all(&staging).await.is_ok() {
+        removed.push(staging.to_string_lossy().to_string());
+    }
+
+    if !previous_install {
+        let is_empty = match fs::read_dir(game_dir).await {
+            Ok(mut entries) => matches!(entries.next_entry().await, Ok(None)),
+            Err(_) => false,
+        };
+        if is_empty && fs::remove_dir(game_dir).await.is_ok() {
+            removed.push(game_dir.to_string_lossy().to_string());
+        }
+    }
+
+    removed
+}
+
+/// Unit tests run without an interpreter, so there is nobody to tell.
+#[cfg(test)]
+fn emit_download_event(_state: &DownloadState) {}
+
+#[cfg(not(test))]
 fn emit_download_event(state: &DownloadState) {
     if let Ok(value) = serde_json::to_value(state.snapshot()) {
         Python::with_gil(|py| {
@@ -291,11 +902,8 @@ fn emit_download_event(state: &DownloadState) {
                     // Prefer decky.emit (async); fall back to decky.emit_event if present
                     let emit_attr = decky_mod.getattr("emit").or_else(|_| decky_mod.getattr("emit_event"));
                     if let Ok(emit_fn) = emit_attr {
-                        match emit_fn.call1((DOWNLOAD_EVENT_NAME, payload)) {
-                            Ok(coro) => {
-                                let _ = asyncio.call_method1("create_task", (coro,));
-                            }
-                            Err(_) => {}
+                        if let Ok(coro) = emit_fn.call1((DOWNLOAD_EVENT_NAME, payload)) {
+                            let _ = asyncio.call_method1("create_task", (coro,));
                         }
                     }
                 }
@@ -304,6 +912,259 @@ fn emit_download_event(state: &DownloadState) {
     }
 }
 
+/// Throughput measured per host across downloads, used to estimate future ones.
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct HostHistory {
+    avg_speed: f64,
+    avg_latency_ms: f64,
+    samples: u32,
+    updated_at: i64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SourceHistory {
+    hosts: HashMap<String, HostHistory>,
+}
+
+impl SourceHistory {
+    fn load(downloads_dir: &Path) -> Self {
+        std::fs::read_to_string(downloads_dir.join(SOURCE_HISTORY_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    async fn save(&self, downloads_dir: &Path) -> Result<()> {
+        fs::create_dir_all(downloads_dir).await?;
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(downloads_dir.join(SOURCE_HISTORY_FILE), json).await?;
+        Ok(())
+    }
+
+    /// The server a source is measured against: its pinned address, else its host.
+    fn host_of(url: &str) -> Option<String> {
+        if let Some((_, addr)) = source_pin(url) {
+            return Some(addr.ip().to_string());
+        }
+        reqwest::Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(|host| host.to_string()))
+    }
+
+    fn record(&mut self, sources: &[DownloadSource]) {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        for source in sources.iter().filter(|s| s.samples > 0) {
+            let Some(host) = Self::host_of(&source.url) else {
+                continue;
+            };
+            let entry = self.hosts.entry(host).or_default();
+            if entry.samples == 0 {
+                entry.avg_speed = source.avg_speed;
+                entry.avg_latency_ms = source.avg_latency_ms;
+            } else {
+                entry.avg_speed =
+                    SPEED_SMOOTHING * source.avg_speed + (1.0 - SPEED_SMOOTHING) * entry.avg_speed;
+                entry.avg_latency_ms = SPEED_SMOOTHING * source.avg_latency_ms
+                    + (1.0 - SPEED_SMOOTHING) * entry.avg_latency_ms;
+            }
+            entry.samples = entry.samples.saturating_add(source.samples);
+            entry.updated_at = now;
+        }
+    }
+
+    fn get(&self, url: &str) -> Option<&HostHistory> {
+        Self::host_of(url).and_then(|host| self.hosts.get(&host))
+    }
+}
+
+#[derive(Serialize)]
+struct SourceProbe {
+    url: String,
+    reachable: bool,
+    status: Option<u16>,
+    size: Option<u64>,
+    range_support: bool,
+    latency_ms: Option<f64>,
+    historical_speed: Option<f64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiskEstimate {
+    download_bytes: u64,
+    extraction_headroom_bytes: u64,
+    required_bytes: u64,
+    available_bytes: Option<u64>,
+    sufficient: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct DownloadConflict {
+    kind: &'static str,
+    detail: String,
+}
+
+/// Result of `plan_download`: what a download would involve, computed without writing.
+#[derive(Serialize)]
+struct DownloadPlan {
+    game_id: String,
+    game_name: String,
+    total_size: Option<u64>,
+    size_source: &'static str,
+    sources: Vec<SourceProbe>,
+    range_support: bool,
+    estimated_speed: Option<f64>,
+    estimated_seconds: Option<u64>,
+    disk: Option<DiskEstimate>,
+    conflicts: Vec<DownloadConflict>,
+    warnings: Vec<String>,
+    ready: bool,
+}
+
+async fn probe_source(client: &HttpClient, url: &str) -> SourceProbe {
+    let client = &client_for_source(client, url);
+    let mut probe = SourceProbe {
+        url: url.to_string(),
+        reachable: false,
+        status: None,
+        size: None,
+        range_support: false,
+        latency_ms: None,
+        historical_speed: None,
+        error: None,
+    };
+
+    // A one-byte ranged GET works on signed URLs that reject HEAD, and the
+    // Content-Range total tells us both the size and that ranges are honoured
+    let started = Instant::now();
+    let request = client.send(client.get(url).header("Range", "bytes=0-0"));
+    let response = match tokio::time::timeout(PROBE_TIMEOUT, request).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            probe.error = Some(err.to_string());
+            return probe;
+        }
+        Err(_) => {
+            probe.error = Some("Timed out".to_string());
+            return probe;
+        }
+    };
+    probe.latency_ms = Some(started.elapsed().as_secs_f64() * 1000.0);
+
+    let status = response.status();
+    probe.status = Some(status.as_u16());
+    if !status.is_success() {
+        probe.error = Some(format!("HTTP error {}", status));
+        return probe;
+    }
+    probe.reachable = true;
+
+    if status.as_u16() == 206 {
+        probe.range_support = true;
+        probe.size = response
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit('/').next())
+            .and_then(|total| total.parse().ok());
+    } else {
+        probe.size = response.content_length();
+    }
+    probe
+}
+
+fn available_space(path: &Path) -> Option<u64> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let mut target = path.to_path_buf();
+    while !target.exists() {
+        target = target.parent()?.to_path_buf();
+    }
+    let target = target.canonicalize().ok()?;
+    disks
+        .list()
+        .iter()
+        .filter(|disk| target.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+/// Shared pieces every download task needs, cloned into spawned tasks.
+#[derive(Clone)]
+struct DownloadContext {
+    http: HttpClient,
+    base_dir: PathBuf,
+    downloads_dir: PathBuf,
+    history: Arc<RwLock<SourceHistory>>,
+}
+
+/// Outcome of one pause/resume/cancel, shared by the single-game and batch APIs.
+#[derive(Serialize)]
+struct JobResult {
+    game_id: String,
+    success: bool,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<String>,
+}
+
+impl JobResult {
+    fn succeeded(game_id: &str, message: &str) -> Self {
+        Self {
+            game_id: game_id.to_string(),
+            success: true,
+            message: message.to_string(),
+            removed: Vec::new(),
+        }
+    }
+
+    fn failed(game_id: &str, message: &str) -> Self {
+        Self {
+            success: false,
+            ..Self::succeeded(game_id, message)
+        }
+    }
+}
+
+fn batch_result(results: Vec<JobResult>) -> PyResult<PyObject> {
+    let value = serde_json::json!({
+        "success": results.iter().all(|r| r.success),
+        "count": results.len(),
+        "results": results
+    });
+    Python::with_gil(|py| value_to_py(py, &value))
+}
+
+/// Selects jobs for `cancel_downloads`; empty lists match everything, except that
+/// completed jobs are only picked when `statuses` names them.
+#[derive(Default, Deserialize)]
+struct DownloadFilter {
+    #[serde(default)]
+    statuses: Vec<DownloadStatus>,
+    #[serde(default)]
+    game_ids: Vec<String>,
+    #[serde(default)]
+    name_contains: Option<String>,
+}
+
+impl DownloadFilter {
+    fn matches(&self, game_id: &str, game_name: &str, status: DownloadStatus) -> bool {
+        let status_matches = if self.statuses.is_empty() {
+            status != DownloadStatus::Completed
+        } else {
+            self.statuses.contains(&status)
+        };
+        status_matches
+            && (self.game_ids.is_empty() || self.game_ids.iter().any(|id| id == game_id))
+            && self
+                .name_contains
+                .as_ref()
+                .map(|needle| game_name.to_lowercase().contains(&needle.to_lowercase()))
+                .unwrap_or(true)
+    }
+}
+
+type DownloadMap = HashMap<String, DownloadHandle>;
+
 struct DownloadHandle {
     state: Arc<RwLock<DownloadState>>,
     task: tokio::task::JoinHandle<()>,
@@ -311,24 +1172,452 @@ struct DownloadHandle {
 
 #[pyclass]
 pub struct DownloadManager {
-    http: Client,
+    http: HttpClient,
     base_dir: PathBuf,
     downloads_dir: PathBuf,
     downloads: Arc<RwLock<HashMap<String, DownloadHandle>>>,
+    history: Arc<RwLock<SourceHistory>>,
 }
 
-impl DownloadManager {
+impl DownloadManager {
+    fn context(&self) -> DownloadContext {
+        DownloadContext {
+            http: self.http.clone(),
+            base_dir: self.base_dir.clone(),
+            downloads_dir: self.downloads_dir.clone(),
+            history: self.history.clone(),
+        }
+    }
+
+    /// Run a download in the background, recording failures on the state and the
+    /// measured source speeds in the shared history once it stops.
+    fn spawn_download(
+        ctx: DownloadContext,
+        state: Arc<RwLock<DownloadState>>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let DownloadContext {
+                http,
+                base_dir,
+                downloads_dir,
+                history,
+            } = ctx;
+            let result =
+                Self::perform_chunked_download(http, state.clone(), base_dir, downloads_dir.clone())
+                    .await;
+
+            let mut guard = state.write().await;
+            if let Err(err) = result {
+                guard.status = DownloadStatus::Failed;
+                guard.message = Some(err.to_string());
+                guard.maybe_emit_event();
+                let _ = guard.save_to_disk(&downloads_dir).await;
+            }
+
+            let mut history = history.write().await;
+            history.record(&guard.sources);
+            let _ = history.save(&downloads_dir).await;
+        })
+    }
+
+    /// Every known job, running or saved on disk, as `(game_id, status, game_name)`.
+    async fn collect_jobs(
+        map: &DownloadMap,
+        downloads_dir: &Path,
+    ) -> Vec<(String, DownloadStatus, String)> {
+        let mut jobs = Vec::new();
+        for (game_id, handle) in map {
+            let state = handle.state.read().await;
+            jobs.push((game_id.clone(), state.status, state.game_name.clone()));
+        }
+
+        if let Ok(mut entries) = fs::read_dir(downloads_dir).await {
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let path = entry.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("json")
+                    || path.file_name().and_then(|name| name.to_str()) == Some(SOURCE_HISTORY_FILE)
+                {
+                    continue;
+                }
+                let Some(game_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                if map.contains_key(game_id) {
+                    continue;
+                }
+                if let Some(state) = DownloadState::load_from_disk(game_id, downloads_dir).await {
+                    jobs.push((game_id.to_string(), state.status, state.game_name));
+                }
+            }
+        }
+
+        jobs.sort_by(|a, b| a.0.cmp(&b.0));
+        jobs
+    }
+
+    async fn pause_locked(map: &mut DownloadMap, game_id: &str, downloads_dir: &Path) -> JobResult {
+        let Some(mut handle) = map.remove(game_id) else {
+            return JobResult::failed(game_id, "Download not found");
+        };
+
+        // Cancel the task and let it unwind so in-flight chunks stop writing
+        handle.task.abort();
+        let _ = (&mut handle.task).await;
+
+        // Keep the state file so the download can be resumed
+        let mut state = handle.state.write().await;
+        state.release_in_flight();
+        state.status = DownloadStatus::Paused;
+        state.message = Some("Paused by user".to_string());
+        state.maybe_emit_event();
+        let _ = state.save_to_disk(downloads_dir).await;
+
+        JobResult::succeeded(game_id, "Download paused")
+    }
+
+    /// Restart a paused, failed or kept-cancelled download. With `retry`, chunk retry
+    /// counts and source failure counts are reset first.
+    async fn resume_locked(
+        map: &mut DownloadMap,
+        game_id: &str,
+        ctx: &DownloadContext,
+        retry: bool,
+    ) -> JobResult {
+        // A failed download keeps its finished handle around; copy its state back.
+        // The handle stays in the map until a new task replaces it, so jobs that
+        // cannot be resumed keep reporting their status.
+        let existing = match map.get(game_id) {
+            Some(handle) if handle.task.is_finished() => Some(handle.state.read().await.clone()),
+            Some(_) => return JobResult::failed(game_id, "Download is already active"),
+            None => DownloadState::load_from_disk(game_id, &ctx.downloads_dir).await,
+        };
+
+        let Some(mut existing_state) = existing else {
+            return JobResult::failed(game_id, "No paused download found");
+        };
+        if !matches!(
+            existing_state.status,
+            DownloadStatus::Paused | DownloadStatus::Failed | DownloadStatus::Cancelled
+        ) {
+            return JobResult::failed(game_id, "Download cannot be resumed in current state");
+        }
+
+        // In-flight work from the previous run is gone; release its slots
+        existing_state.release_in_flight();
+        if retry {
+            existing_state.reset_for_retry();
+        }
+        existing_state.status = DownloadStatus::Pending;
+        existing_state.message = Some("Resuming download".to_string());
+
+        let state = Arc::new(RwLock::new(existing_state));
+        let task = Self::spawn_download(ctx.clone(), state.clone());
+        map.insert(
+            game_id.to_string(),
+            DownloadHandle {
+                state: state.clone(),
+                task,
+            },
+        );
+        state.write().await.maybe_emit_event();
+
+        JobResult::succeeded(game_id, "Download resumed")
+    }
+
+    async fn cancel_locked(
+        map: &mut DownloadMap,
+        game_id: &str,
+        keep_partial: bool,
+        ctx: &DownloadContext,
+    ) -> JobResult {
+        let state = match map.remove(game_id) {
+            Some(handle) => {
+                handle.task.abort();
+                // Wait for the task to unwind so no chunk is still writing
+                let _ = handle.task.await;
+                handle.state
+            }
+            None => match DownloadState::load_from_disk(game_id, &ctx.downloads_dir).await {
+                Some(state) => Arc::new(RwLock::new(state)),
+                None => return JobResult::failed(game_id, "Download not found"),
+            },
+        };
+
+        let mut state = state.write().await;
+        state.release_in_flight();
+
+        let removed = if keep_partial {
+            Vec::new()
+        } else {
+            let game_dir = game_dir_for(&ctx.base_dir, game_id);
+            let mut removed = remove_partial_data(&game_dir, game_id, &state.game_name).await;
+            let state_file = ctx.downloads_dir.join(format!("{}.json", game_id));
+            if fs::remove_file(&state_file).await.is_ok() {
+                removed.push(state_file.to_string_lossy().to_string());
+            }
+            removed
+        };
+
+        state.status = DownloadStatus::Cancelled;
+        state.speed = 0.0;
+        state.eta_seconds = 0;
+        state.message = Some(if keep_partial {
+            "Cancelled by user; partial data kept".to_string()
+        } else {
+            "Cancelled by user".to_string()
+        });
+        if keep_partial {
+            let _ = state.save_to_disk(&ctx.downloads_dir).await;
+        }
+        state.maybe_emit_event();
+
+        let mut result = JobResult::succeeded(game_id, "Download cancelled");
+        result.removed = removed;
+        result
+    }
+
+    /// Work out what downloading `sources` would involve; see `plan_download`.
+    async fn plan(
+        ctx: DownloadContext,
+        downloads: &RwLock<DownloadMap>,
+        game_id: String,
+        game_name: String,
+        sources: Vec<String>,
+        expected_size: Option<u64>,
+        integrity: Result<Option<IntegritySpec>, String>,
+    ) -> DownloadPlan {
+        let DownloadContext {
+            http,
+            base_dir,
+            downloads_dir,
+            history,
+        } = ctx;
+        let mut warnings = Vec::new();
+
+        let mut probes =
+            futures::future::join_all(sources.iter().map(|url| probe_source(&http, url))).await;
+        {
+            let history = history.read().await;
+            for probe in &mut probes {
+                probe.historical_speed = history.get(&probe.url).map(|h| h.avg_speed);
+            }
+        }
+
+        let reachable: Vec<&SourceProbe> = probes.iter().filter(|p| p.reachable).collect();
+        if reachable.is_empty() {
+            warnings.push("No source is reachable".to_string());
+        }
+        let range_support = reachable.iter().any(|p| p.range_support);
+        if !reachable.is_empty() && !range_support {
+            warnings.push("No source supports ranged requests".to_string());
+        }
+
+        // Prefer the caller's size; otherwise take the size most sources agree on
+        let (total_size, size_source) = match expected_size.filter(|size| *size > 0) {
+            Some(size) => (Some(size), "expected"),
+            None => {
+                let mut counts: HashMap<u64, usize> = HashMap::new();
+                for size in reachable.iter().filter_map(|p| p.size) {
+                    *counts.entry(size).or_default() += 1;
+                }
+                match counts.into_iter().max_by_key(|(_, count)| *count) {
+                    Some((size, _)) => (Some(size), "probed"),
+                    None => (None, "unknown"),
+                }
+            }
+        };
+        if let Some(size) = total_size {
+            for probe in reachable.iter().filter(|p| p.size.is_some_and(|s| s != size)) {
+                warnings.push(format!(
+                    "{} reports {} bytes, expected {}",
+                    probe.url,
+                    probe.size.unwrap_or_default(),
+                    size
+                ));
+            }
+        }
+
+        match &integrity {
+            Ok(Some(spec)) => {
+                if let Err(err) = spec.validate(total_size.unwrap_or(0)) {
+                    warnings.push(err.to_string());
+                }
+            }
+            Ok(None) => {}
+            Err(err) => warnings.push(err.clone()),
+        }
+
+        // Estimate with the same connection split a real download would use,
+        // seeded with the speeds previously measured for each host
+        let (estimated_speed, estimated_seconds) = {
+            let urls: Vec<String> = reachable
+                .iter()
+                .filter(|p| p.range_support)
+                .map(|p| p.url.clone())
+                .collect();
+            let mut plan_state = DownloadState::new(
+                game_id.clone(),
+                game_name.clone(),
+                total_size.unwrap_or(0),
+                None,
+                None,
+                urls,
+                String::new(),
+            );
+            let history = history.read().await;
+            for source in &mut plan_state.sources {
+                if let Some(known) = history.get(&source.url) {
+                    source.avg_speed = known.avg_speed;
+                    source.avg_latency_ms = known.avg_latency_ms;
+                    source.samples = MIN_RANKING_SAMPLES;
+                }
+            }
+            let targets = plan_state.connection_targets();
+            let speed: f64 = plan_state
+                .sources
+                .iter()
+                .zip(targets)
+                .filter(|(source, _)| source.is_measured())
+                .map(|(source, connections)| source.avg_speed * connections as f64)
+                .sum();
+            if speed > 0.0 {
+                let seconds = total_size.map(|size| (size as f64 / speed).ceil() as u64);
+                (Some(speed), seconds)
+            } else {
+                (None, None)
+            }
+        };
+
+        let disk = match total_size {
+            Some(size) => {
+                let headroom = (size as f64 * EXTRACTION_HEADROOM).ceil() as u64;
+                let required = size + headroom;
+                let target = base_dir.clone();
+                let available = tokio::task::spawn_blocking(move || available_space(&target))
+                    .await
+                    .ok()
+                    .flatten();
+                Some(DiskEstimate {
+                    download_bytes: size,
+                    extraction_headroom_bytes: headroom,
+                    required_bytes: required,
+                    available_bytes: available,
+                    sufficient: available.map(|free| free >= required),
+                })
+            }
+            None => None,
+        };
+
+        let mut conflicts = Vec::new();
+        let game_dir = game_dir_for(&base_dir, &game_id);
+        let temp_path = temp_path_for(&game_dir, &game_id);
+        let zip_path = game_dir.join(format!("{}.zip", game_name));
+        if has_previous_install(&game_dir, &[&temp_path, &zip_path]).await {
+            conflicts.push(DownloadConflict {
+                kind: "installed",
+                detail: game_dir.to_string_lossy().to_string(),
+            });
+        }
+        let active_state = downloads
+            .read()
+            .await
+            .get(&game_id)
+            .map(|handle| handle.state.clone());
+        if let Some(state) = active_state {
+            conflicts.push(DownloadConflict {
+                kind: "active_download",
+                detail: state.read().await.status.as_str().to_string(),
+            });
+        } else if let Some(saved) = DownloadState::load_from_disk(&game_id, &downloads_dir).await {
+            conflicts.push(DownloadConflict {
+                kind: "saved_download",
+                detail: saved.status.as_str().to_string(),
+            });
+        } else if temp_path.exists() {
+            conflicts.push(DownloadConflict {
+                kind: "partial_data",
+                detail: temp_path.to_string_lossy().to_string(),
+            });
+        }
+
+        let ready = conflicts.is_empty()
+            && range_support
+            && integrity.is_ok()
+            && disk.as_ref().and_then(|d| d.sufficient) != Some(false);
+
+        DownloadPlan {
+            game_id,
+            game_name,
+            total_size,
+            size_source,
+            sources: probes,
+            range_support,
+            estimated_speed,
+            estimated_seconds,
+            disk,
+            conflicts,
+            warnings,
+            ready,
+        }
+    }
+
+    /// Pause every pending or running job.
+    async fn pause_active(map: &mut DownloadMap, downloads_dir: &Path) -> Vec<JobResult> {
+        let mut results = Vec::new();
+        for (game_id, status, _) in Self::collect_jobs(map, downloads_dir).await {
+            if matches!(status, DownloadStatus::Pending | DownloadStatus::Downloading) {
+                results.push(Self::pause_locked(map, &game_id, downloads_dir).await);
+            }
+        }
+        results
+    }
+
+    /// Resume every job in `status`; failed jobs get fresh retry budgets.
+    async fn resume_with_status(
+        map: &mut DownloadMap,
+        status: DownloadStatus,
+        ctx: &DownloadContext,
+    ) -> Vec<JobResult> {
+        let retry = status == DownloadStatus::Failed;
+        let mut results = Vec::new();
+        for (game_id, job_status, _) in Self::collect_jobs(map, &ctx.downloads_dir).await {
+            if job_status == status {
+                results.push(Self::resume_locked(map, &game_id, ctx, retry).await);
+            }
+        }
+        results
+    }
+
+    async fn cancel_matching(
+        map: &mut DownloadMap,
+        filter: &DownloadFilter,
+        keep_partial: bool,
+        ctx: &DownloadContext,
+    ) -> Vec<JobResult> {
+        let mut results = Vec::new();
+        for (game_id, status, game_name) in Self::collect_jobs(map, &ctx.downloads_dir).await {
+            if filter.matches(&game_id, &game_name, status) {
+                results.push(Self::cancel_locked(map, &game_id, keep_partial, ctx).await);
+            }
+        }
+        results
+    }
+
     async fn download_chunk(
-        client: &Client,
+        client: &HttpClient,
         chunk: &mut DownloadChunk,
         source: &DownloadSource,
         temp_path: &Path,
-    ) -> Result<bool> {
+        expected_hash: Option<(HashAlgorithm, String)>,
+        keep_data: bool,
+    ) -> Result<TransferSample> {
         let request = client
             .get(&source.url)
             .header("Range", format!("bytes={}-{}", chunk.start, chunk.end));
 
-        let response = request.send().await?;
+        let started = Instant::now();
+        let response = client.send_streaming(request).await?;
+        let latency = started.elapsed();
 
         if !response.status().is_success() && response.status().as_u16() != 206 {
             return Err(anyhow!("HTTP error {}", response.status()));
@@ -337,6 +1626,7 @@ impl DownloadManager {
         let mut file = tokio::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(temp_path)
             .await?;
 
@@ -346,10 +1636,19 @@ impl DownloadManager {
         chunk.downloaded = 0;
         chunk.status = DownloadStatus::Downloading;
 
-        while let Some(bytes_result) = stream.next().await {
+        let mut hasher = expected_hash.as_ref().map(|(algorithm, _)| algorithm.hasher());
+        let mut data = keep_data.then(|| Vec::with_capacity(chunk.size as usize));
+
+        while let Some(bytes_result) = next_chunk(&mut stream).await {
             let bytes = bytes_result?;
             file.write_all(&bytes).await?;
             chunk.downloaded += bytes.len() as u64;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&bytes);
+            }
+            if let Some(data) = data.as_mut() {
+                data.extend_from_slice(&bytes);
+            }
 
             if chunk.downloaded > chunk.size {
                 return Err(anyhow!("Chunk downloaded more data than expected"));
@@ -357,34 +1656,59 @@ impl DownloadManager {
         }
 
         file.flush().await?;
+        if chunk.downloaded != chunk.size {
+            return Err(anyhow!(
+                "Chunk ended early ({} of {} bytes)",
+                chunk.downloaded,
+                chunk.size
+            ));
+        }
+        if let (Some(hasher), Some((_, expected))) = (hasher, expected_hash.as_ref()) {
+            let actual = hasher.finalize_hex();
+            if !digests_match(&actual, expected) {
+                return Err(anyhow!(
+                    "Chunk {} failed hash check (expected {}, got {})",
+                    chunk.id,
+                    expected,
+                    actual
+                ));
+            }
+        }
         chunk.status = DownloadStatus::Completed;
-        Ok(chunk.downloaded == chunk.size)
+        Ok(TransferSample {
+            bytes: chunk.downloaded,
+            latency,
+            elapsed: started.elapsed(),
+            data,
+        })
     }
 
     async fn perform_chunked_download(
-        client: Client,
+        client: HttpClient,
         state: Arc<RwLock<DownloadState>>,
         base_dir: PathBuf,
         downloads_dir: PathBuf,
     ) -> Result<()> {
         let game_id;
         let game_name;
+        let install_metadata;
         {
             let guard = state.read().await;
             game_id = guard.game_id.clone();
             game_name = guard.game_name.clone();
+            install_metadata = guard.install_metadata.clone();
 
             if guard.sources.is_empty() {
                 return Err(anyhow!("No download sources available"));
             }
         }
 
-        let game_dir = base_dir.join(format!("game_{}", game_id));
+        let game_dir = game_dir_for(&base_dir, &game_id);
         fs::create_dir_all(&game_dir)
             .await
             .with_context(|| format!("Failed to create directory {}", game_dir.display()))?;
 
-        let temp_path = game_dir.join(format!("{}.tmp", game_id));
+        let temp_path = temp_path_for(&game_dir, &game_id);
 
         {
             let mut guard = state.write().await;
@@ -393,8 +1717,11 @@ impl DownloadManager {
             guard.save_to_disk(&downloads_dir).await?;
         }
 
-        // Main download loop with chunking
-        let mut chunk_tasks = Vec::new();
+        // Main download loop with chunking. The JoinSet aborts in-flight chunks when this
+        // task is aborted, so nothing keeps writing after a pause or cancel.
+        let mut chunk_tasks = tokio::task::JoinSet::new();
+        // One client per pinned source, so its connections are reused across chunks
+        let mut source_clients: HashMap<String, HttpClient> = HashMap::new();
         loop {
             let has_pending_chunks = {
                 let mut guard = state.write().await;
@@ -422,34 +1749,64 @@ impl DownloadManager {
                         guard.chunks[chunk_idx].source_url = source_url.clone();
 
                         let chunk = guard.chunks[chunk_idx].clone();
-                        let client_clone = client.clone();
+                        let expected_hash = guard.integrity.as_ref().and_then(|spec| {
+                            spec.chunk_hash(chunk_idx)
+                                .map(|hash| (spec.algorithm, hash.to_string()))
+                        });
+                        let keep_data = guard.needs_file_hash();
+                        let client_clone = source_clients
+                            .entry(source_url.clone())
+                            .or_insert_with(|| client_for_source(&client, &source_url))
+                            .clone();
                         let temp_path_clone = temp_path.clone();
                         let state_clone = state.clone();
                         let downloads_dir_clone = downloads_dir.clone();
 
-                        let task = tokio::spawn(async move {
+                        chunk_tasks.spawn(async move {
                             let mut chunk = chunk;
                             let source = DownloadSource::new(source_url, 0);
-                            let result = Self::download_chunk(
+                            let mut result = Self::download_chunk(
                                 &client_clone,
                                 &mut chunk,
                                 &source,
                                 &temp_path_clone,
+                                expected_hash,
+                                keep_data,
                             )
                             .await;
 
-                            // Update chunk status in state
+                            // Update chunk status and source measurements in state
                             {
                                 let mut guard = state_clone.write().await;
+                                let source = guard
+                                    .sources
+                                    .iter_mut()
+                                    .find(|s| s.url == source.url);
+                                if let Some(source) = source {
+                                    source.active_connections =
+                                        source.active_connections.saturating_sub(1);
+                                    match &result {
+                                        Ok(sample) => source.record_sample(sample),
+                                        Err(_) => source.failures += 1,
+                                    }
+                                }
+                                if result.is_err() {
+                                    chunk.status = DownloadStatus::Failed;
+                                    chunk.retry_count += 1;
+                                    chunk.downloaded = 0;
+                                }
                                 guard.chunks[chunk_idx] = chunk;
+                                if let Some(data) = result.as_mut().ok().and_then(|s| s.data.take()) {
+                                    guard.buffer_for_hash(chunk_idx, data);
+                                }
+                                guard.review_active_source();
                                 guard.update_progress();
                                 let _ = guard.save_to_disk(&downloads_dir_clone).await;
                             }
+                            advance_file_hash(&state_clone, &temp_path_clone).await;
 
-                            result
+                            result.map(|_| ())
                         });
-
-                        chunk_tasks.push(task);
                     }
                 }
 
@@ -468,8 +1825,9 @@ impl DownloadManager {
             }
 
             // Wait for at least one chunk to complete
-            let (finished, _, remaining_tasks) = futures::future::select_all(chunk_tasks).await;
-            chunk_tasks = remaining_tasks;
+            let Some(finished) = chunk_tasks.join_next().await else {
+                continue;
+            };
 
             match finished {
                 Ok(Ok(_)) => {}, // Chunk completed successfully
@@ -481,28 +1839,60 @@ impl DownloadManager {
                     eprintln!("Chunk task failed: {}", e);
                 }
             }
-
-            // Update source connection counts
-            {
-                let mut guard = state.write().await;
-                for source in &mut guard.sources {
-                    if source.active_connections > 0 {
-                        source.active_connections -= 1;
-                    }
-                }
-            }
         }
 
         // Check if all chunks completed
-        let (all_completed, integrity_hash) = {
+        let all_completed = {
             let guard = state.read().await;
-            let completed = guard.chunks.iter().all(|c| c.status == DownloadStatus::Completed);
-            (completed, guard.integrity_hash.clone())
+            guard.chunks.iter().all(|c| c.status == DownloadStatus::Completed)
         };
 
         if !all_completed {
             return Err(anyhow!("Download failed - not all chunks completed"));
         }
+
+        // Verify the whole-file hash; it has been computed while chunks arrived,
+        // so this only folds in whatever the cursor has not reached yet. A hash that
+        // failed along the way is redone from the temp file.
+        if state.read().await.needs_file_hash() {
+            advance_file_hash(&state, &temp_path).await;
+            let retry = state.read().await.hash_error.is_some();
+            if retry {
+                state.write().await.restart_file_hash();
+                advance_file_hash(&state, &temp_path).await;
+            }
+            let mut guard = state.write().await;
+            if let Some(error) = guard.hash_error.take() {
+                return Err(anyhow!("Could not hash the download: {}", error));
+            }
+            if guard.hashed_chunks != guard.chunks.len() {
+                return Err(anyhow!("Could not hash the download: chunks left unhashed"));
+            }
+            let expected = guard
+                .integrity
+                .as_ref()
+                .and_then(|spec| spec.hash.clone())
+                .unwrap_or_default();
+            let actual = guard
+                .file_hasher
+                .take()
+                .map(StreamHasher::finalize_hex)
+                .unwrap_or_default();
+            guard.hashed_chunks = 0;
+            if !digests_match(&actual, &expected) {
+                // Every chunk looked fine on its own, so none of the bytes can be
+                // trusted; a retry starts from scratch instead of re-hashing them
+                guard.discard_chunks();
+                drop(guard);
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(anyhow!(
+                    "Integrity check failed (expected {}, got {})",
+                    expected,
+                    actual
+                ));
+            }
+        }
+
         // All chunks completed, assemble final file
         let final_path = game_dir.join(format!("{}.zip", game_name));
 
@@ -511,20 +1901,26 @@ impl DownloadManager {
             .await
             .with_context(|| format!("Failed to move file to {}", final_path.display()))?;
 
-        // Verify integrity if hash provided
-        if let Some(expected_hash) = integrity_hash {
-            let actual_hash = sha1_file(final_path.to_string_lossy().as_ref())
-                .map_err(|err| anyhow!(err.to_string()))?;
-            if actual_hash.to_lowercase() != expected_hash.to_lowercase() {
-                return Err(anyhow!("Integrity check failed"));
+        // Extract into a staging directory first so a cancel never leaves a
+        // half-extracted archive mixed into the game directory
+        let staging_dir = game_dir.join(STAGING_DIR_NAME);
+        let _ = fs::remove_dir_all(&staging_dir).await;
+        let extracted = extract_zip_inner(&final_path, &staging_dir);
+        let install_dir = game_dir.clone();
+        let library_dir = base_dir.clone();
+        let installed_id = game_id.clone();
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            if extracted.is_ok() {
+                commit_staging(&staging_dir, &install_dir)?;
+            } else if staging_dir.exists() {
+                std::fs::remove_dir_all(&staging_dir)?;
             }
-        }
-
-        // Extract the downloaded archive
-        let _ = extract_zip(
-            final_path.to_string_lossy().as_ref(),
-            game_dir.to_string_lossy().as_ref(),
-        );
+            GameLibrary::record_install(&library_dir, &installed_id, install_metadata.as_ref())?;
+            std::fs::File::create(install_dir.join(INSTALL_MARKER))?;
+            Ok(())
+        })
+        .await
+        .map_err(|err| anyhow!("Install task failed: {}", err))??;
 
         // Mark download as completed
         {
@@ -581,20 +1977,27 @@ impl DownloadManager {
 impl DownloadManager {
     #[new]
     pub fn new(games_dir: String) -> PyResult<Self> {
-        let http = Client::builder()
-            .user_agent(crate::hikari::DEFAULT_USER_AGENT.as_str())
-            .build()
-            .map_err(|err| runtime_error(format!("Failed to create HTTP client: {}", err)))?;
+        let http = HttpClient::new(ClientOptions {
+            user_agent: crate::hikari::DEFAULT_USER_AGENT.clone(),
+            ..ClientOptions::default()
+        })?;
         let base_dir = PathBuf::from(games_dir);
         let downloads_dir = base_dir.join(".downloads");
         Ok(Self {
             http,
             base_dir: base_dir.clone(),
+            history: Arc::new(RwLock::new(SourceHistory::load(&downloads_dir))),
             downloads_dir,
             downloads: Arc::new(RwLock::new(HashMap::new())),
         })
     }
 
+    /// Start a chunked download.
+    ///
+    /// `integrity` is a typed spec such as
+    /// `{"algorithm": "sha256", "hash": "...", "chunk_size": 4194304, "chunk_hashes": [...]}`;
+    /// when omitted, `integrity_hash` is parsed as `"<algorithm>:<hex>"` or a bare digest.
+    #[allow(clippy::too_many_arguments)]
     pub fn start_download<'py>(
         &'py self,
         py: Python<'py>,
@@ -603,26 +2006,47 @@ impl DownloadManager {
         sources: Vec<String>,
         expected_size: Option<u64>,
         integrity_hash: Option<String>,
+        integrity: Option<&PyAny>,
+        metadata: Option<&PyAny>,
     ) -> PyResult<&'py PyAny> {
-        let http = self.http.clone();
-        let base_dir = self.base_dir.clone();
-        let downloads_dir = self.downloads_dir.clone();
         let downloads = self.downloads.clone();
+        let ctx = self.context();
         let size = expected_size.unwrap_or(0);
 
         if sources.is_empty() {
             return Err(runtime_error("No sources provided"));
         }
 
+        let integrity = match integrity {
+            Some(spec) if !spec.is_none() => Some(extract_serde::<IntegritySpec>(spec)?),
+            _ => IntegritySpec::from_legacy(integrity_hash.as_deref().unwrap_or_default())
+                .map_err(|err| runtime_error(err.to_string()))?,
+        };
+        if let Some(spec) = integrity.as_ref() {
+            spec.validate(size)
+                .map_err(|err| runtime_error(err.to_string()))?;
+        }
+
+        let install_metadata = match metadata {
+            Some(value) if !value.is_none() => match extract_value(value)? {
+                fields @ Value::Object(_) => Some(fields),
+                _ => return Err(runtime_error("metadata must be a dict")),
+            },
+            _ => None,
+        };
+
         let task_id = format!("download_{}", uuid::Uuid::new_v4());
-        let state = Arc::new(RwLock::new(DownloadState::new(
+        let mut download = DownloadState::new(
             game_id.clone(),
             game_name.clone(),
             size,
             integrity_hash,
+            integrity,
             sources,
             task_id,
-        )));
+        );
+        download.install_metadata = install_metadata;
+        let state = Arc::new(RwLock::new(download));
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
             {
@@ -631,18 +2055,7 @@ impl DownloadManager {
                     return Err(runtime_error("Download already exists"));
                 }
 
-                let state_clone = state.clone();
-                let task = tokio::spawn(async move {
-                    if let Err(err) =
-                        Self::perform_chunked_download(http, state_clone.clone(), base_dir, downloads_dir.clone()).await
-                    {
-                        let mut guard = state_clone.write().await;
-                        guard.status = DownloadStatus::Failed;
-                        guard.message = Some(err.to_string());
-                        guard.maybe_emit_event();
-                        let _ = guard.save_to_disk(&downloads_dir).await;
-                    }
-                });
+                let task = Self::spawn_download(ctx, state.clone());
 
                 guard.insert(
                     game_id.clone(),
@@ -668,6 +2081,54 @@ impl DownloadManager {
         })
     }
 
+    /// Dry-run a download without writing anything.
+    ///
+    /// Takes the same arguments as `start_download` and returns the probed size and
+    /// range support of every source, an estimated duration based on speeds measured
+    /// in earlier downloads, the disk space needed including extraction headroom, and
+    /// any conflict with an existing install or download job.
+    #[allow(clippy::too_many_arguments)]
+    pub fn plan_download<'py>(
+        &'py self,
+        py: Python<'py>,
+        game_id: String,
+        game_name: String,
+        sources: Vec<String>,
+        expected_size: Option<u64>,
+        integrity_hash: Option<String>,
+        integrity: Option<&PyAny>,
+        metadata: Option<&PyAny>,
+    ) -> PyResult<&'py PyAny> {
+        // Only recorded once a download installs; accepted so a plan can be made from
+        // exactly the arguments `start_download` will get
+        let _ = metadata;
+        let ctx = self.context();
+        let downloads = self.downloads.clone();
+
+        let integrity = match integrity {
+            Some(spec) if !spec.is_none() => {
+                extract_serde::<IntegritySpec>(spec).map(Some).map_err(|err| err.to_string())
+            }
+            _ => IntegritySpec::from_legacy(integrity_hash.as_deref().unwrap_or_default())
+                .map_err(|err| err.to_string()),
+        };
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let plan = Self::plan(
+                ctx,
+                &downloads,
+                game_id,
+                game_name,
+                sources,
+                expected_size,
+                integrity,
+            )
+            .await;
+            let value = serde_json::to_value(plan).map_err(|err| runtime_error(err.to_string()))?;
+            Python::with_gil(|py| value_to_py(py, &value))
+        })
+    }
+
     pub fn get_active_downloads(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
         let downloads = self.downloads.clone();
         let asyncio = py.import("asyncio")?;
@@ -703,39 +2164,30 @@ impl DownloadManager {
         })
     }
 
+    /// Cancel an active or paused download and clean up after it.
+    ///
+    /// By default the temp file, staging data, state file and (if it held no previous
+    /// install) the game directory are removed. With `keep_partial` the downloaded data
+    /// and state file are kept so `resume_download` can pick the download up again.
     pub fn cancel_download<'py>(
         &'py self,
         py: Python<'py>,
         game_id: String,
+        keep_partial: Option<bool>,
     ) -> PyResult<&'py PyAny> {
         let downloads = self.downloads.clone();
-        let downloads_dir = self.downloads_dir.clone();
+        let ctx = self.context();
+        let keep_partial = keep_partial.unwrap_or(false);
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
             let mut guard = downloads.write().await;
-            if let Some(handle) = guard.remove(&game_id) {
-                handle.task.abort();
-                {
-                    let mut state = handle.state.write().await;
-                    state.status = DownloadStatus::Cancelled;
-                    state.message = Some("Cancelled by user".to_string());
-                    state.maybe_emit_event();
-                }
-
-                // Clean up state file
-                let state_file = downloads_dir.join(format!("{}.json", game_id));
-                let _ = tokio::fs::remove_file(state_file).await;
-
-                let result = serde_json::json!({"success": true});
-                Python::with_gil(|py| {
-                    value_to_py(py, &result)
-                })
-            } else {
-                let result = serde_json::json!({"success": false});
-                Python::with_gil(|py| {
-                    value_to_py(py, &result)
-                })
-            }
+            let result = Self::cancel_locked(&mut guard, &game_id, keep_partial, &ctx).await;
+            json_result!({
+                "success": result.success,
+                "message": result.message,
+                "kept_partial": keep_partial,
+                "removed": result.removed
+            })
         })
     }
 
@@ -749,38 +2201,11 @@ impl DownloadManager {
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
             let mut guard = downloads.write().await;
-            if let Some(handle) = guard.get_mut(&game_id) {
-                // Cancel the task
-                handle.task.abort();
-
-                // Update state to paused
-                {
-                    let mut state = handle.state.write().await;
-                    state.status = DownloadStatus::Paused;
-                    state.message = Some("Paused by user".to_string());
-                    state.maybe_emit_event();
-                    let _ = state.save_to_disk(&downloads_dir).await;
-                }
-
-                // Remove from active downloads but keep state file for resume
-                guard.remove(&game_id);
-
-                let result = serde_json::json!({
-                    "success": true,
-                    "message": "Download paused"
-                });
-                Python::with_gil(|py| {
-                    value_to_py(py, &result)
-                })
-            } else {
-                let result = serde_json::json!({
-                    "success": false,
-                    "message": "Download not found"
-                });
-                Python::with_gil(|py| {
-                    value_to_py(py, &result)
-                })
-            }
+            let result = Self::pause_locked(&mut guard, &game_id, &downloads_dir).await;
+            json_result!({
+                "success": result.success,
+                "message": result.message
+            })
         })
     }
 
@@ -789,81 +2214,74 @@ impl DownloadManager {
         py: Python<'py>,
         game_id: String,
     ) -> PyResult<&'py PyAny> {
-        let http = self.http.clone();
-        let base_dir = self.base_dir.clone();
+        let downloads = self.downloads.clone();
+        let ctx = self.context();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut guard = downloads.write().await;
+            let result = Self::resume_locked(&mut guard, &game_id, &ctx, false).await;
+            json_result!({
+                "success": result.success,
+                "message": result.message
+            })
+        })
+    }
+
+    /// Pause every pending or running download.
+    pub fn pause_all<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let downloads = self.downloads.clone();
         let downloads_dir = self.downloads_dir.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut guard = downloads.write().await;
+            batch_result(Self::pause_active(&mut guard, &downloads_dir).await)
+        })
+    }
+
+    /// Resume every paused download.
+    pub fn resume_all<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
         let downloads = self.downloads.clone();
+        let ctx = self.context();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            // Check if download is already active
-            {
-                let guard = downloads.read().await;
-                if guard.contains_key(&game_id) {
-                    return json_result!({
-                        "success": false,
-                        "message": "Download is already active"
-                    });
-                }
-            }
+            let mut guard = downloads.write().await;
+            let results = Self::resume_with_status(&mut guard, DownloadStatus::Paused, &ctx).await;
+            batch_result(results)
+        })
+    }
 
-            // Load existing state
-            if let Some(mut existing_state) = DownloadState::load_from_disk(&game_id, &downloads_dir).await {
-                if existing_state.status == DownloadStatus::Paused ||
-                   existing_state.status == DownloadStatus::Failed {
-
-                    existing_state.status = DownloadStatus::Pending;
-                    existing_state.message = Some("Resuming download".to_string());
-
-                    let state = Arc::new(RwLock::new(existing_state));
-                    let state_clone = state.clone();
-
-                    let task = tokio::spawn(async move {
-                        if let Err(err) = Self::perform_chunked_download(
-                            http,
-                            state_clone.clone(),
-                            base_dir,
-                            downloads_dir.clone(),
-                        ).await {
-                            let mut guard = state_clone.write().await;
-                            guard.status = DownloadStatus::Failed;
-                            guard.message = Some(err.to_string());
-                            guard.maybe_emit_event();
-                            let _ = guard.save_to_disk(&downloads_dir).await;
-                        }
-                    });
-
-                    {
-                        let mut guard = downloads.write().await;
-                        guard.insert(
-                            game_id,
-                            DownloadHandle {
-                                state: state.clone(),
-                                task,
-                            },
-                        );
-                    }
+    /// Restart every failed download with fresh retry budgets for its chunks and sources.
+    pub fn retry_failed<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let downloads = self.downloads.clone();
+        let ctx = self.context();
 
-                    {
-                        let mut state_guard = state.write().await;
-                        state_guard.maybe_emit_event();
-                    }
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut guard = downloads.write().await;
+            let results = Self::resume_with_status(&mut guard, DownloadStatus::Failed, &ctx).await;
+            batch_result(results)
+        })
+    }
 
-                    json_result!({
-                        "success": true,
-                        "message": "Download resumed"
-                    })
-                } else {
-                    json_result!({
-                        "success": false,
-                        "message": "Download cannot be resumed in current state"
-                    })
-                }
-            } else {
-                json_result!({
-                    "success": false,
-                    "message": "No paused download found"
-                })
-            }
+    /// Cancel every download matching `filter`, e.g. `{"statuses": ["paused", "failed"]}`
+    /// or `{"game_ids": [...]}`. An empty or missing filter cancels everything that has
+    /// not completed.
+    pub fn cancel_downloads<'py>(
+        &'py self,
+        py: Python<'py>,
+        filter: Option<&PyAny>,
+        keep_partial: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let downloads = self.downloads.clone();
+        let ctx = self.context();
+        let keep_partial = keep_partial.unwrap_or(false);
+        let filter: DownloadFilter = match filter {
+            Some(value) if !value.is_none() => extract_serde(value)?,
+            _ => DownloadFilter::default(),
+        };
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let mut guard = downloads.write().await;
+            batch_result(Self::cancel_matching(&mut guard, &filter, keep_partial, &ctx).await)
         })
     }
 
@@ -891,6 +2309,7 @@ impl DownloadManager {
     }
 
     /// Switch primary source by matching substring in URL; moves preferred source to highest priority.
+    /// The switch is recorded in the snapshot's `source_switches` with reason `manual`.
     pub fn switch_download_source<'py>(
         &'py self,
         py: Python<'py>,
@@ -902,19 +2321,15 @@ impl DownloadManager {
             let mut guard = downloads.write().await;
             if let Some(handle) = guard.get_mut(&game_id) {
                 let mut state = handle.state.write().await;
-                let mut found = false;
-                for src in &mut state.sources {
-                    if src.url.contains(&preferred_substring) {
-                        src.priority = 0;
-                        found = true;
-                    } else if src.priority == 0 {
-                        src.priority = 1;
-                    }
-                }
-                // Re-sort
-                state.sources.sort_by_key(|s| (s.priority, s.active_connections, s.failures));
-                state.maybe_emit_event();
-                if found {
+                let found = state
+                    .sources
+                    .iter()
+                    .position(|src| src.url.contains(&preferred_substring));
+                if let Some(index) = found {
+                    let detail = format!("Matched \"{}\"", preferred_substring);
+                    state.promote_source(index, SwitchReason::Manual, detail);
+                    state.sources.sort_by_key(|s| (s.priority, s.active_connections, s.failures));
+                    state.maybe_emit_event();
                     json_result!({"success": true})
                 } else {
                     json_result!({"success": false, "message": "Preferred source not found"})
@@ -926,3 +2341,616 @@ impl DownloadManager {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn state_with_sources(urls: &[&str]) -> DownloadState {
+        DownloadState::new(
+            "game".to_string(),
+            "Game".to_string(),
+            64 * 1024 * 1024,
+            None,
+            None,
+            urls.iter().map(|url| url.to_string()).collect(),
+            "task".to_string(),
+        )
+    }
+
+    /// `bytes` transferred in one second.
+    fn sample(bytes: u64) -> TransferSample {
+        TransferSample {
+            bytes,
+            latency: Duration::from_millis(20),
+            elapsed: Duration::from_secs(1),
+            data: None,
+        }
+    }
+
+    fn measure(state: &mut DownloadState, index: usize, speeds: &[u64]) {
+        for speed in speeds {
+            state.sources[index].record_sample(&sample(*speed));
+        }
+    }
+
+    #[test]
+    fn source_speed_is_a_moving_average() {
+        let mut source = DownloadSource::new("https://a.example/game.zip".to_string(), 0);
+        source.record_sample(&sample(1000));
+        assert_eq!(source.avg_speed, 1000.0);
+        assert!(!source.is_measured());
+
+        source.record_sample(&sample(2000));
+        assert!((source.avg_speed - 1300.0).abs() < 1e-9);
+        assert_eq!(source.last_speed, 2000.0);
+        assert_eq!(source.bytes_downloaded, 3000);
+        assert!(source.is_measured());
+    }
+
+    #[test]
+    fn clearly_faster_mirror_is_promoted() {
+        let mut state = state_with_sources(&["https://a.example/g.zip", "https://b.example/g.zip"]);
+        measure(&mut state, 0, &[100_000, 100_000]);
+        // 10% faster is within the margin
+        measure(&mut state, 1, &[110_000, 110_000]);
+        state.review_active_source();
+        assert_eq!(state.active_source.as_deref(), Some("https://a.example/g.zip"));
+        assert!(state.source_switches.is_empty());
+
+        measure(&mut state, 1, &[400_000, 400_000]);
+        state.review_active_source();
+        assert_eq!(state.active_source.as_deref(), Some("https://b.example/g.zip"));
+        assert_eq!((state.sources[0].priority, state.sources[1].priority), (1, 0));
+        let switch = &state.source_switches[0];
+        assert_eq!(switch.from.as_deref(), Some("https://a.example/g.zip"));
+        assert!(matches!(switch.reason, SwitchReason::Faster));
+    }
+
+    #[test]
+    fn failed_source_hands_over_to_a_mirror() {
+        let mut state = state_with_sources(&["https://a.example/g.zip", "https://b.example/g.zip"]);
+        state.sources[0].failures = MAX_SOURCE_FAILURES;
+        state.review_active_source();
+
+        assert_eq!(state.active_source.as_deref(), Some("https://b.example/g.zip"));
+        assert!(matches!(state.source_switches[0].reason, SwitchReason::SourceFailed));
+        // A failed source gets no connections at all
+        assert_eq!(state.connection_targets()[0], 0);
+    }
+
+    #[test]
+    fn connections_follow_measured_speed() {
+        let mut state = state_with_sources(&[
+            "https://a.example/g.zip",
+            "https://b.example/g.zip",
+            "https://c.example/g.zip",
+        ]);
+        measure(&mut state, 0, &[100_000, 100_000]);
+        measure(&mut state, 1, &[300_000, 300_000]);
+
+        let targets = state.connection_targets();
+        // The unmeasured mirror keeps one probe connection
+        assert_eq!(targets[2], 1);
+        assert!(targets[1] > targets[0], "{:?}", targets);
+        assert_eq!(targets.iter().sum::<u32>(), state.max_concurrent_chunks);
+    }
+
+    fn hashed_state(total_size: u64) -> DownloadState {
+        let integrity = IntegritySpec {
+            algorithm: HashAlgorithm::Sha256,
+            hash: Some("unused".to_string()),
+            chunk_size: None,
+            chunk_hashes: Vec::new(),
+        };
+        let mut state = DownloadState::new(
+            "game".to_string(),
+            "Game".to_string(),
+            total_size,
+            None,
+            Some(integrity),
+            vec!["https://a.example/g.zip".to_string()],
+            "task".to_string(),
+        );
+        for chunk in &mut state.chunks {
+            chunk.status = DownloadStatus::Completed;
+        }
+        state
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+
+    #[tokio::test]
+    async fn file_hash_mixes_buffered_chunks_and_reads_from_disk() {
+        let data: Vec<u8> = (0..2_500_000u32).map(|i| (i % 251) as u8).collect();
+        let temp_path = std::env::temp_dir().join(format!("vn_core_hash_{}", uuid::Uuid::new_v4()));
+        std::fs::write(&temp_path, &data).unwrap();
+        let mut state = hashed_state(data.len() as u64);
+        let (start, end) = (state.chunks[1].start as usize, state.chunks[1].end as usize);
+        state.buffer_for_hash(1, data[start..=end].to_vec());
+        let state = RwLock::new(state);
+
+        advance_file_hash(&state, &temp_path).await;
+        let mut guard = state.write().await;
+        assert_eq!(guard.hashed_chunks, 3);
+        assert!(guard.hash_buffers.is_empty());
+        assert_eq!(guard.hash_buffer_bytes, 0);
+        assert_eq!(guard.file_hasher.take().unwrap().finalize_hex(), sha256_hex(&data));
+        std::fs::remove_file(temp_path).ok();
+    }
+
+    #[tokio::test]
+    async fn hash_failures_are_recorded_and_redone() {
+        let data = vec![7u8; 1_500_000];
+        let temp_path = std::env::temp_dir().join(format!("vn_core_hash_{}", uuid::Uuid::new_v4()));
+        let state = RwLock::new(hashed_state(data.len() as u64));
+
+        // Nothing buffered and no temp file yet
+        advance_file_hash(&state, &temp_path).await;
+        {
+            let guard = state.read().await;
+            assert!(guard.hash_error.as_deref().unwrap().contains("Failed to open"));
+            assert!(guard.message.as_deref().unwrap().contains("redone"));
+            assert!(!guard.hashing);
+        }
+
+        std::fs::write(&temp_path, &data).unwrap();
+        state.write().await.restart_file_hash();
+        advance_file_hash(&state, &temp_path).await;
+        let mut guard = state.write().await;
+        assert!(guard.hash_error.is_none());
+        assert_eq!(guard.file_hasher.take().unwrap().finalize_hex(), sha256_hex(&data));
+        std::fs::remove_file(temp_path).ok();
+    }
+
+    #[test]
+    fn hash_buffers_are_capped_by_bytes() {
+        let mut state = hashed_state(4 * 1024 * 1024);
+        state.buffer_for_hash(2, vec![0u8; MAX_HASH_BUFFER_BYTES - 16]);
+        state.buffer_for_hash(3, vec![0u8; 32]);
+        assert!(state.hash_buffers.contains_key(&2));
+        assert!(!state.hash_buffers.contains_key(&3));
+        assert_eq!(state.hash_buffer_bytes, MAX_HASH_BUFFER_BYTES - 16);
+
+        state.release_in_flight();
+        assert_eq!(state.hash_buffer_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn cancelling_keeps_installs_that_predate_the_marker() {
+        let root = std::env::temp_dir().join(format!("vn_core_partial_{}", uuid::Uuid::new_v4()));
+        let (fresh, legacy) = (root.join("fresh"), root.join("legacy"));
+        for dir in [&fresh, &legacy] {
+            std::fs::create_dir_all(dir.join(STAGING_DIR_NAME)).unwrap();
+            std::fs::write(temp_path_for(dir, "game"), b"partial").unwrap();
+            std::fs::write(dir.join("Game.zip"), b"zip").unwrap();
+        }
+        std::fs::write(legacy.join("Game.exe"), b"installed").unwrap();
+
+        let removed = remove_partial_data(&fresh, "game", "Game").await;
+        assert_eq!(removed.len(), 4);
+        assert!(!fresh.exists());
+
+        remove_partial_data(&legacy, "game", "Game").await;
+        assert!(legacy.join("Game.zip").exists());
+        assert!(legacy.join("Game.exe").exists());
+        assert!(!temp_path_for(&legacy, "game").exists());
+        assert!(!legacy.join(STAGING_DIR_NAME).exists());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    /// A zip holding one stored (uncompressed) file, so its size is predictable.
+    fn stored_zip(payload_len: usize) -> Vec<u8> {
+        use std::io::Write;
+        let payload: Vec<u8> = (0..payload_len).map(|i| (i % 251) as u8).collect();
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("Game.exe", options).unwrap();
+        writer.write_all(&payload).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Answer ranged GETs for `path` from `data`; the range starting at `corrupt_at` is
+    /// served with a flipped byte the first time it is asked for.
+    fn serve_ranges(server: &MockServer, path: &str, data: Vec<u8>, corrupt_at: Option<u64>) {
+        let corrupted = std::sync::atomic::AtomicBool::new(false);
+        server.on("GET", path, move |request| {
+            let range = request.header("range").unwrap_or_default();
+            let (start, end) = range.trim_start_matches("bytes=").split_once('-').unwrap();
+            let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+            let mut body = data[start..=end].to_vec();
+            if corrupt_at == Some(start as u64)
+                && !corrupted.swap(true, std::sync::atomic::Ordering::SeqCst)
+            {
+                body[0] ^= 0xff;
+            }
+            MockResponse::status(206)
+                .header("content-range", &format!("bytes {}-{}/{}", start, end, data.len()))
+                .body(body)
+        });
+    }
+
+    fn download_dirs() -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("vn_core_download_{}", uuid::Uuid::new_v4()));
+        (root.join("games"), root.join("downloads"))
+    }
+
+    #[tokio::test]
+    async fn corrupt_chunks_are_fetched_again_on_their_own() {
+        const CHUNK: u64 = 16 * 1024;
+        let data = stored_zip(40_000);
+        let integrity = IntegritySpec {
+            algorithm: HashAlgorithm::Sha256,
+            hash: Some(sha256_hex(&data)),
+            chunk_size: Some(CHUNK),
+            chunk_hashes: data.chunks(CHUNK as usize).map(sha256_hex).collect(),
+        };
+        let server = MockServer::start().await;
+        serve_ranges(&server, "/g.zip", data.clone(), Some(CHUNK));
+        let state = DownloadState::new(
+            "game".to_string(),
+            "Game".to_string(),
+            data.len() as u64,
+            None,
+            Some(integrity),
+            vec![format!("{}/g.zip", server.url())],
+            "task".to_string(),
+        );
+        assert_eq!(state.chunks.len(), 3);
+        let state = Arc::new(RwLock::new(state));
+        let (base_dir, downloads_dir) = download_dirs();
+
+        DownloadManager::perform_chunked_download(
+            HttpClient::new(ClientOptions::default()).unwrap(),
+            state.clone(),
+            base_dir.clone(),
+            downloads_dir,
+        )
+        .await
+        .unwrap();
+
+        let ranges: Vec<String> = server
+            .requests_to("/g.zip")
+            .iter()
+            .filter_map(|request| request.header("range").map(str::to_string))
+            .collect();
+        assert_eq!(ranges.len(), 4, "{:?}", ranges);
+        let corrupt_range = format!("bytes={}-{}", CHUNK, 2 * CHUNK - 1);
+        assert_eq!(ranges.iter().filter(|range| **range == corrupt_range).count(), 2);
+        assert_eq!(state.read().await.status, DownloadStatus::Completed);
+        assert!(game_dir_for(&base_dir, "game").join("Game.exe").exists());
+        std::fs::remove_dir_all(base_dir.parent().unwrap()).ok();
+    }
+
+    #[tokio::test]
+    async fn a_file_hash_mismatch_discards_the_download() {
+        let data = stored_zip(40_000);
+        let integrity = IntegritySpec {
+            algorithm: HashAlgorithm::Sha256,
+            hash: Some(sha256_hex(b"something else")),
+            chunk_size: None,
+            chunk_hashes: Vec::new(),
+        };
+        let server = MockServer::start().await;
+        serve_ranges(&server, "/g.zip", data.clone(), None);
+        let state = DownloadState::new(
+            "game".to_string(),
+            "Game".to_string(),
+            data.len() as u64,
+            None,
+            Some(integrity),
+            vec![format!("{}/g.zip", server.url())],
+            "task".to_string(),
+        );
+        let state = Arc::new(RwLock::new(state));
+        let (base_dir, downloads_dir) = download_dirs();
+
+        let error = DownloadManager::perform_chunked_download(
+            HttpClient::new(ClientOptions::default()).unwrap(),
+            state.clone(),
+            base_dir.clone(),
+            downloads_dir,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.to_string().starts_with("Integrity check failed"), "{}", error);
+        let guard = state.read().await;
+        assert!(guard
+            .chunks
+            .iter()
+            .all(|chunk| chunk.status == DownloadStatus::Pending && chunk.downloaded == 0));
+        assert_eq!(guard.downloaded_size, 0);
+        let game_dir = game_dir_for(&base_dir, "game");
+        assert!(!temp_path_for(&game_dir, "game").exists());
+        std::fs::remove_dir_all(base_dir.parent().unwrap()).ok();
+    }
+
+    fn batch_context() -> DownloadContext {
+        let (base_dir, downloads_dir) = download_dirs();
+        DownloadContext {
+            http: HttpClient::new(ClientOptions::default()).unwrap(),
+            base_dir,
+            downloads_dir,
+            history: Arc::new(RwLock::new(SourceHistory::default())),
+        }
+    }
+
+    fn job_state(game_id: &str, status: DownloadStatus, source: &str) -> DownloadState {
+        let mut state = DownloadState::new(
+            game_id.to_string(),
+            game_id.to_string(),
+            1024,
+            None,
+            None,
+            vec![source.to_string()],
+            "task".to_string(),
+        );
+        state.status = status;
+        state
+    }
+
+    async fn save_job(ctx: &DownloadContext, game_id: &str, status: DownloadStatus, source: &str) {
+        let state = job_state(game_id, status, source);
+        state.save_to_disk(&ctx.downloads_dir).await.unwrap();
+    }
+
+    /// A job held in memory whose task never finishes on its own.
+    fn running_job(map: &mut DownloadMap, game_id: &str, status: DownloadStatus, source: &str) {
+        map.insert(
+            game_id.to_string(),
+            DownloadHandle {
+                state: Arc::new(RwLock::new(job_state(game_id, status, source))),
+                task: tokio::spawn(std::future::pending()),
+            },
+        );
+    }
+
+    fn outcomes(results: &[JobResult]) -> Vec<(&str, bool, &str)> {
+        results
+            .iter()
+            .map(|result| (result.game_id.as_str(), result.success, result.message.as_str()))
+            .collect()
+    }
+
+    async fn saved_status(ctx: &DownloadContext, game_id: &str) -> Option<DownloadStatus> {
+        DownloadState::load_from_disk(game_id, &ctx.downloads_dir)
+            .await
+            .map(|state| state.status)
+    }
+
+    fn stop_tasks(map: &DownloadMap, ctx: &DownloadContext) {
+        for handle in map.values() {
+            handle.task.abort();
+        }
+        std::fs::remove_dir_all(ctx.base_dir.parent().unwrap()).ok();
+    }
+
+    #[tokio::test]
+    async fn pausing_everything_only_touches_active_jobs() {
+        let server = MockServer::start().await;
+        let (ctx, source) = (batch_context(), format!("{}/g.zip", server.url()));
+        let mut map = DownloadMap::new();
+        running_job(&mut map, "a", DownloadStatus::Downloading, &source);
+        running_job(&mut map, "b", DownloadStatus::Pending, &source);
+        save_job(&ctx, "c", DownloadStatus::Paused, &source).await;
+        save_job(&ctx, "d", DownloadStatus::Failed, &source).await;
+
+        let results = DownloadManager::pause_active(&mut map, &ctx.downloads_dir).await;
+
+        assert_eq!(
+            outcomes(&results),
+            vec![("a", true, "Download paused"), ("b", true, "Download paused")]
+        );
+        assert!(map.is_empty());
+        assert_eq!(saved_status(&ctx, "a").await, Some(DownloadStatus::Paused));
+        assert_eq!(saved_status(&ctx, "d").await, Some(DownloadStatus::Failed));
+        stop_tasks(&map, &ctx);
+    }
+
+    #[tokio::test]
+    async fn resuming_and_retrying_pick_jobs_by_status() {
+        let server = MockServer::start().await;
+        server.respond("GET", "/g.zip", MockResponse::status(404));
+        let (ctx, source) = (batch_context(), format!("{}/g.zip", server.url()));
+        let mut map = DownloadMap::new();
+        running_job(&mut map, "a", DownloadStatus::Paused, &source);
+        save_job(&ctx, "b", DownloadStatus::Paused, &source).await;
+        let mut failed = job_state("c", DownloadStatus::Failed, &source);
+        failed.chunks[0].status = DownloadStatus::Failed;
+        failed.chunks[0].retry_count = 3;
+        failed.save_to_disk(&ctx.downloads_dir).await.unwrap();
+
+        let resumed =
+            DownloadManager::resume_with_status(&mut map, DownloadStatus::Paused, &ctx).await;
+        assert_eq!(
+            outcomes(&resumed),
+            vec![
+                ("a", false, "Download is already active"),
+                ("b", true, "Download resumed"),
+            ]
+        );
+        assert!(!map.contains_key("c"));
+
+        let retried =
+            DownloadManager::resume_with_status(&mut map, DownloadStatus::Failed, &ctx).await;
+        assert_eq!(outcomes(&retried), vec![("c", true, "Download resumed")]);
+        let retried = map["c"].state.read().await.clone();
+        assert_eq!(retried.chunks[0].retry_count, 0);
+        stop_tasks(&map, &ctx);
+    }
+
+    #[tokio::test]
+    async fn cancelling_by_status_leaves_other_jobs_alone() {
+        let server = MockServer::start().await;
+        let (ctx, source) = (batch_context(), format!("{}/g.zip", server.url()));
+        let mut map = DownloadMap::new();
+        save_job(&ctx, "a", DownloadStatus::Paused, &source).await;
+        save_job(&ctx, "b", DownloadStatus::Failed, &source).await;
+        running_job(&mut map, "c", DownloadStatus::Completed, &source);
+
+        let paused_only = DownloadFilter {
+            statuses: vec![DownloadStatus::Paused],
+            ..DownloadFilter::default()
+        };
+        let results = DownloadManager::cancel_matching(&mut map, &paused_only, false, &ctx).await;
+        assert_eq!(outcomes(&results), vec![("a", true, "Download cancelled")]);
+        assert_eq!(saved_status(&ctx, "a").await, None);
+        assert_eq!(saved_status(&ctx, "b").await, Some(DownloadStatus::Failed));
+
+        // Without statuses everything but the completed job is cancelled
+        let results =
+            DownloadManager::cancel_matching(&mut map, &DownloadFilter::default(), true, &ctx)
+                .await;
+        assert_eq!(outcomes(&results), vec![("b", true, "Download cancelled")]);
+        assert_eq!(saved_status(&ctx, "b").await, Some(DownloadStatus::Cancelled));
+        assert!(map.contains_key("c"));
+        stop_tasks(&map, &ctx);
+    }
+
+    async fn plan_with(
+        ctx: &DownloadContext,
+        downloads: &RwLock<DownloadMap>,
+        sources: Vec<String>,
+    ) -> DownloadPlan {
+        DownloadManager::plan(
+            ctx.clone(),
+            downloads,
+            "game".to_string(),
+            "Game".to_string(),
+            sources,
+            None,
+            Ok(None),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn plans_probe_sources_and_agree_on_a_size() {
+        const SIZE: u64 = 1_000_000;
+        let server = MockServer::start().await;
+        for path in ["/a.zip", "/b.zip"] {
+            server.respond(
+                "GET",
+                path,
+                MockResponse::status(206)
+                    .header("content-range", &format!("bytes 0-0/{}", SIZE))
+                    .body(vec![0u8]),
+            );
+        }
+        server.respond("GET", "/whole.zip", MockResponse::status(200).body(vec![0u8; 5000]));
+        let ctx = batch_context();
+        ctx.history.write().await.hosts.insert(
+            "127.0.0.1".to_string(),
+            HostHistory {
+                avg_speed: 1024.0,
+                avg_latency_ms: 10.0,
+                samples: MIN_RANKING_SAMPLES,
+                updated_at: 0,
+            },
+        );
+        let downloads = RwLock::new(DownloadMap::new());
+        let sources: Vec<String> = ["/a.zip", "/b.zip", "/whole.zip"]
+            .iter()
+            .map(|path| format!("{}{}", server.url(), path))
+            .collect();
+
+        let plan = plan_with(&ctx, &downloads, sources.clone()).await;
+
+        let ranged: Vec<(bool, Option<u64>)> =
+            plan.sources.iter().map(|probe| (probe.range_support, probe.size)).collect();
+        assert_eq!(ranged, vec![(true, Some(SIZE)), (true, Some(SIZE)), (false, Some(5000))]);
+        assert!(plan.range_support);
+        assert_eq!((plan.total_size, plan.size_source), (Some(SIZE), "probed"));
+        assert_eq!(
+            plan.warnings,
+            vec![format!("{} reports 5000 bytes, expected {}", sources[2], SIZE)]
+        );
+
+        // Only the ranged sources take connections, all at the remembered host speed
+        let speed = 1024.0 * state_with_sources(&[]).max_concurrent_chunks as f64;
+        assert_eq!(plan.estimated_speed, Some(speed));
+        assert_eq!(plan.estimated_seconds, Some((SIZE as f64 / speed).ceil() as u64));
+        let disk = plan.disk.unwrap();
+        assert_eq!(disk.download_bytes, SIZE);
+        assert_eq!(disk.extraction_headroom_bytes, 1_500_000);
+        assert_eq!(disk.required_bytes, 2_500_000);
+        assert!(plan.conflicts.is_empty());
+        std::fs::remove_dir_all(ctx.base_dir.parent().unwrap()).ok();
+    }
+
+    #[tokio::test]
+    async fn plans_report_installs_and_leftover_jobs() {
+        let server = MockServer::start().await;
+        server.respond(
+            "GET",
+            "/g.zip",
+            MockResponse::status(206).header("content-range", "bytes 0-0/2048"),
+        );
+        let ctx = batch_context();
+        let downloads = RwLock::new(DownloadMap::new());
+        let sources = vec![format!("{}/g.zip", server.url())];
+        let game_dir = game_dir_for(&ctx.base_dir, "game");
+        let kinds = |plan: DownloadPlan| -> Vec<&'static str> {
+            plan.conflicts.iter().map(|conflict| conflict.kind).collect()
+        };
+
+        // Leftovers of an earlier attempt are not an install
+        std::fs::create_dir_all(&game_dir).unwrap();
+        std::fs::write(temp_path_for(&game_dir, "game"), b"partial").unwrap();
+        std::fs::write(game_dir.join("Game.zip"), b"zip").unwrap();
+        let plan = plan_with(&ctx, &downloads, sources.clone()).await;
+        assert_eq!(kinds(plan), vec!["partial_data"]);
+
+        // A game installed before the marker existed still counts
+        std::fs::write(game_dir.join("Game.exe"), b"installed").unwrap();
+        save_job(&ctx, "game", DownloadStatus::Paused, &sources[0]).await;
+        let plan = plan_with(&ctx, &downloads, sources.clone()).await;
+        assert_eq!(kinds(plan), vec!["installed", "saved_download"]);
+
+        let status = DownloadStatus::Downloading;
+        running_job(&mut *downloads.write().await, "game", status, &sources[0]);
+        let plan = plan_with(&ctx, &downloads, sources).await;
+        assert_eq!(kinds(plan), vec!["installed", "active_download"]);
+        stop_tasks(&*downloads.read().await, &ctx);
+    }
+
+    #[test]
+    fn pinned_sources_keep_their_host_name() {
+        let url = "https://dl.example.com/builds/b.zip?signature=abc";
+        let pinned = pin_source(url, "203.0.113.7".parse().unwrap()).unwrap();
+        assert_eq!(pinned, format!("{}#via=203.0.113.7", url));
+
+        let (host, addr) = source_pin(&pinned).unwrap();
+        assert_eq!(host, "dl.example.com");
+        assert_eq!(addr, "203.0.113.7:443".parse().unwrap());
+        assert_eq!(SourceHistory::host_of(&pinned).as_deref(), Some("203.0.113.7"));
+        assert!(source_pin(url).is_none());
+    }
+
+    #[tokio::test]
+    async fn pinned_sources_are_fetched_from_their_server() {
+        let server = MockServer::start().await;
+        server.respond(
+            "GET",
+            "/builds/b.zip",
+            MockResponse::status(206).header("content-range", "bytes 0-0/2048"),
+        );
+        let port = server.url().rsplit(':').next().unwrap().to_string();
+        let url = format!("http://dl.invalid:{}/builds/b.zip", port);
+        let pinned = pin_source(&url, "127.0.0.1".parse().unwrap()).unwrap();
+        let client = HttpClient::new(ClientOptions::default()).unwrap();
+
+        let probe = probe_source(&client, &pinned).await;
+        assert!(probe.reachable, "{:?}", probe.error);
+        assert_eq!(probe.size, Some(2048));
+        let sent = server.requests_to("/builds/b.zip");
+        assert_eq!(sent[0].header("host"), Some(format!("dl.invalid:{}", port).as_str()));
+    }
+}
@@ -1,4 +1,5 @@
-use crate::downloads::DownloadManager;
+use crate::downloads::{DownloadManager, INSTALL_MARKER};
+use crate::hikari::HikariApp;
 use crate::util::{runtime_error, value_to_py, extract_value, extract_serde};
 use crate::json_result;
 use anyhow::{anyhow, Context, Result};
@@ -19,6 +20,15 @@ use tar::Builder;
 use tokio::task;
 use walkdir::WalkDir;
 
+/// Build information recorded in a game's metadata at install time.
+pub(crate) struct InstalledBuild {
+    pub game_id: String,
+    pub build_id: String,
+    pub version: String,
+    pub platform: Option<String>,
+    pub size: Option<u64>,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum SortKey {
     Name,
@@ -230,8 +240,12 @@ impl GameLibrary {
     }
 
     fn read_directory_games(&self) -> Vec<String> {
+        GameLibrary::read_directory_games_in(&self.games_dir)
+    }
+
+    fn read_directory_games_in(base: &Path) -> Vec<String> {
         let mut games = Vec::new();
-        if let Ok(entries) = fs::read_dir(&self.games_dir) {
+        if let Ok(entries) = fs::read_dir(base) {
             for entry in entries.flatten() {
                 let path = entry.path();
                 if path.is_dir() {
@@ -246,6 +260,69 @@ impl GameLibrary {
         games
     }
 
+    /// Merge `fields` (store build id, version, ...) into a freshly installed game's
+    /// metadata and stamp the install time.
+    pub(crate) fn record_install(base: &Path, game_id: &str, fields: Option<&Value>) -> Result<()> {
+        let mut metadata = GameLibrary::read_metadata_from(base, game_id)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| json!({}));
+        let object = GameLibrary::metadata_as_object(&mut metadata);
+        if let Some(Value::Object(fields)) = fields {
+            for (key, value) in fields {
+                object.insert(key.clone(), value.clone());
+            }
+        }
+        object.insert("installed_at".to_string(), json!(GameLibrary::current_time_secs()));
+        GameLibrary::write_metadata_to(base, game_id, &metadata)
+    }
+
+    /// Store `keys` (a list of `{code, label}`) as the game's `serial_keys`, keeping the
+    /// rest of its metadata.
+    pub(crate) fn record_serial_keys(base: &Path, game_id: &str, keys: &Value) -> Result<()> {
+        let mut metadata =
+            GameLibrary::read_metadata_from(base, game_id)?.unwrap_or_else(|| json!({}));
+        let object = GameLibrary::metadata_as_object(&mut metadata);
+        object.insert("serial_keys".to_string(), keys.clone());
+        object.insert(
+            "serial_keys_updated_at".to_string(),
+            json!(GameLibrary::current_time_secs()),
+        );
+        GameLibrary::write_metadata_to(base, game_id, &metadata)
+    }
+
+    pub(crate) fn games_dir(&self) -> &Path {
+        &self.games_dir
+    }
+
+    /// Installed games under `base` whose metadata records the store build they were
+    /// installed from. Walks the whole library, so call it off the async threads.
+    pub(crate) fn installed_builds(base: &Path) -> Vec<InstalledBuild> {
+        GameLibrary::read_directory_games_in(base)
+            .into_iter()
+            .filter(|game_id| base.join(format!("game_{}", game_id)).join(INSTALL_MARKER).exists())
+            .filter_map(|game_id| {
+                let metadata = GameLibrary::read_metadata_from(base, &game_id).ok().flatten()?;
+                let text = |key: &str| {
+                    metadata
+                        .get(key)
+                        .and_then(|value| match value {
+                            Value::String(s) => Some(s.clone()),
+                            Value::Number(n) => Some(n.to_string()),
+                            _ => None,
+                        })
+                };
+                Some(InstalledBuild {
+                    build_id: text("build_id")?,
+                    version: text("version").unwrap_or_default(),
+                    platform: text("build_platform"),
+                    size: metadata.get("build_size").and_then(Value::as_u64),
+                    game_id,
+                })
+            })
+            .collect()
+    }
+
     fn gather_tags_from_metadata(metadata: &Value) -> HashSet<String> {
         metadata
             .get("user_tags")
@@ -258,6 +335,19 @@ impl GameLibrary {
             .unwrap_or_default()
     }
 
+    /// Read a list of games given as plain dicts or typed `HikariApp` objects.
+    fn game_entries(games: &PyAny) -> PyResult<Vec<Value>> {
+        let mut entries = Vec::new();
+        for item in games.iter()? {
+            let item = item?;
+            match item.extract::<PyRef<HikariApp>>() {
+                Ok(app) => entries.push(app.to_game_value()),
+                Err(_) => entries.push(extract_value(item)?),
+            }
+        }
+        Ok(entries)
+    }
+
     pub fn format_size(bytes: u64) -> String {
         if bytes == 0 {
             return "0B".to_string();
@@ -483,7 +573,7 @@ impl GameLibrary {
     }
 
     pub fn update_library_cache(&self, games_data: &PyAny) -> PyResult<()> {
-        let entries: Vec<Value> = extract_serde(games_data)?;
+        let entries: Vec<Value> = GameLibrary::game_entries(games_data)?;
         let mut map = HashMap::new();
         for entry in entries {
             if let Some(id) = entry.get("id").and_then(|v| v.as_str()) {
@@ -528,6 +618,19 @@ impl GameLibrary {
             .map_err(|err| runtime_error(err.to_string()))
     }
 
+    /// Serial numbers kept for the game, as `{code, label}` dicts; empty when none.
+    pub fn get_serial_keys(&self, py: Python<'_>, game_id: String) -> PyResult<PyObject> {
+        let metadata = self
+            .read_metadata(&game_id)
+            .map_err(|err| runtime_error(err.to_string()))?
+            .unwrap_or_else(|| json!({}));
+        let keys = match metadata.get("serial_keys") {
+            Some(keys @ Value::Array(_)) => keys.clone(),
+            _ => json!([]),
+        };
+        value_to_py(py, &keys)
+    }
+
     pub fn get_game_tags(&self, py: Python<'_>, game_id: String) -> PyResult<PyObject> {
         let metadata = self
             .read_metadata(&game_id)
@@ -539,14 +642,15 @@ impl GameLibrary {
         Ok(pyset.into())
     }
 
-    /// Enrich a list of games with installation and download status information.
+    /// Enrich a list of games (dicts or `HikariApp`s) with installation and download
+    /// status information.
     pub fn enrich_games(
         &self,
         py: Python<'_>,
         games: &PyAny,
         download_manager: &DownloadManager,
     ) -> PyResult<PyObject> {
-        let entries: Vec<Value> = extract_serde(games)?;
+        let entries: Vec<Value> = GameLibrary::game_entries(games)?;
         let downloads = download_manager.snapshot_all();
         let mut out = Vec::with_capacity(entries.len());
 
@@ -571,6 +675,16 @@ impl GameLibrary {
             let mut total_size = 0u64;
             let mut message: Option<String> = None;
 
+            // Which Hikari account profile the installed copy was downloaded with
+            let installed_by = if installed {
+                self.read_metadata(&gid)
+                    .ok()
+                    .flatten()
+                    .and_then(|meta| meta.get("account").and_then(Value::as_str).map(str::to_string))
+            } else {
+                None
+            };
+
             if let Some(snap) = snapshot {
                 downloading = snap.status.is_active();
                 progress = snap.progress;
@@ -595,6 +709,9 @@ impl GameLibrary {
                         obj.remove("downloadMessage");
                     }
                 }
+                if let Some(account) = installed_by {
+                    obj.insert("installedBy".into(), json!(account));
+                }
             }
 
             out.push(entry);
@@ -723,7 +840,7 @@ impl GameLibrary {
         games: &PyAny,
         filters: &PyAny,
     ) -> PyResult<PyObject> {
-        let games_list: Vec<Value> = extract_serde(games)?;
+        let games_list: Vec<Value> = GameLibrary::game_entries(games)?;
         let filters_value: Value = extract_value(filters)?;
 
         let filtered = games_list
@@ -745,7 +862,7 @@ impl GameLibrary {
         sort_by: &SortBy,
         reverse: Option<bool>,
     ) -> PyResult<PyObject> {
-        let mut games_list: Vec<Value> = extract_serde(games)?;
+        let mut games_list: Vec<Value> = GameLibrary::game_entries(games)?;
         let reverse = reverse.unwrap_or(false);
         let key = sort_by.key;
 
@@ -856,7 +973,7 @@ impl GameLibrary {
 
             let game_dir = games_dir.join(format!("game_{}", game_id));
 
-            let _restore = task::spawn_blocking(move || -> Result<_> {
+            task::spawn_blocking(move || -> Result<_> {
                 if game_dir.exists() {
                     fs::remove_dir_all(&game_dir)
                         .with_context(|| format!("Failed to remove {}", game_dir.display()))?;
@@ -1024,10 +1141,8 @@ impl GameLibrary {
         for game_id in self.read_directory_games() {
             let dir = self.game_dir(&game_id);
             let marker = dir.join(".download_progress");
-            if marker.exists() {
-                if fs::remove_file(&marker).is_ok() {
-                    removed += 1;
-                }
+            if marker.exists() && fs::remove_file(&marker).is_ok() {
+                removed += 1;
             }
         }
         Ok(removed)
@@ -1143,10 +1258,42 @@ impl GameLibrary {
                 .map(|arr| {
                     arr.iter()
                         .filter_map(|item| item.as_str())
+                        .map(str::to_lowercase)
                         .collect::<HashSet<_>>()
                 })
                 .unwrap_or_default();
-            if !languages.contains(&language) {
+            if !languages.contains(&language.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if filters.get("games_only").and_then(|v| v.as_bool()) == Some(true)
+            && game.get("is_game").and_then(|v| v.as_bool()) == Some(false)
+        {
+            return false;
+        }
+
+        if let Some(category) = filters.get("category") {
+            let wanted = match category {
+                Value::Number(n) => n.to_string(),
+                Value::String(s) => s.to_lowercase(),
+                _ => String::new(),
+            };
+            let listed = game
+                .get("categories")
+                .and_then(|v| v.as_array())
+                .map(|arr| arr.iter().any(|id| match id {
+                    Value::String(s) => s.to_lowercase() == wanted,
+                    Value::Number(n) => n.to_string() == wanted,
+                    _ => false,
+                }))
+                .unwrap_or(false);
+            let named = game
+                .get("category_name")
+                .and_then(|v| v.as_str())
+                .map(|name| name.to_lowercase() == wanted)
+                .unwrap_or(false);
+            if !wanted.is_empty() && !listed && !named {
                 return false;
             }
         }
@@ -1,131 +1,1680 @@
-use crate::util::{runtime_error, value_to_py};
+use crate::downloads::pin_source;
+use crate::game_library::{GameLibrary, InstalledBuild};
+use crate::http::{ClientOptions, HttpClient, HttpError, NetworkError};
+use crate::util::{runtime_error, value_to_py, Fields};
 use crate::json_result;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use once_cell::sync::Lazy;
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
 use reqwest::header::{ACCEPT, CONTENT_TYPE};
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use futures::future::join_all;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::timeout;
 use uuid::Uuid;
 
 pub(crate) static DEFAULT_USER_AGENT: Lazy<String> =
     Lazy::new(|| format!("VisualNovelManager/{} (Rust)", env!("CARGO_PKG_VERSION")));
 
+create_exception!(
+    vn_core,
+    HikariSessionExpired,
+    PyRuntimeError,
+    "The Hikari session expired and could not be renewed; the user has to log in again."
+);
+
+/// Profile used until the user creates or switches to another account.
+const DEFAULT_PROFILE: &str = "default";
+
+/// Refresh the token this many seconds before it actually expires.
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 60;
+
+/// Session, CDN list and catalog cache, stored in the plugin data dir.
+const CACHE_FILE_NAME: &str = "hikari_cache.json";
+/// Cached data older than this is reported as stale (but still served).
+const LIBRARY_STALE_AFTER_SECS: i64 = 6 * 60 * 60;
+const CDN_STALE_AFTER_SECS: i64 = 24 * 60 * 60;
+
+/// Bytes fetched per server when measuring throughput.
+const CDN_BENCHMARK_BYTES: u64 = 1024 * 1024;
+const CDN_BENCHMARK_TIMEOUT_SECS: f64 = 8.0;
+
+/// Login details kept in memory only, so an expired session can be renewed silently.
+#[derive(Clone)]
+struct Credentials {
+    email: String,
+    password: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("email", &self.email)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Live state of the active account profile plus the stashed state of the others.
 #[derive(Default, Clone, Debug)]
 struct HikariState {
     token: Option<String>,
+    refresh_token: Option<String>,
+    /// Unix timestamp after which `token` is no longer accepted, when known.
+    token_expires_at: Option<i64>,
+    credentials: Option<Credentials>,
     cdn_servers: Vec<HashMap<String, Value>>,
+    cdn_fetched_at: Option<i64>,
+    cdn_benchmarks: Vec<CdnBenchmark>,
     selected_cdn: Option<String>,
+    /// Most recent signed download URL, reused as the benchmark sample.
+    last_signed_url: Option<String>,
     cached_library: Option<Value>,
+    library_fetched_at: Option<i64>,
+    cached_apps: Vec<HikariApp>,
+    /// Serve cached data only and refuse every network call.
+    offline: bool,
+    active_profile: String,
+    /// Inactive profiles, swapped into the fields above by `switch_profile`.
+    profiles: HashMap<String, ProfileData>,
+    profile_credentials: HashMap<String, Credentials>,
+}
+
+/// Per-account session and cache. Credentials are never written to disk.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct ProfileData {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    token_expires_at: Option<i64>,
+    #[serde(default)]
+    selected_cdn: Option<String>,
+    #[serde(default)]
+    cdn_servers: Vec<HashMap<String, Value>>,
+    #[serde(default)]
+    cdn_fetched_at: Option<i64>,
+    #[serde(default)]
+    cdn_benchmarks: Vec<CdnBenchmark>,
+    #[serde(default)]
+    library: Option<Value>,
+    #[serde(default)]
+    library_fetched_at: Option<i64>,
+}
+
+/// On-disk form of `HikariState`. The active profile sits at the top level, which
+/// keeps single-account cache files from before profiles existed readable.
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedState {
+    #[serde(flatten)]
+    active: ProfileData,
+    #[serde(default = "default_profile_name")]
+    active_profile: String,
+    #[serde(default)]
+    profiles: HashMap<String, ProfileData>,
+    #[serde(default)]
+    offline: bool,
+    #[serde(default)]
+    saved_at: i64,
+}
+
+impl PersistedState {
+    fn load(path: &Path) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+}
+
+fn default_profile_name() -> String {
+    DEFAULT_PROFILE.to_string()
+}
+
+fn now_secs() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Age and staleness of a cached item, for the UI.
+fn cache_age(fetched_at: Option<i64>, stale_after: i64) -> Value {
+    let age = fetched_at.map(|at| (now_secs() - at).max(0));
+    json!({
+        "fetchedAt": fetched_at,
+        "ageSecs": age,
+        "stale": age.map(|age| age > stale_after).unwrap_or(true),
+    })
+}
+
+impl HikariState {
+    fn from_persisted(saved: PersistedState) -> Self {
+        let mut state = Self {
+            offline: saved.offline,
+            active_profile: saved.active_profile,
+            profiles: saved.profiles,
+            ..Self::default()
+        };
+        state.load_profile(saved.active);
+        state
+    }
+
+    fn to_persisted(&self) -> PersistedState {
+        PersistedState {
+            active: self.profile_data(),
+            active_profile: self.active_profile.clone(),
+            profiles: self.profiles.clone(),
+            offline: self.offline,
+            saved_at: now_secs(),
+        }
+    }
+
+    fn profile_data(&self) -> ProfileData {
+        ProfileData {
+            token: self.token.clone(),
+            refresh_token: self.refresh_token.clone(),
+            token_expires_at: self.token_expires_at,
+            selected_cdn: self.selected_cdn.clone(),
+            cdn_servers: self.cdn_servers.clone(),
+            cdn_fetched_at: self.cdn_fetched_at,
+            cdn_benchmarks: self.cdn_benchmarks.clone(),
+            library: self.cached_library.clone(),
+            library_fetched_at: self.library_fetched_at,
+        }
+    }
+
+    fn load_profile(&mut self, data: ProfileData) {
+        self.token = data.token;
+        self.refresh_token = data.refresh_token;
+        self.token_expires_at = data.token_expires_at;
+        self.selected_cdn = data.selected_cdn;
+        self.cdn_servers = data.cdn_servers;
+        self.cdn_fetched_at = data.cdn_fetched_at;
+        self.cdn_benchmarks = data.cdn_benchmarks;
+        self.cached_apps = data
+            .library
+            .as_ref()
+            .map(HikariApp::parse_catalog)
+            .unwrap_or_default();
+        self.cached_library = data.library;
+        self.library_fetched_at = data.library_fetched_at;
+        self.last_signed_url = None;
+    }
+
+    /// Stash the active profile and make `name` active, creating it if needed.
+    fn switch_profile(&mut self, name: &str) {
+        if self.active_profile == name {
+            return;
+        }
+        let current = std::mem::replace(&mut self.active_profile, name.to_string());
+        self.profiles.insert(current.clone(), self.profile_data());
+        if let Some(credentials) = self.credentials.take() {
+            self.profile_credentials.insert(current, credentials);
+        }
+        let next = self.profiles.remove(name).unwrap_or_default();
+        self.load_profile(next);
+        self.credentials = self.profile_credentials.remove(name);
+    }
+
+    fn profile_summaries(&self) -> Vec<Value> {
+        let mut summaries = vec![json!({
+            "name": self.active_profile,
+            "active": true,
+            "isLoggedIn": self.token.is_some(),
+            "tokenExpiresAt": self.token_expires_at,
+            "selectedCdn": self.selected_cdn,
+            "libraryCount": self.cached_apps.len(),
+        })];
+        let mut names = self.profiles.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let data = &self.profiles[name];
+            summaries.push(json!({
+                "name": name,
+                "active": false,
+                "isLoggedIn": data.token.is_some(),
+                "tokenExpiresAt": data.token_expires_at,
+                "selectedCdn": data.selected_cdn,
+                "libraryCount": data
+                    .library
+                    .as_ref()
+                    .and_then(catalog_items)
+                    .map(Vec::len)
+                    .unwrap_or(0),
+            }));
+        }
+        summaries
+    }
+
+    fn set_session(&mut self, login: &LoginResponse) {
+        self.token = Some(login.access_token.clone());
+        if login.refresh_token.is_some() {
+            self.refresh_token = login.refresh_token.clone();
+        }
+        self.token_expires_at = login.expiry();
+    }
+
+    fn clear_session(&mut self) {
+        self.token = None;
+        self.refresh_token = None;
+        self.token_expires_at = None;
+    }
+
+    /// CDN hosts to route downloads through: the selected server, then benchmarked
+    /// servers fastest first, then the rest in list order. Unreachable servers are skipped.
+    fn ranked_cdn_hosts(&self) -> Vec<String> {
+        let unreachable: HashSet<&str> = self
+            .cdn_benchmarks
+            .iter()
+            .filter(|bench| bench.latency_ms.is_none())
+            .map(|bench| bench.ip.as_str())
+            .collect();
+        let mut hosts: Vec<String> = Vec::new();
+        let candidates = self
+            .selected_cdn
+            .iter()
+            .cloned()
+            .chain(self.cdn_benchmarks.iter().map(|bench| bench.ip.clone()))
+            .chain(self.cdn_servers.iter().filter_map(cdn_host));
+        for host in candidates {
+            if !unreachable.contains(host.as_str()) && !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+        hosts
+    }
+
+    fn token_expiring(&self) -> bool {
+        self.token_expires_at
+            .map(|expires| now_secs() + TOKEN_REFRESH_MARGIN_SECS >= expires)
+            .unwrap_or(false)
+    }
 }
 
 #[pyclass]
 pub struct HikariClient {
-    http: Client,
+    http: HttpClient,
     api_base: String,
     state: Arc<RwLock<HikariState>>,
+    refresh_lock: Arc<Mutex<()>>,
+    cache_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LoginResponse {
+    access_token: String,
+    #[serde(default)]
+    user: Value,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Value,
+    #[serde(default)]
+    expires_at: Value,
+}
+
+impl LoginResponse {
+    /// Expiry from `expires_at`, `expires_in` or the token's own `exp` claim, in that order.
+    fn expiry(&self) -> Option<i64> {
+        let expires_at = match &self.expires_at {
+            Value::Number(n) => n.as_i64(),
+            Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+                .map(|dt| dt.timestamp())
+                .ok()
+                .or_else(|| s.parse().ok()),
+            _ => None,
+        };
+        let expires_in = match &self.expires_in {
+            Value::Number(n) => n.as_i64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        };
+        expires_at
+            .or_else(|| expires_in.map(|secs| now_secs() + secs))
+            .or_else(|| jwt_expiry(&self.access_token))
+    }
+}
+
+/// The `exp` claim of a JWT, if the token is one.
+fn jwt_expiry(token: &str) -> Option<i64> {
+    let payload = token.split('.').nth(1)?;
+    let bytes = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: Value = serde_json::from_slice(&bytes).ok()?;
+    claims.get("exp")?.as_i64()
+}
+
+/// Latency and throughput measured against one CDN server.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CdnBenchmark {
+    ip: String,
+    #[serde(default)]
+    latency_ms: Option<f64>,
+    /// Bytes per second of a short ranged download; `None` without a sample URL.
+    #[serde(default)]
+    throughput: Option<f64>,
+    #[serde(default)]
+    error: Option<String>,
+    measured_at: i64,
+}
+
+impl CdnBenchmark {
+    /// Fastest first: measured throughput, then connect latency; unreachable servers last.
+    fn rank(&self, other: &Self) -> Ordering {
+        fn desc(a: Option<f64>, b: Option<f64>) -> Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+        }
+        desc(self.throughput, other.throughput).then_with(|| {
+            desc(
+                self.latency_ms.map(|ms| -ms),
+                other.latency_ms.map(|ms| -ms),
+            )
+        })
+    }
+}
+
+fn cdn_host(entry: &HashMap<String, Value>) -> Option<String> {
+    entry
+        .get("ip")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+}
+
+/// Signed download URLs from a `builds/sign` response (`result` as a list or `{urls}`).
+fn signed_urls_from(value: &Value) -> Vec<String> {
+    let result = value.get("result").unwrap_or(value);
+    let urls = match result {
+        Value::Array(items) => items,
+        _ => match result.get("urls").and_then(Value::as_array) {
+            Some(items) => items,
+            None => return Vec::new(),
+        },
+    };
+    urls.iter()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+/// `url` served by the CDN `host` instead of its own host. CDN servers listed by IP are
+/// pinned rather than written into the URL, so HTTPS still sees the original name.
+fn route_via(url: &str, host: &str) -> Option<String> {
+    let mut parsed = reqwest::Url::parse(url).ok()?;
+    if parsed.host_str() == Some(host) {
+        return Some(url.to_string());
+    }
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return pin_source(url, ip);
+    }
+    parsed.set_host(Some(host)).ok()?;
+    Some(parsed.to_string())
+}
+
+/// Download sources in priority order: each signed URL routed through each CDN host in
+/// rank order, then the signed URLs as the API returned them.
+fn route_sources(signed: &[String], hosts: &[String]) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    let routed = hosts
+        .iter()
+        .flat_map(|host| signed.iter().filter_map(move |url| route_via(url, host)));
+    for url in routed.chain(signed.iter().cloned()) {
+        if !sources.contains(&url) {
+            sources.push(url);
+        }
+    }
+    sources
+}
+
+/// Update info for an installed game, or `None` when it is on the newest build.
+fn update_for(installed: &InstalledBuild, apps: &[HikariApp]) -> Option<Value> {
+    let app = apps.iter().find(|app| app.id == installed.game_id).or_else(|| {
+        apps.iter()
+            .find(|app| app.builds.iter().any(|build| build.id == installed.build_id))
+    })?;
+    let platform = installed.platform.as_deref().unwrap_or(DEFAULT_PLATFORM);
+    let latest = app.latest_build_for(platform)?;
+    if latest.id == installed.build_id {
+        return None;
+    }
+
+    let current = app.builds.iter().find(|build| build.id == installed.build_id);
+    let newer = match current {
+        Some(current) => latest.compare_age(current) == Ordering::Greater,
+        None => version_key(&latest.version) > version_key(&installed.version),
+    };
+    if !newer {
+        return None;
+    }
+
+    // Changelogs of every build between the installed one and the latest, oldest first
+    let mut skipped = app
+        .builds
+        .iter()
+        .filter(|build| build.supports_platform(platform))
+        .filter(|build| match current {
+            Some(current) => build.compare_age(current) == Ordering::Greater,
+            None => version_key(&build.version) > version_key(&installed.version),
+        })
+        .collect::<Vec<_>>();
+    skipped.sort_by(|a, b| a.compare_age(b));
+    let changelog = skipped
+        .iter()
+        .filter_map(|build| {
+            build.changelog.as_ref().map(|notes| {
+                if build.version.is_empty() {
+                    notes.clone()
+                } else {
+                    format!("{}\n{}", build.version, notes)
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let installed_size = installed.size.or(current.map(|build| build.size));
+    Some(json!({
+        "game_id": installed.game_id,
+        "name": app.name,
+        "installed_build_id": installed.build_id,
+        "installed_version": installed.version,
+        "latest_build_id": latest.id,
+        "latest_version": latest.version,
+        "download_size": latest.size,
+        "size_delta": installed_size.map(|size| latest.size as i64 - size as i64),
+        "changelog": (!changelog.is_empty()).then(|| changelog.join("\n\n")),
+        "released_at": latest.released_at,
+    }))
+}
+
+/// The build a download resolves to, with the app it belongs to.
+struct DownloadTarget {
+    app_id: String,
+    app_name: String,
+    build: HikariBuild,
+}
+
+impl DownloadTarget {
+    /// Find `id` as a build (of an app or DLC) or, failing that, as an app whose
+    /// latest build for `platform` is used.
+    fn resolve(apps: &[HikariApp], id: &str, platform: &str) -> Option<Self> {
+        let target = |app: &HikariApp, build: &HikariBuild| Self {
+            app_id: app.id.clone(),
+            app_name: app.name.clone(),
+            build: build.clone(),
+        };
+        for app in apps {
+            let mut builds = app
+                .builds
+                .iter()
+                .chain(app.dlcs.iter().flat_map(|dlc| dlc.builds.iter()));
+            if let Some(build) = builds.find(|build| build.id == id) {
+                return Some(target(app, build));
+            }
+        }
+        apps.iter()
+            .filter(|app| app.id == id)
+            .find_map(|app| app.latest_build_for(platform).map(|build| target(app, build)))
+    }
+}
+
+/// Client and URL that reach `sample` through the CDN server `host`. IP addresses are
+/// pinned via DNS override so TLS still sees the original host name.
+fn pinned_request(
+    http: &HttpClient,
+    sample: &reqwest::Url,
+    host: &str,
+) -> Option<(HttpClient, reqwest::Url)> {
+    let mut url = sample.clone();
+    match host.parse::<IpAddr>() {
+        Ok(ip) => {
+            let port = sample.port_or_known_default()?;
+            let client = http.pinned(sample.host_str()?, SocketAddr::new(ip, port)).ok()?;
+            Some((client, url))
+        }
+        Err(_) => {
+            url.set_host(Some(host)).ok()?;
+            Some((http.clone(), url))
+        }
+    }
+}
+
+async fn measure_throughput(
+    client: &HttpClient,
+    url: reqwest::Url,
+    limit: Duration,
+) -> Result<f64, String> {
+    let started = Instant::now();
+    let request = client
+        .get(url)
+        .header(reqwest::header::RANGE, format!("bytes=0-{}", CDN_BENCHMARK_BYTES - 1))
+        .timeout(limit);
+    let mut resp = client
+        .send_streaming(request)
+        .await
+        .map_err(|err| err.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("HTTP {}", resp.status()));
+    }
+
+    let mut received = 0u64;
+    while received < CDN_BENCHMARK_BYTES {
+        match resp.chunk().await.map_err(|err| err.to_string())? {
+            Some(chunk) => received += chunk.len() as u64,
+            None => break,
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    if received == 0 || elapsed <= 0.0 {
+        return Err("No data received".to_string());
+    }
+    Ok(received as f64 / elapsed)
+}
+
+/// Latency is the time until the server answers a HEAD request for the sample (or its
+/// root without one), whatever the status; throughput comes from a short ranged GET.
+async fn benchmark_server(
+    http: HttpClient,
+    host: String,
+    sample: Option<reqwest::Url>,
+    limit: Duration,
+) -> CdnBenchmark {
+    let mut result = CdnBenchmark {
+        ip: host.clone(),
+        latency_ms: None,
+        throughput: None,
+        error: None,
+        measured_at: now_secs(),
+    };
+
+    let target = match &sample {
+        Some(sample) => Some(sample.clone()),
+        None => reqwest::Url::parse(&format!("http://{}/", host)).ok(),
+    };
+    let Some((client, url)) = target.and_then(|target| pinned_request(&http, &target, &host))
+    else {
+        result.error = Some("Cannot route requests through this server".to_string());
+        return result;
+    };
+
+    let started = Instant::now();
+    let probe = client.client().head(url.clone()).timeout(limit);
+    match timeout(limit, client.send(probe)).await {
+        Ok(Ok(_)) => result.latency_ms = Some(started.elapsed().as_secs_f64() * 1000.0),
+        Ok(Err(err)) => {
+            result.error = Some(format!("Connect failed: {}", err));
+            return result;
+        }
+        Err(_) => {
+            result.error = Some("Connect timed out".to_string());
+            return result;
+        }
+    }
+
+    if sample.is_some() {
+        match measure_throughput(&client, url, limit).await {
+            Ok(speed) => result.throughput = Some(speed),
+            Err(err) => result.error = Some(format!("Download test failed: {}", err)),
+        }
+    }
+    result
+}
+
+/// Failure of a Hikari API call. Kept free of Python types so the session logic runs
+/// without an interpreter; pymethods convert it with `?`.
+#[derive(Debug, Clone, PartialEq)]
+enum HikariError {
+    /// The session could not be renewed; the user has to log in again.
+    SessionExpired,
+    /// No response at all: timeout, refused connection, proxy failure.
+    Network(String),
+    /// The API turned down the credentials or refresh token it was given.
+    Rejected(String),
+    Failed(String),
+}
+
+type ApiResult<T> = std::result::Result<T, HikariError>;
+
+fn failed(message: impl Into<String>) -> HikariError {
+    HikariError::Failed(message.into())
+}
+
+/// Statuses with which the auth endpoints turn down a login or refresh token.
+fn refused(status: StatusCode) -> bool {
+    matches!(status, StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED)
+}
+
+fn network(what: &str, err: HttpError) -> HikariError {
+    HikariError::Network(format!("{}: {}", what, err))
+}
+
+impl fmt::Display for HikariError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HikariError::SessionExpired => f.write_str("Hikari session expired, please log in again"),
+            HikariError::Network(message)
+            | HikariError::Rejected(message)
+            | HikariError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<HikariError> for PyErr {
+    fn from(err: HikariError) -> Self {
+        match err {
+            HikariError::SessionExpired => HikariSessionExpired::new_err(err.to_string()),
+            HikariError::Network(message) => NetworkError::new_err(message),
+            HikariError::Rejected(message) | HikariError::Failed(message) => {
+                runtime_error(message)
+            }
+        }
+    }
+}
+
+/// Everything an API call needs, cloned into the futures handed to Python.
+#[derive(Clone)]
+struct HikariSession {
+    http: HttpClient,
+    api_base: String,
+    state: Arc<RwLock<HikariState>>,
+    refresh_lock: Arc<Mutex<()>>,
+    cache_path: Option<PathBuf>,
+}
+
+impl HikariSession {
+    async fn ensure_online(&self) -> ApiResult<()> {
+        if self.state.read().await.offline {
+            return Err(failed(
+                "Hikari offline mode is enabled; network access is disabled",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Write the current state to the cache file, if the client has one.
+    async fn persist(&self) -> ApiResult<()> {
+        let Some(path) = self.cache_path.as_ref() else {
+            return Ok(());
+        };
+        let payload = {
+            let guard = self.state.read().await;
+            serde_json::to_vec(&guard.to_persisted())
+                .map_err(|err| failed(format!("Failed to encode Hikari cache: {}", err)))?
+        };
+
+        let tmp = path.with_extension("json.tmp");
+        let write = async {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&tmp, &payload).await?;
+            // The file holds the access token; keep it private to the user
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                tokio::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600)).await?;
+            }
+            tokio::fs::rename(&tmp, path).await
+        };
+        write
+            .await
+            .map_err(|err| failed(format!("Failed to save Hikari cache: {}", err)))
+    }
+
+    async fn login(&self, email: &str, password: &str) -> ApiResult<LoginResponse> {
+        self.ensure_online().await?;
+        let payload = json!({
+            "email": email,
+            "password": password,
+        });
+
+        let request = self
+            .http
+            .post(format!("{}auth/login", self.api_base))
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .json(&payload);
+        let resp = self
+            .http
+            .send(request)
+            .await
+            .map_err(|err| network("Login request failed", err))?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let message = resp
+                .text()
+                .await
+                .unwrap_or_else(|_| "Authentication failed".to_string());
+            let message = format!("Hikari login failed: {}", message);
+            return Err(if refused(status) {
+                HikariError::Rejected(message)
+            } else {
+                failed(message)
+            });
+        }
+
+        let parsed: LoginResponse = resp
+            .json()
+            .await
+            .map_err(|err| failed(format!("Invalid login response: {}", err)))?;
+
+        {
+            let mut guard = self.state.write().await;
+            guard.set_session(&parsed);
+            guard.credentials = Some(Credentials {
+                email: email.to_string(),
+                password: password.to_string(),
+            });
+        }
+        let _ = self.persist().await;
+        Ok(parsed)
+    }
+
+    /// Trade the refresh token for a new access token. `Ok(None)` means the API has
+    /// no refresh endpoint or rejected the refresh token; server errors are returned.
+    async fn refresh_with_token(&self, refresh_token: &str) -> ApiResult<Option<LoginResponse>> {
+        let request = self
+            .http
+            .post(format!("{}auth/refresh", self.api_base))
+            .header(ACCEPT, "application/json")
+            .json(&json!({ "refresh_token": refresh_token }));
+        let resp = self
+            .http
+            .send(request)
+            .await
+            .map_err(|err| network("Token refresh failed", err))?;
+
+        let status = resp.status();
+        if refused(status) || status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(failed(format!("Token refresh failed: HTTP {}", status)));
+        }
+        Ok(resp.json::<LoginResponse>().await.ok())
+    }
+
+    /// Renew the session after `stale` stopped working: refresh token first, then the
+    /// stored credentials. Concurrent callers share one renewal.
+    async fn renew(&self, stale: &str) -> ApiResult<String> {
+        let _renewing = self.refresh_lock.lock().await;
+
+        let (current, refresh_token, credentials) = {
+            let guard = self.state.read().await;
+            if let Some(token) = guard.token.as_deref() {
+                if token != stale && !guard.token_expiring() {
+                    return Ok(token.to_string());
+                }
+            }
+            (
+                guard.token.clone(),
+                guard.refresh_token.clone(),
+                guard.credentials.clone(),
+            )
+        };
+        if current.is_none() {
+            return Err(failed("Not logged in"));
+        }
+
+        if let Some(refresh_token) = refresh_token {
+            if let Some(login) = self.refresh_with_token(&refresh_token).await? {
+                self.state.write().await.set_session(&login);
+                let _ = self.persist().await;
+                return Ok(login.access_token);
+            }
+        }
+
+        // Only a definite refusal ends the session; outages leave it for the next try
+        if let Some(credentials) = credentials {
+            match self.login(&credentials.email, &credentials.password).await {
+                Ok(login) => return Ok(login.access_token),
+                Err(HikariError::Rejected(_)) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.state.write().await.clear_session();
+        let _ = self.persist().await;
+        Err(HikariError::SessionExpired)
+    }
+
+    /// Categories the account's library is split into. Falls back to the games
+    /// category alone when the API does not list them.
+    async fn fetch_categories(&self) -> Vec<LibraryCategory> {
+        let api = self.api_base.clone();
+        let listed = match self
+            .send("Failed to fetch categories", |client, token| {
+                client
+                    .get(format!("{}categories", api))
+                    .bearer_auth(token)
+                    .header(ACCEPT, "application/json")
+            })
+            .await
+        {
+            Ok(resp) if resp.status().is_success() => resp
+                .json::<Value>()
+                .await
+                .map(|data| LibraryCategory::parse_list(&data))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        if listed.is_empty() {
+            vec![LibraryCategory::games()]
+        } else {
+            listed
+        }
+    }
+
+    async fn fetch_page(&self, category: &LibraryCategory, page: u32) -> ApiResult<Value> {
+        let payload = json!({
+            "category_id": category.id_value(),
+            "page": page,
+            "per_page": LIBRARY_PAGE_SIZE,
+        });
+
+        let api = self.api_base.clone();
+        let resp = self
+            .send("Failed to fetch library", |client, token| {
+                client
+                    .post(format!("{}apps", api))
+                    .bearer_auth(token)
+                    .header(CONTENT_TYPE, "application/json")
+                    .json(&payload)
+            })
+            .await?;
+
+        if !resp.status().is_success() {
+            let message = resp
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to fetch library".to_string());
+            return Err(failed(message));
+        }
+
+        resp.json()
+            .await
+            .map_err(|err| failed(format!("Invalid library response: {}", err)))
+    }
+
+    /// All items of one category. With a page count the remaining pages are fetched
+    /// in parallel; otherwise pages are walked until one comes back short or repeats.
+    async fn fetch_category(&self, category: &LibraryCategory) -> ApiResult<Vec<Value>> {
+        let first = self.fetch_page(category, 1).await?;
+        let mut items = catalog_items(&first).cloned().unwrap_or_default();
+
+        match page_count(&first) {
+            Some(pages) if pages > 1 => {
+                let rest = join_all((2..=pages).map(|page| self.fetch_page(category, page))).await;
+                for page in rest {
+                    items.extend(catalog_items(&page?).cloned().unwrap_or_default());
+                }
+            }
+            Some(_) => {}
+            None => {
+                let mut seen: HashSet<String> = items.iter().filter_map(item_id).collect();
+                let mut page_len = items.len();
+                let mut page = 1;
+                while page_len >= LIBRARY_PAGE_SIZE && page < MAX_LIBRARY_PAGES {
+                    page += 1;
+                    let data = self.fetch_page(category, page).await?;
+                    let batch = catalog_items(&data).cloned().unwrap_or_default();
+                    page_len = batch.len();
+                    let fresh = batch
+                        .into_iter()
+                        .filter(|item| item_id(item).map(|id| seen.insert(id)).unwrap_or(true))
+                        .collect::<Vec<_>>();
+                    // A server that ignores `page` keeps returning the same items
+                    if fresh.is_empty() {
+                        break;
+                    }
+                    items.extend(fresh);
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// Ask the API to sign the download URLs of a build.
+    async fn sign_build(&self, build_id: &str, task_type: i32) -> ApiResult<Value> {
+        let payload = json!({
+            "game_build_id": build_id,
+            "task_type": task_type,
+            "uuid": Uuid::new_v4().to_string(),
+        });
+
+        let api = self.api_base.clone();
+        let resp = self
+            .send("Failed to sign download URL", |client, token| {
+                client
+                    .post(format!("{}builds/sign", api))
+                    .bearer_auth(token)
+                    .header(CONTENT_TYPE, "application/json")
+                    .json(&payload)
+            })
+            .await?;
+
+        if !resp.status().is_success() {
+            let message = resp
+                .text()
+                .await
+                .unwrap_or_else(|_| "Failed to sign download URL".to_string());
+            return Err(failed(message));
+        }
+
+        let value: Value = resp
+            .json()
+            .await
+            .map_err(|err| failed(format!("Invalid sign response: {}", err)))?;
+
+        if let Some(url) = signed_urls_from(&value).into_iter().next() {
+            self.state.write().await.last_signed_url = Some(url);
+        }
+        Ok(value)
+    }
+
+    /// Current access token, renewed first when it is about to expire.
+    async fn token(&self) -> ApiResult<String> {
+        let (token, expiring) = {
+            let guard = self.state.read().await;
+            (guard.token.clone(), guard.token_expiring())
+        };
+        let token = token.ok_or_else(|| failed("Not logged in"))?;
+        if expiring {
+            self.renew(&token).await
+        } else {
+            Ok(token)
+        }
+    }
+
+    /// Send an authenticated request built by `build`, renewing the session and retrying
+    /// once on 401. A second 401 ends the session with `HikariSessionExpired`.
+    async fn send<F>(&self, what: &str, build: F) -> ApiResult<Response>
+    where
+        F: Fn(&Client, &str) -> RequestBuilder,
+    {
+        self.ensure_online().await?;
+        let token = self.token().await?;
+        let resp = self
+            .http
+            .send(build(&self.http.client(), &token))
+            .await
+            .map_err(|err| network(what, err))?;
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let token = self.renew(&token).await?;
+        let resp = self
+            .http
+            .send(build(&self.http.client(), &token))
+            .await
+            .map_err(|err| network(what, err))?;
+        if resp.status() == StatusCode::UNAUTHORIZED {
+            self.state.write().await.clear_session();
+            let _ = self.persist().await;
+            return Err(HikariError::SessionExpired);
+        }
+        Ok(resp)
+    }
+}
+
+/// Category the `/apps` endpoint files games under; other categories hold soundtracks,
+/// art books and similar extras.
+const GAME_CATEGORY_ID: &str = "1";
+/// Items requested per `/apps` page.
+const LIBRARY_PAGE_SIZE: usize = 50;
+/// Upper bound on pages per category when the API gives no page count.
+const MAX_LIBRARY_PAGES: u32 = 100;
+
+/// Platform assumed when callers don't ask for one; the Deck runs Windows builds via Proton.
+const DEFAULT_PLATFORM: &str = "windows";
+
+fn normalize_platform(value: &str) -> String {
+    let lower = value.trim().to_lowercase();
+    match lower.as_str() {
+        "win" | "win32" | "win64" | "windows" | "pc" => "windows".to_string(),
+        "mac" | "macos" | "osx" | "darwin" => "macos".to_string(),
+        _ => lower,
+    }
+}
+
+/// The item list of a catalog response: a bare array or one under `apps`/`data`/`items`.
+fn catalog_items(data: &Value) -> Option<&Vec<Value>> {
+    match data {
+        Value::Array(items) => Some(items),
+        _ => ["apps", "data", "items"]
+            .iter()
+            .find_map(|key| data.get(*key).and_then(Value::as_array)),
+    }
+}
+
+/// Page count of a paginated response, from the root or a `meta`/`pagination` object.
+fn page_count(data: &Value) -> Option<u32> {
+    let scopes = [Some(data), data.get("meta"), data.get("pagination")];
+    scopes.into_iter().flatten().find_map(|scope| {
+        ["last_page", "total_pages", "page_count"]
+            .iter()
+            .find_map(|key| scope.get(*key).and_then(Value::as_u64))
+            .or_else(|| {
+                let total = scope.get("total").and_then(Value::as_u64)?;
+                let per_page = scope
+                    .get("per_page")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(LIBRARY_PAGE_SIZE as u64)
+                    .max(1);
+                Some(total.div_ceil(per_page))
+            })
+            .map(|pages| pages.min(MAX_LIBRARY_PAGES as u64) as u32)
+    })
+}
+
+fn item_id(item: &Value) -> Option<String> {
+    match item.get("id").or_else(|| item.get("app_id"))? {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// A library category as listed by the API.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LibraryCategory {
+    id: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+impl LibraryCategory {
+    fn games() -> Self {
+        Self {
+            id: GAME_CATEGORY_ID.to_string(),
+            name: None,
+        }
+    }
+
+    fn parse_list(data: &Value) -> Vec<Self> {
+        let items = match data {
+            Value::Array(items) => Some(items),
+            _ => ["categories", "data", "items"]
+                .iter()
+                .find_map(|key| data.get(*key).and_then(Value::as_array)),
+        };
+        items
+            .into_iter()
+            .flatten()
+            .filter_map(|item| {
+                Some(Self {
+                    id: item_id(item)?,
+                    name: item
+                        .get("name")
+                        .or_else(|| item.get("title"))
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                })
+            })
+            .collect()
+    }
+
+    /// Request payload value; numeric ids are sent as numbers like the API expects.
+    fn id_value(&self) -> Value {
+        self.id
+            .parse::<i64>()
+            .map(Value::from)
+            .unwrap_or_else(|_| Value::from(self.id.clone()))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct LoginResponse {
-    access_token: String,
-    #[serde(default)]
-    user: Value,
+/// Merge per-category item lists, deduping by id. The first listing wins and every
+/// category an item appeared in is recorded under `categories`.
+fn merge_category_items(pages: Vec<(LibraryCategory, Vec<Value>)>) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (category, items) in pages {
+        for mut item in items {
+            let Some(id) = item_id(&item) else {
+                continue;
+            };
+            if let Some(&at) = index.get(&id) {
+                if let Some(Value::Array(list)) = merged[at].get_mut("categories") {
+                    let tag = Value::from(category.id.clone());
+                    if !list.contains(&tag) {
+                        list.push(tag);
+                    }
+                }
+                continue;
+            }
+            if let Some(obj) = item.as_object_mut() {
+                obj.entry("category_id")
+                    .or_insert_with(|| Value::from(category.id.clone()));
+                if let Some(name) = &category.name {
+                    obj.entry("category_name")
+                        .or_insert_with(|| Value::from(name.clone()));
+                }
+                obj.insert("categories".into(), json!([category.id]));
+            }
+            index.insert(id, merged.len());
+            merged.push(item);
+        }
+    }
+    merged
+}
+
+/// Numeric components of a version string, so "1.10" sorts after "1.9".
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|part| !part.is_empty())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+fn merge_languages<'a>(lists: impl IntoIterator<Item = &'a Vec<String>>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for language in lists.into_iter().flatten() {
+        if seen.insert(language.to_lowercase()) {
+            out.push(language.clone());
+        }
+    }
+    out.sort_by_key(|language| language.to_lowercase());
+    out
+}
+
+#[pyclass(module = "vn_core")]
+#[derive(Clone, Debug, Serialize)]
+pub struct HikariBuild {
+    #[pyo3(get)]
+    pub id: String,
+    #[pyo3(get)]
+    pub version: String,
+    #[pyo3(get)]
+    pub platform: String,
+    #[pyo3(get)]
+    pub size: u64,
+    #[pyo3(get)]
+    pub languages: Vec<String>,
+    #[pyo3(get)]
+    pub hash: Option<String>,
+    #[pyo3(get)]
+    pub changelog: Option<String>,
+    #[pyo3(get)]
+    pub released_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HikariBuild {
+    fn from_map(map: &Map<String, Value>) -> Option<Self> {
+        let mut fields = Fields::new(map);
+        let id = fields.string(&["id", "build_id", "game_build_id"])?;
+        Some(Self {
+            id,
+            version: fields
+                .string(&["version", "version_name", "build_version"])
+                .unwrap_or_default(),
+            platform: fields
+                .string(&["platform", "os"])
+                .map(|p| normalize_platform(&p))
+                .unwrap_or_default(),
+            size: fields.u64(&["size", "file_size", "package_size", "total_size"]),
+            languages: fields.strings(&["languages", "supported_languages", "language"]),
+            hash: fields.string(&["hash", "sha256", "sha1", "md5", "checksum"]),
+            changelog: fields.string(&["changelog", "release_notes", "notes"]),
+            released_at: fields.string(&["released_at", "created_at", "updated_at"]),
+            extra: fields.rest(),
+        })
+    }
+
+    /// Builds without a platform are treated as available everywhere.
+    pub fn supports_platform(&self, platform: &str) -> bool {
+        self.platform.is_empty() || self.platform == normalize_platform(platform)
+    }
+
+    fn compare_age(&self, other: &Self) -> Ordering {
+        version_key(&self.version)
+            .cmp(&version_key(&other.version))
+            .then_with(|| self.released_at.cmp(&other.released_at))
+            .then_with(|| version_key(&self.id).cmp(&version_key(&other.id)))
+    }
+}
+
+fn latest_build<'a>(builds: &'a [HikariBuild], platform: &str) -> Option<&'a HikariBuild> {
+    builds
+        .iter()
+        .filter(|build| build.supports_platform(platform))
+        .max_by(|a, b| a.compare_age(b))
+}
+
+#[pymethods]
+impl HikariBuild {
+    #[getter]
+    fn extra(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &Value::Object(self.extra.clone()))
+    }
+
+    #[pyo3(name = "supports_platform")]
+    fn py_supports_platform(&self, platform: &str) -> bool {
+        self.supports_platform(platform)
+    }
+
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &json!(self))
+    }
+}
+
+#[pyclass(module = "vn_core")]
+#[derive(Clone, Debug, Serialize)]
+pub struct HikariDlc {
+    #[pyo3(get)]
+    pub id: String,
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub size: u64,
+    #[pyo3(get)]
+    pub owned: bool,
+    #[pyo3(get)]
+    pub builds: Vec<HikariBuild>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HikariDlc {
+    fn from_map(map: &Map<String, Value>) -> Option<Self> {
+        let mut fields = Fields::new(map);
+        let id = fields.string(&["id", "dlc_id", "app_id"])?;
+        Some(Self {
+            id,
+            name: fields.string(&["name", "title"]).unwrap_or_default(),
+            size: fields.u64(&["size", "file_size"]),
+            owned: fields.bool(&["owned", "is_owned", "purchased"]).unwrap_or(true),
+            builds: fields
+                .objects(&["builds", "game_builds"])
+                .into_iter()
+                .filter_map(HikariBuild::from_map)
+                .collect(),
+            extra: fields.rest(),
+        })
+    }
+
+    fn download_size(&self, platform: &str) -> u64 {
+        latest_build(&self.builds, platform)
+            .map(|build| build.size)
+            .unwrap_or(self.size)
+    }
+}
+
+#[pymethods]
+impl HikariDlc {
+    #[getter]
+    fn extra(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &Value::Object(self.extra.clone()))
+    }
+
+    fn latest_build(&self, platform: Option<String>) -> Option<HikariBuild> {
+        latest_build(&self.builds, platform.as_deref().unwrap_or(DEFAULT_PLATFORM)).cloned()
+    }
+
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &json!(self))
+    }
+}
+
+/// One entry of the Hikari `/apps` catalog. Fields the API adds later are kept in `extra`.
+#[pyclass(module = "vn_core")]
+#[derive(Clone, Debug, Serialize)]
+pub struct HikariApp {
+    #[pyo3(get)]
+    pub id: String,
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub developer: String,
+    #[pyo3(get)]
+    pub description: String,
+    #[pyo3(get)]
+    pub cover: String,
+    #[pyo3(get)]
+    pub tags: Vec<String>,
+    #[pyo3(get)]
+    pub languages: Vec<String>,
+    #[pyo3(get)]
+    pub category_id: Option<String>,
+    #[pyo3(get)]
+    pub category_name: Option<String>,
+    /// Every category the app was listed under.
+    #[pyo3(get)]
+    pub categories: Vec<String>,
+    #[pyo3(get)]
+    pub builds: Vec<HikariBuild>,
+    #[pyo3(get)]
+    pub dlcs: Vec<HikariDlc>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HikariApp {
+    pub fn from_value(data: &Value) -> Option<Self> {
+        let mut fields = Fields::new(data.as_object()?);
+        let id = fields.string(&["id", "app_id", "game_id"])?;
+        Some(Self {
+            id,
+            name: fields
+                .string(&["name", "title", "app_name"])
+                .unwrap_or_default(),
+            developer: fields
+                .string(&["developer", "brand", "publisher", "maker"])
+                .unwrap_or_default(),
+            description: fields
+                .string(&["description", "summary", "intro"])
+                .unwrap_or_default(),
+            cover: fields
+                .string(&["cover", "cover_url", "thumbnail", "image", "icon"])
+                .unwrap_or_default(),
+            tags: fields.strings(&["tags", "genres"]),
+            languages: fields.strings(&["languages", "supported_languages", "language"]),
+            category_id: fields.string(&["category_id", "category"]),
+            category_name: fields.string(&["category_name"]),
+            categories: fields.strings(&["categories"]),
+            builds: fields
+                .objects(&["builds", "game_builds", "versions"])
+                .into_iter()
+                .filter_map(HikariBuild::from_map)
+                .collect(),
+            dlcs: fields
+                .objects(&["dlcs", "dlc", "addons"])
+                .into_iter()
+                .filter_map(HikariDlc::from_map)
+                .collect(),
+            extra: fields.rest(),
+        })
+    }
+
+    /// Parse every app in an `/apps` response, skipping entries without an id.
+    pub fn parse_catalog(data: &Value) -> Vec<Self> {
+        catalog_items(data)
+            .map(|items| items.iter().filter_map(Self::from_value).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether the app is in the games category rather than soundtracks, art books, ...
+    pub fn is_game(&self) -> bool {
+        self.categories.is_empty() || self.categories.iter().any(|id| id == GAME_CATEGORY_ID)
+    }
+
+    pub fn latest_build_for(&self, platform: &str) -> Option<&HikariBuild> {
+        latest_build(&self.builds, platform)
+    }
+
+    pub fn download_size_for(&self, platform: &str, include_dlcs: bool) -> u64 {
+        let base = self
+            .latest_build_for(platform)
+            .map(|build| build.size)
+            .unwrap_or(0);
+        if !include_dlcs {
+            return base;
+        }
+        base + self
+            .dlcs
+            .iter()
+            .filter(|dlc| dlc.owned)
+            .map(|dlc| dlc.download_size(platform))
+            .sum::<u64>()
+    }
+
+    /// Languages declared on the app or on any of its builds.
+    pub fn all_languages(&self) -> Vec<String> {
+        merge_languages(
+            std::iter::once(&self.languages).chain(self.builds.iter().map(|b| &b.languages)),
+        )
+    }
+
+    /// Flat game entry in the shape the frontend and `GameLibrary` work with.
+    pub fn to_game_value(&self) -> Value {
+        let build = self.latest_build_for(DEFAULT_PLATFORM);
+        let size = self.download_size_for(DEFAULT_PLATFORM, false);
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "developer": self.developer,
+            "description": self.description,
+            "thumbnail": self.cover,
+            "tags": self.tags,
+            "supported_languages": self.all_languages(),
+            "category_id": self.category_id,
+            "category_name": self.category_name,
+            "categories": self.categories,
+            "is_game": self.is_game(),
+            "size": GameLibrary::format_size(size),
+            "expected_size": size,
+            "build_id": build.map(|b| b.id.clone()),
+            "version": build.map(|b| b.version.clone()),
+            "integrity_hash": build.and_then(|b| b.hash.clone()),
+            "dlc_count": self.dlcs.len(),
+            "platform": "hikari",
+        })
+    }
+}
+
+#[pymethods]
+impl HikariApp {
+    #[getter]
+    fn extra(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &Value::Object(self.extra.clone()))
+    }
+
+    /// Newest build for `platform` (default "windows"), by version then release date.
+    pub fn latest_build(&self, platform: Option<String>) -> Option<HikariBuild> {
+        self.latest_build_for(platform.as_deref().unwrap_or(DEFAULT_PLATFORM))
+            .cloned()
+    }
+
+    /// Size of the latest build for `platform`, optionally including owned DLCs.
+    pub fn download_size(&self, platform: Option<String>, include_dlcs: Option<bool>) -> u64 {
+        self.download_size_for(
+            platform.as_deref().unwrap_or(DEFAULT_PLATFORM),
+            include_dlcs.unwrap_or(false),
+        )
+    }
+
+    pub fn supported_languages(&self) -> Vec<String> {
+        self.all_languages()
+    }
+
+    #[pyo3(name = "is_game")]
+    fn py_is_game(&self) -> bool {
+        self.is_game()
+    }
+
+    pub fn to_game(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &self.to_game_value())
+    }
+
+    pub fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &json!(self))
+    }
+}
+
+impl HikariClient {
+    fn session(&self) -> HikariSession {
+        HikariSession {
+            http: self.http.clone(),
+            api_base: self.api_base.clone(),
+            state: self.state.clone(),
+            refresh_lock: self.refresh_lock.clone(),
+            cache_path: self.cache_path.clone(),
+        }
+    }
+
+    /// Return the cached `/apps` response, fetching it (and re-parsing the typed
+    /// catalog) when there is none or `refresh` is set. Offline, only the cache is used.
+    async fn load_library(session: HikariSession, refresh: bool) -> ApiResult<Value> {
+        let profile = {
+            let guard = session.state.read().await;
+            if !refresh || guard.offline {
+                if let Some(ref cached) = guard.cached_library {
+                    return Ok(cached.clone());
+                }
+            }
+            if guard.offline {
+                return Err(failed("Offline and no cached Hikari library available"));
+            }
+            guard.active_profile.clone()
+        };
+
+        let categories = session.fetch_categories().await;
+        let fetched = join_all(
+            categories
+                .iter()
+                .map(|category| session.fetch_category(category)),
+        )
+        .await;
+
+        let mut pages = Vec::new();
+        let mut failed_ids = Vec::new();
+        let mut first_error = None;
+        for (category, result) in categories.iter().zip(fetched) {
+            match result {
+                Ok(items) => pages.push((category.clone(), items)),
+                Err(err) => {
+                    failed_ids.push(category.id.clone());
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        if pages.is_empty() {
+            return Err(first_error
+                .unwrap_or_else(|| failed("No library categories available")));
+        }
+
+        let result = json!({
+            "apps": merge_category_items(pages),
+            "categories": categories,
+            "failed_categories": failed_ids,
+        });
+
+        {
+            // Don't file the catalog under another account if the profile was switched meanwhile
+            let mut guard = session.state.write().await;
+            if guard.active_profile != profile {
+                return Err(failed("Hikari profile changed while fetching the library"));
+            }
+            guard.cached_apps = HikariApp::parse_catalog(&result);
+            guard.cached_library = Some(result.clone());
+            guard.library_fetched_at = Some(now_secs());
+        }
+        let _ = session.persist().await;
+
+        Ok(result)
+    }
 }
 
 #[pymethods]
 impl HikariClient {
+    /// `data_dir` enables the on-disk cache; state saved there is restored immediately.
     #[new]
-    pub fn new(api_base: Option<String>) -> PyResult<Self> {
-        let api_base = api_base.unwrap_or_else(|| "https://api.hikarifield.co.jp/v1/".to_string());
-        let http = Client::builder()
-            .user_agent(DEFAULT_USER_AGENT.as_str())
-            .build()
-            .map_err(|err| runtime_error(format!("Failed to create HTTP client: {}", err)))?;
+    pub fn new(api_base: Option<String>, data_dir: Option<String>) -> PyResult<Self> {
+        let mut api_base =
+            api_base.unwrap_or_else(|| "https://api.hikarifield.co.jp/v1/".to_string());
+        // Endpoints are joined onto the base, so it has to end in a slash
+        if !api_base.ends_with('/') {
+            api_base.push('/');
+        }
+        let http = HttpClient::new(ClientOptions {
+            user_agent: DEFAULT_USER_AGENT.clone(),
+            ..ClientOptions::default()
+        })?;
+
+        let cache_path = data_dir.map(|dir| PathBuf::from(dir).join(CACHE_FILE_NAME));
+        let state = cache_path
+            .as_deref()
+            .and_then(PersistedState::load)
+            .map(HikariState::from_persisted)
+            .unwrap_or_else(|| HikariState {
+                active_profile: default_profile_name(),
+                ..HikariState::default()
+            });
 
         Ok(Self {
             http,
             api_base,
-            state: Arc::new(RwLock::new(HikariState::default())),
+            state: Arc::new(RwLock::new(state)),
+            refresh_lock: Arc::new(Mutex::new(())),
+            cache_path,
         })
     }
 
+    /// Log in to the active profile, or to `profile` after switching to it.
     pub fn login<'py>(
         &'py self,
         py: Python<'py>,
         email: String,
         password: String,
+        profile: Option<String>,
     ) -> PyResult<&'py PyAny> {
-        let client = self.http.clone();
-        let api = self.api_base.clone();
-        let state = self.state.clone();
+        let session = self.session();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let payload = json!({
-                "email": email,
-                "password": password,
-            });
-
-            let resp = client
-                .post(format!("{}auth/login", api))
-                .header(CONTENT_TYPE, "application/json")
-                .header(ACCEPT, "application/json")
-                .json(&payload)
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Login request failed: {}", err)))?;
-
-            if !resp.status().is_success() {
-                let message = resp
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Authentication failed".to_string());
-                return Err(runtime_error(format!("Hikari login failed: {}", message)));
-            }
-
-            let parsed: LoginResponse = resp
-                .json()
-                .await
-                .map_err(|err| runtime_error(format!("Invalid login response: {}", err)))?;
-
-            {
-                let mut guard = state.write().await;
-                guard.token = Some(parsed.access_token.clone());
+            if let Some(name) = profile.as_deref().map(str::trim).filter(|name| !name.is_empty()) {
+                session.state.write().await.switch_profile(name);
             }
+            let parsed = session.login(&email, &password).await?;
+            let expires_at = parsed.expiry();
+            let active = session.state.read().await.active_profile.clone();
 
             json_result!({
                 "success": true,
                 "token": parsed.access_token,
+                "expiresAt": expires_at,
+                "profile": active,
             })
         })
     }
 
+    /// Log the active profile out and drop its cached data; other profiles are kept.
     pub fn logout<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
         let client = self.http.clone();
         let api = self.api_base.clone();
         let state = self.state.clone();
+        let session = self.session();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let token = {
+            let (token, offline) = {
                 let guard = state.read().await;
-                guard.token.clone()
+                (guard.token.clone(), guard.offline)
             };
 
-            if let Some(token_value) = token {
-                let _ = client
+            if let (Some(token_value), false) = (token, offline) {
+                let request = client
+                    .client()
                     .delete(format!("{}auth/logout", api))
-                    .bearer_auth(token_value)
-                    .send()
-                    .await;
+                    .bearer_auth(token_value);
+                let _ = client.send(request).await;
             }
 
             let mut guard = state.write().await;
-            guard.token = None;
+            guard.clear_session();
+            guard.credentials = None;
             guard.cdn_servers.clear();
+            guard.cdn_fetched_at = None;
             guard.selected_cdn = None;
+            // The cached catalog belongs to the account that just logged out
+            guard.cached_library = None;
+            guard.cached_apps.clear();
+            guard.library_fetched_at = None;
+            drop(guard);
+            let _ = session.persist().await;
 
             json_result!({
                 "success": true
@@ -141,6 +1690,10 @@ impl HikariClient {
             let selected = guard.selected_cdn.clone();
             json_result!({
                 "isLoggedIn": guard.token.is_some(),
+                "tokenExpiresAt": guard.token_expires_at,
+                "canRenew": guard.refresh_token.is_some() || guard.credentials.is_some(),
+                "offline": guard.offline,
+                "profile": guard.active_profile,
                 "cdnServers": cdn,
                 "selectedCdn": selected
             })
@@ -148,24 +1701,29 @@ impl HikariClient {
     }
 
     pub fn fetch_cdn_servers<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
-        let client = self.http.clone();
+        let session = self.session();
         let api = self.api_base.clone();
         let state = self.state.clone();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let token = {
+            {
                 let guard = state.read().await;
-                guard.token.clone()
-            };
-
-            let token_value = token.ok_or_else(|| runtime_error("Not logged in"))?;
+                if guard.offline {
+                    let cached = guard.cdn_servers.clone();
+                    return json_result!({
+                        "servers": cached,
+                        "cached": true
+                    });
+                }
+            }
 
-            let resp = client
-                .get(format!("{}clients/iplist", api))
-                .bearer_auth(&token_value)
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to fetch CDN servers: {}", err)))?;
+            let resp = session
+                .send("Failed to fetch CDN servers", |client, token| {
+                    client
+                        .get(format!("{}clients/iplist", api))
+                        .bearer_auth(token)
+                })
+                .await?;
 
             let data: Value = resp
                 .json()
@@ -190,14 +1748,16 @@ impl HikariClient {
                 guard.cdn_servers = cdn_list.clone().into_iter().map(|map| {
                     map.into_iter().collect::<HashMap<String, Value>>()
                 }).collect();
+                guard.cdn_fetched_at = Some(now_secs());
                 if guard.selected_cdn.is_none() {
                     guard.selected_cdn = cdn_list
-                        .get(0)
+                        .first()
                         .and_then(|entry| entry.get("ip"))
                         .and_then(|ip| ip.as_str())
                         .map(|s| s.to_string());
                 }
             }
+            let _ = session.persist().await;
 
             json_result!({
                 "servers": cdn_list
@@ -206,6 +1766,7 @@ impl HikariClient {
     }
 
     pub fn select_cdn<'py>(&'py self, py: Python<'py>, server_ip: String) -> PyResult<&'py PyAny> {
+        let session = self.session();
         let state = self.state.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
             let mut guard = state.write().await;
@@ -215,6 +1776,8 @@ impl HikariClient {
                 .any(|entry| entry.get("ip").and_then(|v| v.as_str()) == Some(server_ip.as_str()));
             if exists {
                 guard.selected_cdn = Some(server_ip);
+                drop(guard);
+                let _ = session.persist().await;
                 json_result!({"success": true})
             } else {
                 Err(runtime_error("Server IP not found"))
@@ -227,115 +1790,137 @@ impl HikariClient {
         py: Python<'py>,
         force_refresh: Option<bool>,
     ) -> PyResult<&'py PyAny> {
-        let client = self.http.clone();
-        let api = self.api_base.clone();
-        let state = self.state.clone();
+        let session = self.session();
         let refresh = force_refresh.unwrap_or(false);
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            {
-                let guard = state.read().await;
-                if !refresh {
-                    if let Some(ref cached) = guard.cached_library {
-                        return Python::with_gil(|py| {
-                            value_to_py(py, &cached)
-                        });
-                    }
-                }
-            }
-
-            let token = {
-                let guard = state.read().await;
-                guard.token.clone()
-            };
-            let token_value = token.ok_or_else(|| runtime_error("Not logged in"))?;
-
-            let payload = json!({
-                "category_id": 1
-            });
-
-            let resp = client
-                .post(format!("{}apps", api))
-                .bearer_auth(&token_value)
-                .header(CONTENT_TYPE, "application/json")
-                .json(&payload)
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to fetch library: {}", err)))?;
-
-            if !resp.status().is_success() {
-                let message = resp
-                    .text()
-                    .await
-                    .unwrap_or_else(|_| "Failed to fetch library".to_string());
-                return Err(runtime_error(message));
-            }
-
-            let result: Value = resp
-                .json()
-                .await
-                .map_err(|err| runtime_error(format!("Invalid library response: {}", err)))?;
-
-            {
-                let mut guard = state.write().await;
-                guard.cached_library = Some(result.clone());
-            }
-
+            let result = Self::load_library(session, refresh).await?;
             Python::with_gil(|py| {
                 value_to_py(py, &result)
             })
         })
     }
 
+    /// Typed view of the library: one `HikariApp` per catalog entry.
+    pub fn get_apps<'py>(
+        &'py self,
+        py: Python<'py>,
+        force_refresh: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        let refresh = force_refresh.unwrap_or(false);
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Self::load_library(session, refresh).await?;
+            let guard = state.read().await;
+            Ok(guard.cached_apps.clone())
+        })
+    }
+
     pub fn get_signed_urls<'py>(
         &'py self,
         py: Python<'py>,
         game_build_id: String,
         task_type: Option<i32>,
     ) -> PyResult<&'py PyAny> {
-        let client = self.http.clone();
-        let api = self.api_base.clone();
-        let state = self.state.clone();
+        let session = self.session();
         let task_type_value = task_type.unwrap_or(0);
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let token = {
+            let value = session.sign_build(&game_build_id, task_type_value).await?;
+            Python::with_gil(|py| {
+                value_to_py(py, &value)
+            })
+        })
+    }
+
+    /// Everything `DownloadManager.start_download` needs for a build: signed URLs routed
+    /// through the selected and benchmarked CDNs (original URLs kept as fallbacks), plus
+    /// the expected size and hash from the catalog. An app id selects its latest build.
+    pub fn prepare_download<'py>(
+        &'py self,
+        py: Python<'py>,
+        build_id: String,
+        platform: Option<String>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        let platform = platform.unwrap_or_else(|| DEFAULT_PLATFORM.to_string());
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let (target, hosts, profile) = {
                 let guard = state.read().await;
-                guard.token.clone()
+                (
+                    DownloadTarget::resolve(&guard.cached_apps, &build_id, &platform),
+                    guard.ranked_cdn_hosts(),
+                    guard.active_profile.clone(),
+                )
             };
-            let token_value = token.ok_or_else(|| runtime_error("Not logged in"))?;
+            let build_id = target
+                .as_ref()
+                .map(|target| target.build.id.clone())
+                .unwrap_or(build_id);
 
-            let payload = json!({
-                "game_build_id": game_build_id,
-                "task_type": task_type_value,
-                "uuid": Uuid::new_v4().to_string(),
-            });
+            let signed = signed_urls_from(&session.sign_build(&build_id, 0).await?);
+            if signed.is_empty() {
+                return Err(runtime_error("No download URLs returned for this build"));
+            }
+            let sources = route_sources(&signed, &hosts);
 
-            let resp = client
-                .post(format!("{}builds/sign", api))
-                .bearer_auth(&token_value)
-                .header(CONTENT_TYPE, "application/json")
-                .json(&payload)
-                .send()
-                .await
-                .map_err(|err| runtime_error(format!("Failed to sign download URL: {}", err)))?;
+            let build = target.as_ref().map(|target| &target.build);
+            json_result!({
+                "build_id": build_id,
+                "game_id": target.as_ref().map(|target| target.app_id.clone()),
+                "game_name": target.as_ref().map(|target| target.app_name.clone()),
+                "version": build.map(|build| build.version.clone()),
+                "sources": sources,
+                "expected_size": build.map(|build| build.size).filter(|size| *size > 0),
+                "integrity_hash": build.and_then(|build| build.hash.clone()),
+                // Pass to `start_download(metadata=...)` so update checks know what is installed
+                "install_metadata": {
+                    "store": "hikari",
+                    "account": profile,
+                    "build_id": build_id,
+                    "version": build.map(|build| build.version.clone()),
+                    "build_size": build.map(|build| build.size),
+                    "build_platform": build
+                        .map(|build| build.platform.clone())
+                        .filter(|platform| !platform.is_empty())
+                        .unwrap_or(platform),
+                }
+            })
+        })
+    }
+
+    /// Compare the builds recorded for installed games against the catalog and
+    /// return one entry per game with a newer build available.
+    pub fn check_updates<'py>(
+        &'py self,
+        py: Python<'py>,
+        library: &GameLibrary,
+        force_refresh: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        let games_dir = library.games_dir().to_path_buf();
+        let refresh = force_refresh.unwrap_or(false);
 
-            if !resp.status().is_success() {
-                let message = resp
-                    .text()
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let installed =
+                tokio::task::spawn_blocking(move || GameLibrary::installed_builds(&games_dir))
                     .await
-                    .unwrap_or_else(|_| "Failed to sign download URL".to_string());
-                return Err(runtime_error(message));
+                    .map_err(|err| runtime_error(err.to_string()))?;
+            if installed.is_empty() {
+                return json_result!([]);
             }
-
-            let value: Value = resp
-                .json()
-                .await
-                .map_err(|err| runtime_error(format!("Invalid sign response: {}", err)))?;
-
-            Python::with_gil(|py| {
-                value_to_py(py, &value)
-            })
+            Self::load_library(session, refresh).await?;
+            let guard = state.read().await;
+            let updates = installed
+                .iter()
+                .filter_map(|build| update_for(build, &guard.cached_apps))
+                .collect::<Vec<_>>();
+            json_result!(updates)
         })
     }
 
@@ -345,7 +1930,75 @@ impl HikariClient {
             let guard = state.read().await;
             json_result!({
                 "selected": guard.selected_cdn,
-                "servers": guard.cdn_servers
+                "servers": guard.cdn_servers,
+                "benchmarks": guard.cdn_benchmarks
+            })
+        })
+    }
+
+    /// Measure every known CDN server in parallel: response latency, plus the
+    /// throughput of a short ranged download of `sample_url` (default: the last signed
+    /// URL). Results are stored, the fastest server is selected unless `auto_select` is
+    /// false, and the full table is returned best first.
+    pub fn benchmark_cdn_servers<'py>(
+        &'py self,
+        py: Python<'py>,
+        sample_url: Option<String>,
+        timeout_secs: Option<f64>,
+        auto_select: Option<bool>,
+    ) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        let limit = Duration::from_secs_f64(
+            timeout_secs
+                .filter(|secs| *secs > 0.0)
+                .unwrap_or(CDN_BENCHMARK_TIMEOUT_SECS),
+        );
+        let auto_select = auto_select.unwrap_or(true);
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            session.ensure_online().await?;
+            let (hosts, sample) = {
+                let guard = state.read().await;
+                let hosts = guard.cdn_servers.iter().filter_map(cdn_host).collect::<Vec<_>>();
+                (hosts, sample_url.or_else(|| guard.last_signed_url.clone()))
+            };
+            if hosts.is_empty() {
+                return Err(runtime_error("No CDN servers known; fetch the server list first"));
+            }
+            let sample = match sample {
+                Some(url) => Some(
+                    reqwest::Url::parse(&url)
+                        .map_err(|err| runtime_error(format!("Invalid sample URL: {}", err)))?,
+                ),
+                None => None,
+            };
+
+            let mut results = join_all(hosts.into_iter().map(|host| {
+                benchmark_server(session.http.clone(), host, sample.clone(), limit)
+            }))
+            .await;
+            results.sort_by(|a, b| a.rank(b));
+
+            let fastest = results
+                .first()
+                .filter(|best| best.latency_ms.is_some())
+                .map(|best| best.ip.clone());
+            let selected = {
+                let mut guard = state.write().await;
+                guard.cdn_benchmarks = results.clone();
+                if auto_select && fastest.is_some() {
+                    guard.selected_cdn = fastest.clone();
+                }
+                guard.selected_cdn.clone()
+            };
+            let _ = session.persist().await;
+
+            json_result!({
+                "results": results,
+                "fastest": fastest,
+                "selected": selected,
+                "measuredThroughput": sample.is_some()
             })
         })
     }
@@ -356,6 +2009,8 @@ impl HikariClient {
             let guard = state.read().await;
             json_result!({
                 "token": guard.token,
+                "refresh_token": guard.refresh_token,
+                "token_expires_at": guard.token_expires_at,
                 "selected_cdn": guard.selected_cdn
             })
         })
@@ -366,16 +2021,132 @@ impl HikariClient {
         py: Python<'py>,
         token: Option<String>,
         selected_cdn: Option<String>,
+        refresh_token: Option<String>,
+        token_expires_at: Option<i64>,
     ) -> PyResult<&'py PyAny> {
+        let session = self.session();
         let state = self.state.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let mut guard = state.write().await;
-            guard.token = token;
-            guard.selected_cdn = selected_cdn;
+            {
+                // Missing values keep whatever the cache file restored. The cache owns
+                // the session, so a token from older settings only fills an empty one.
+                let mut guard = state.write().await;
+                if token.is_some() && guard.token.is_none() {
+                    // Older settings files carry no expiry; fall back to the token's own claim
+                    guard.token_expires_at =
+                        token_expires_at.or_else(|| token.as_deref().and_then(jwt_expiry));
+                    guard.token = token;
+                    guard.refresh_token = refresh_token;
+                }
+                if selected_cdn.is_some() {
+                    guard.selected_cdn = selected_cdn;
+                }
+            }
+            let _ = session.persist().await;
+            json_result!({"success": true})
+        })
+    }
+
+    pub fn list_profiles<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let state = self.state.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let guard = state.read().await;
+            json_result!(guard.profile_summaries())
+        })
+    }
+
+    /// Make `name` the active account profile, creating an empty one if it is new.
+    /// Its token, CDN choice and cached library replace the current ones.
+    pub fn switch_profile<'py>(&'py self, py: Python<'py>, name: String) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let name = name.trim().to_string();
+            if name.is_empty() {
+                return Err(runtime_error("Profile name must not be empty"));
+            }
+            let logged_in = {
+                let mut guard = state.write().await;
+                guard.switch_profile(&name);
+                guard.token.is_some()
+            };
+            let _ = session.persist().await;
+            json_result!({
+                "success": true,
+                "profile": name,
+                "isLoggedIn": logged_in
+            })
+        })
+    }
+
+    /// Forget an inactive profile and everything cached for it.
+    pub fn remove_profile<'py>(&'py self, py: Python<'py>, name: String) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            {
+                let mut guard = state.write().await;
+                if guard.active_profile == name {
+                    return Err(runtime_error("Cannot remove the active profile; switch first"));
+                }
+                if guard.profiles.remove(&name).is_none() {
+                    return Err(runtime_error("Profile not found"));
+                }
+                guard.profile_credentials.remove(&name);
+            }
+            let _ = session.persist().await;
             json_result!({"success": true})
         })
     }
 
+    pub fn get_active_profile(&self) -> PyResult<String> {
+        if let Ok(guard) = self.state.try_read() {
+            Ok(guard.active_profile.clone())
+        } else {
+            Ok(DEFAULT_PROFILE.to_string())
+        }
+    }
+
+    /// In offline mode only cached data is served and no request leaves the device.
+    pub fn set_offline_mode<'py>(&'py self, py: Python<'py>, enabled: bool) -> PyResult<&'py PyAny> {
+        let session = self.session();
+        let state = self.state.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            state.write().await.offline = enabled;
+            let _ = session.persist().await;
+            json_result!({"success": true, "offline": enabled})
+        })
+    }
+
+    pub fn is_offline(&self) -> PyResult<bool> {
+        if let Ok(guard) = self.state.try_read() {
+            Ok(guard.offline)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// What is cached and how old it is.
+    pub fn get_cache_info<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
+        let state = self.state.clone();
+        let cache_path = self.cache_path.clone();
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let guard = state.read().await;
+            let mut library = cache_age(guard.library_fetched_at, LIBRARY_STALE_AFTER_SECS);
+            library["available"] = json!(guard.cached_library.is_some());
+            library["count"] = json!(guard.cached_apps.len());
+            let mut cdn = cache_age(guard.cdn_fetched_at, CDN_STALE_AFTER_SECS);
+            cdn["available"] = json!(!guard.cdn_servers.is_empty());
+            cdn["count"] = json!(guard.cdn_servers.len());
+            json_result!({
+                "offline": guard.offline,
+                "path": cache_path.map(|path| path.to_string_lossy().to_string()),
+                "library": library,
+                "cdn": cdn
+            })
+        })
+    }
+
     pub fn to_dict<'py>(&'py self, py: Python<'py>) -> PyResult<&'py PyAny> {
         let state = self.state.clone();
         pyo3_asyncio::tokio::future_into_py(py, async move {
@@ -396,3 +2167,295 @@ impl HikariClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{fixture, MockResponse, MockServer};
+
+    const EMAIL: &str = "reader@example.com";
+    const PASSWORD: &str = "hunter2";
+
+    fn session_for(server: &MockServer) -> HikariSession {
+        HikariSession {
+            http: HttpClient::new(ClientOptions::default()).unwrap(),
+            api_base: format!("{}/v1/", server.url()),
+            state: Arc::new(RwLock::new(HikariState {
+                active_profile: default_profile_name(),
+                ..HikariState::default()
+            })),
+            refresh_lock: Arc::new(Mutex::new(())),
+            cache_path: None,
+        }
+    }
+
+    async fn logged_in(server: &MockServer) -> HikariSession {
+        server.respond("POST", "/v1/auth/login", MockResponse::fixture("hikari/login.json"));
+        let session = session_for(server);
+        session.login(EMAIL, PASSWORD).await.expect("login");
+        session
+    }
+
+    fn mount_library(server: &MockServer) {
+        server.respond("GET", "/v1/categories", MockResponse::fixture("hikari/categories.json"));
+        server.on("POST", "/v1/apps", |req| {
+            let body = req.json();
+            match (body["category_id"].as_i64(), body["page"].as_u64()) {
+                (Some(1), Some(1)) => MockResponse::fixture("hikari/apps_games_page1.json"),
+                (Some(1), Some(2)) => MockResponse::fixture("hikari/apps_games_page2.json"),
+                (Some(3), Some(1)) => MockResponse::fixture("hikari/apps_soundtracks.json"),
+                _ => MockResponse::status(404),
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn login_stores_tokens_and_expiry() {
+        let server = MockServer::start().await;
+        let session = logged_in(&server).await;
+
+        let sent = server.requests_to("/v1/auth/login");
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].json(), json!({ "email": EMAIL, "password": PASSWORD }));
+
+        let state = session.state.read().await;
+        assert_eq!(state.token.as_deref(), Some("token-1"));
+        assert_eq!(state.refresh_token.as_deref(), Some("refresh-1"));
+        let expires_at = state.token_expires_at.expect("expiry");
+        assert!((expires_at - now_secs() - 3600).abs() <= 5);
+    }
+
+    #[tokio::test]
+    async fn login_failure_reports_server_message() {
+        let server = MockServer::start().await;
+        server.respond(
+            "POST",
+            "/v1/auth/login",
+            MockResponse::status(401).body(r#"{"message":"Invalid email or password"}"#),
+        );
+        let session = session_for(&server);
+
+        let err = session.login(EMAIL, "wrong").await.unwrap_err();
+        assert!(err.to_string().contains("Invalid email or password"), "{}", err);
+        assert!(session.state.read().await.token.is_none());
+    }
+
+    #[tokio::test]
+    async fn library_merges_every_page_of_every_category() {
+        let server = MockServer::start().await;
+        mount_library(&server);
+        let session = logged_in(&server).await;
+
+        let library = HikariClient::load_library(session.clone(), true).await.unwrap();
+
+        let ids: Vec<_> = library["apps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(item_id)
+            .collect();
+        assert_eq!(ids, ["101", "102", "103", "201"]);
+        assert_eq!(library["apps"][0]["categories"], json!(["1", "3"]));
+        assert_eq!(library["failed_categories"], json!([]));
+
+        let pages = server.requests_to("/v1/apps");
+        assert_eq!(pages.len(), 3);
+        assert!(pages
+            .iter()
+            .all(|req| req.header("authorization") == Some("Bearer token-1")));
+
+        let state = session.state.read().await;
+        assert_eq!(state.cached_apps.len(), 4);
+        let sakura = &state.cached_apps[0];
+        assert!(sakura.is_game());
+        assert_eq!(sakura.latest_build_for("windows").unwrap().id, "b-101-2");
+    }
+
+    #[tokio::test]
+    async fn failed_category_is_reported_not_fatal() {
+        let server = MockServer::start().await;
+        mount_library(&server);
+        server.on("POST", "/v1/apps", |req| match req.json()["category_id"].as_i64() {
+            Some(3) => MockResponse::status(500).body("upstream error"),
+            _ => MockResponse::fixture(if req.json()["page"] == 1 {
+                "hikari/apps_games_page1.json"
+            } else {
+                "hikari/apps_games_page2.json"
+            }),
+        });
+        let session = logged_in(&server).await;
+
+        let library = HikariClient::load_library(session, true).await.unwrap();
+        assert_eq!(library["apps"].as_array().unwrap().len(), 3);
+        assert_eq!(library["failed_categories"], json!(["3"]));
+    }
+
+    #[tokio::test]
+    async fn pages_without_count_stop_when_the_server_repeats_itself() {
+        let server = MockServer::start().await;
+        let full_page: Vec<Value> = (0..LIBRARY_PAGE_SIZE)
+            .map(|i| json!({ "id": i, "name": format!("App {}", i) }))
+            .collect();
+        // Ignores `page` entirely, like older API versions
+        server.respond("POST", "/v1/apps", MockResponse::json(&json!({ "apps": full_page })));
+        let session = logged_in(&server).await;
+
+        let items = session.fetch_category(&LibraryCategory::games()).await.unwrap();
+        assert_eq!(items.len(), LIBRARY_PAGE_SIZE);
+        assert_eq!(server.requests_to("/v1/apps").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn sign_build_returns_signed_urls() {
+        let server = MockServer::start().await;
+        server.respond("POST", "/v1/builds/sign", MockResponse::fixture("hikari/sign.json"));
+        let session = logged_in(&server).await;
+
+        let value = session.sign_build("b-101-2", 0).await.unwrap();
+        let urls = signed_urls_from(&value);
+        assert_eq!(urls, signed_urls_from(&fixture("hikari/sign.json")));
+        assert_eq!(urls.len(), 2);
+
+        let sent = &server.requests_to("/v1/builds/sign")[0];
+        assert_eq!(sent.json()["game_build_id"], "b-101-2");
+        assert_eq!(sent.json()["task_type"], 0);
+        assert_eq!(
+            session.state.read().await.last_signed_url.as_deref(),
+            Some(urls[0].as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn sign_build_surfaces_error_responses() {
+        let server = MockServer::start().await;
+        server.respond(
+            "POST",
+            "/v1/builds/sign",
+            MockResponse::status(403).body("Build not owned"),
+        );
+        let session = logged_in(&server).await;
+
+        let err = session.sign_build("b-999", 0).await.unwrap_err();
+        assert_eq!(err, HikariError::Failed("Build not owned".to_string()));
+    }
+
+    #[tokio::test]
+    async fn rejected_token_is_refreshed_and_the_request_retried() {
+        let server = MockServer::start().await;
+        server.on("POST", "/v1/builds/sign", |req| {
+            if req.header("authorization") == Some("Bearer token-2") {
+                MockResponse::fixture("hikari/sign.json")
+            } else {
+                MockResponse::status(401)
+            }
+        });
+        server.on("POST", "/v1/auth/refresh", |req| {
+            if req.json()["refresh_token"] == "refresh-1" {
+                MockResponse::fixture("hikari/refresh.json")
+            } else {
+                MockResponse::status(401)
+            }
+        });
+        let session = logged_in(&server).await;
+
+        session.sign_build("b-101-2", 0).await.unwrap();
+        assert_eq!(server.requests_to("/v1/builds/sign").len(), 2);
+        let state = session.state.read().await;
+        assert_eq!(state.token.as_deref(), Some("token-2"));
+        assert_eq!(state.refresh_token.as_deref(), Some("refresh-2"));
+    }
+
+    #[tokio::test]
+    async fn session_expires_when_renewal_does_not_help() {
+        let server = MockServer::start().await;
+        server.respond("POST", "/v1/builds/sign", MockResponse::status(401));
+        server.respond("POST", "/v1/auth/refresh", MockResponse::status(401));
+        let session = logged_in(&server).await;
+
+        let err = session.sign_build("b-101-2", 0).await.unwrap_err();
+        assert_eq!(err, HikariError::SessionExpired);
+        // Refresh failed, so the stored credentials were tried before giving up
+        assert_eq!(server.requests_to("/v1/auth/login").len(), 2);
+        assert!(session.state.read().await.token.is_none());
+    }
+
+    #[tokio::test]
+    async fn outages_during_renewal_keep_the_session() {
+        let server = MockServer::start().await;
+        server.respond("POST", "/v1/builds/sign", MockResponse::status(401));
+        server.respond("POST", "/v1/auth/refresh", MockResponse::status(503));
+        let session = logged_in(&server).await;
+
+        let err = session.sign_build("b-101-2", 0).await.unwrap_err();
+        assert!(matches!(err, HikariError::Failed(_)), "{}", err);
+
+        // Refresh token refused, but the login endpoint is down
+        server.respond("POST", "/v1/auth/refresh", MockResponse::status(401));
+        server.respond("POST", "/v1/auth/login", MockResponse::status(502));
+        let err = session.sign_build("b-101-2", 0).await.unwrap_err();
+        assert!(matches!(err, HikariError::Failed(_)), "{}", err);
+
+        let state = session.state.read().await;
+        assert_eq!(state.token.as_deref(), Some("token-1"));
+        assert_eq!(state.refresh_token.as_deref(), Some("refresh-1"));
+    }
+
+    #[tokio::test]
+    async fn cdn_benchmark_goes_through_the_shared_client() {
+        let server = MockServer::start().await;
+        server.respond("HEAD", "/builds/b-101-2.zip", MockResponse::status(403));
+        server.respond(
+            "GET",
+            "/builds/b-101-2.zip",
+            MockResponse::status(206).body("x".repeat(4096)),
+        );
+        let port = server.url().rsplit(':').next().unwrap().to_string();
+        let sample = reqwest::Url::parse(&format!("http://dl.invalid:{}/builds/b-101-2.zip", port));
+        let http = HttpClient::new(ClientOptions::default()).unwrap();
+
+        let result = benchmark_server(
+            http,
+            "127.0.0.1".to_string(),
+            Some(sample.unwrap()),
+            Duration::from_secs(5),
+        )
+        .await;
+        assert!(result.error.is_none(), "{:?}", result.error);
+        assert!(result.latency_ms.is_some());
+        assert!(result.throughput.unwrap() > 0.0);
+        let sent = server.requests_to("/builds/b-101-2.zip");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[1].header("range"), Some("bytes=0-1048575"));
+    }
+
+    #[test]
+    fn selected_cdn_leads_the_download_sources() {
+        let signed = signed_urls_from(&fixture("hikari/sign.json"));
+        let server = |ip: &str| HashMap::from([("ip".to_string(), json!(ip))]);
+        let mut state = HikariState {
+            cdn_servers: vec![server("203.0.113.7"), server("198.51.100.2")],
+            ..HikariState::default()
+        };
+        let first = route_sources(&signed, &state.ranked_cdn_hosts());
+        assert!(first[0].ends_with("#via=203.0.113.7"), "{}", first[0]);
+
+        state.selected_cdn = Some("198.51.100.2".to_string());
+        let sources = route_sources(&signed, &state.ranked_cdn_hosts());
+        assert_ne!(sources, first);
+        assert_eq!(sources[0], format!("{}#via=198.51.100.2", signed[0]));
+        assert_eq!(sources[1], format!("{}#via=198.51.100.2", signed[1]));
+        // The API's own URLs stay as the last resort
+        assert_eq!(&sources[sources.len() - 2..], &signed[..]);
+    }
+
+    #[tokio::test]
+    async fn offline_mode_blocks_network_calls() {
+        let server = MockServer::start().await;
+        let session = logged_in(&server).await;
+        session.state.write().await.offline = true;
+
+        assert!(session.sign_build("b-101-2", 0).await.is_err());
+        assert!(HikariClient::load_library(session, true).await.is_err());
+        assert!(server.requests_to("/v1/builds/sign").is_empty());
+    }
+}
@@ -0,0 +1,737 @@
+//! HTTP plumbing shared by the store clients and the download manager: connect/read
+//! timeouts, an optional proxy, per-host rate limits, retries for idempotent requests
+//! and an opt-in trace log with secrets redacted.
+
+use crate::util::{extract_serde, value_to_py};
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::redirect::Policy;
+use reqwest::{Client, IntoUrl, Method, Request, RequestBuilder, Response, StatusCode, Url};
+use reqwest_cookie_store::CookieStoreMutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+create_exception!(
+    vn_core,
+    NetworkError,
+    PyRuntimeError,
+    "A request could not be completed: timeout, refused connection or proxy failure."
+);
+
+/// Longest `Retry-After` we are willing to wait between attempts.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(30);
+
+/// Header, query and body fields that never make it into the trace log.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-xsrf-token",
+];
+const SECRET_FIELDS: &[&str] = &[
+    "password",
+    "token",
+    "secret",
+    "signature",
+    "policy",
+    "key-pair-id",
+    "sig",
+    "auth",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Network settings from the plugin config. Missing fields keep their defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct HttpConfig {
+    pub connect_timeout_secs: f64,
+    /// Longest wait for response headers, and between body chunks while streaming.
+    pub read_timeout_secs: f64,
+    /// `http://`, `https://` or `socks5://` proxy used for every request.
+    pub proxy: Option<String>,
+    /// Extra attempts for idempotent requests after a network error or 429/5xx.
+    pub max_retries: u32,
+    pub retry_backoff_ms: u64,
+    /// Requests per second allowed to any single host; `None` means unlimited.
+    pub requests_per_second: Option<f64>,
+    /// Per-host overrides of `requests_per_second`, keyed by host name.
+    pub host_rate_limits: HashMap<String, f64>,
+    /// Append one JSON line per request attempt to this file.
+    pub trace_log: Option<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10.0,
+            read_timeout_secs: 30.0,
+            proxy: None,
+            max_retries: 2,
+            retry_backoff_ms: 500,
+            requests_per_second: None,
+            host_rate_limits: HashMap::new(),
+            trace_log: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    fn connect_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.connect_timeout_secs.max(0.1))
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.read_timeout_secs.max(0.1))
+    }
+
+    fn rate_for(&self, host: &str) -> Option<f64> {
+        self.host_rate_limits
+            .get(host)
+            .copied()
+            .or(self.requests_per_second)
+            .filter(|rate| *rate > 0.0)
+    }
+}
+
+static CONFIG: Lazy<RwLock<Arc<HttpConfig>>> = Lazy::new(Default::default);
+/// Bumped on every reconfiguration so clients know to rebuild.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+/// Earliest instant the next request to each host may start. Shared by all clients,
+/// since the limit protects the host rather than one client.
+static NEXT_SLOT: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+static TRACE_FILE: Lazy<Mutex<()>> = Lazy::new(Default::default);
+
+pub(crate) fn config() -> Arc<HttpConfig> {
+    CONFIG.read().clone()
+}
+
+pub(crate) fn set_config(config: HttpConfig) {
+    *CONFIG.write() = Arc::new(config);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Failure to get a response at all. HTTP error statuses are not errors here.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum HttpError {
+    Timeout { url: String },
+    Connect { url: String, message: String },
+    Request { url: String, message: String },
+    Invalid(String),
+}
+
+impl HttpError {
+    fn from_reqwest(url: &Url, err: reqwest::Error) -> Self {
+        let url = redact_url(url);
+        if err.is_timeout() {
+            HttpError::Timeout { url }
+        } else if err.is_connect() {
+            HttpError::Connect {
+                url,
+                message: root_cause(&err),
+            }
+        } else {
+            HttpError::Request {
+                url,
+                message: root_cause(&err),
+            }
+        }
+    }
+
+    fn retryable(&self) -> bool {
+        matches!(self, HttpError::Timeout { .. } | HttpError::Connect { .. })
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout { url } => write!(f, "request to {} timed out", url),
+            HttpError::Connect { url, message } => {
+                write!(f, "could not connect to {}: {}", url, message)
+            }
+            HttpError::Request { url, message } => write!(f, "request to {} failed: {}", url, message),
+            HttpError::Invalid(message) => write!(f, "invalid request: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<HttpError> for PyErr {
+    fn from(err: HttpError) -> Self {
+        NetworkError::new_err(err.to_string())
+    }
+}
+
+fn root_cause(err: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = err;
+    while let Some(next) = cause.source() {
+        cause = next;
+    }
+    cause.to_string()
+}
+
+/// Per-client settings that survive a rebuild after the shared config changes.
+#[derive(Clone, Default)]
+pub(crate) struct ClientOptions {
+    pub user_agent: String,
+    pub cookies: Option<Arc<CookieStoreMutex>>,
+    /// Hand redirects back to the caller instead of following them.
+    pub no_redirects: bool,
+    /// Connect to this address for the given host name instead of resolving it, e.g. to
+    /// reach one CDN server while TLS still checks the original name.
+    pub resolve: Option<(String, SocketAddr)>,
+}
+
+struct Built {
+    generation: u64,
+    client: Client,
+}
+
+/// `reqwest::Client` wrapper every outgoing request goes through. Cheap to clone.
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    options: Arc<ClientOptions>,
+    built: Arc<RwLock<Built>>,
+}
+
+impl HttpClient {
+    pub fn new(options: ClientOptions) -> Result<Self, HttpError> {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        let client = Self::build(&options, &config())?;
+        Ok(Self {
+            options: Arc::new(options),
+            built: Arc::new(RwLock::new(Built { generation, client })),
+        })
+    }
+
+    fn build(options: &ClientOptions, config: &HttpConfig) -> Result<Client, HttpError> {
+        let mut builder = Client::builder()
+            .user_agent(options.user_agent.as_str())
+            .connect_timeout(config.connect_timeout());
+        if let Some(cookies) = &options.cookies {
+            builder = builder.cookie_provider(Arc::clone(cookies));
+        }
+        if options.no_redirects {
+            builder = builder.redirect(Policy::none());
+        }
+        if let Some((host, addr)) = &options.resolve {
+            builder = builder.resolve(host, *addr);
+        }
+        if let Some(proxy) = config.proxy.as_deref().filter(|p| !p.trim().is_empty()) {
+            let proxy = reqwest::Proxy::all(proxy.trim())
+                .map_err(|err| HttpError::Invalid(format!("proxy {}: {}", proxy, err)))?;
+            builder = builder.proxy(proxy);
+        }
+        builder
+            .build()
+            .map_err(|err| HttpError::Invalid(format!("failed to create HTTP client: {}", err)))
+    }
+
+    /// A client with the same options that connects to `addr` for `host`.
+    pub fn pinned(&self, host: &str, addr: SocketAddr) -> Result<Self, HttpError> {
+        Self::new(ClientOptions {
+            resolve: Some((host.to_string(), addr)),
+            ..(*self.options).clone()
+        })
+    }
+
+    /// The underlying client, rebuilt first if the shared config changed. A config that
+    /// fails to build (e.g. a malformed proxy) keeps the previous client.
+    pub fn client(&self) -> Client {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        {
+            let built = self.built.read();
+            if built.generation == generation {
+                return built.client.clone();
+            }
+        }
+        let mut built = self.built.write();
+        if built.generation != generation {
+            if let Ok(client) = Self::build(&self.options, &config()) {
+                built.client = client;
+            }
+            built.generation = generation;
+        }
+        built.client.clone()
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client().get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client().post(url)
+    }
+
+    /// Send a request whose body is read in one go; the read timeout covers the whole
+    /// exchange.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+        let config = config();
+        let request = request.timeout(config.connect_timeout() + config.read_timeout());
+        self.execute(request, &config).await
+    }
+
+    /// Send a request whose body is streamed. Only the wait for headers is bounded here;
+    /// use `next_chunk` to bound each body read.
+    pub async fn send_streaming(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+        self.execute(request, &config()).await
+    }
+
+    async fn execute(
+        &self,
+        request: RequestBuilder,
+        config: &HttpConfig,
+    ) -> Result<Response, HttpError> {
+        let request = request
+            .build()
+            .map_err(|err| HttpError::Invalid(err.to_string()))?;
+        let idempotent = matches!(
+            *request.method(),
+            Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+        );
+        // Streaming bodies cannot be cloned, so those requests get a single attempt
+        let attempts = if idempotent && request.try_clone().is_some() {
+            config.max_retries + 1
+        } else {
+            1
+        };
+        let header_wait = config.connect_timeout() + config.read_timeout();
+        let client = self.client();
+
+        let mut pending = Some(request);
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let current = pending.take().expect("request for this attempt");
+            if attempt < attempts {
+                pending = current.try_clone();
+            }
+            let url = current.url().clone();
+            if let Some(host) = url.host_str() {
+                throttle(host, config).await;
+            }
+
+            let trace = config.trace_log.as_ref().map(|_| TraceEntry::start(&current, attempt));
+            let result = match tokio::time::timeout(header_wait, client.execute(current)).await {
+                Ok(Ok(response)) => Ok(response),
+                Ok(Err(err)) => Err(HttpError::from_reqwest(&url, err)),
+                Err(_) => Err(HttpError::Timeout {
+                    url: redact_url(&url),
+                }),
+            };
+            if let (Some(path), Some(entry)) = (config.trace_log.as_ref(), trace) {
+                entry.finish(&result).write(path.clone()).await;
+            }
+
+            let retry_wait = match &result {
+                Ok(response) if retryable_status(response.status()) => {
+                    Some(retry_after(response.headers()))
+                }
+                Err(err) if err.retryable() => Some(None),
+                _ => None,
+            };
+            match (retry_wait, pending.is_some()) {
+                (Some(wait), true) => {
+                    let backoff = Duration::from_millis(config.retry_backoff_ms)
+                        * 2u32.saturating_pow(attempt - 1);
+                    tokio::time::sleep(wait.unwrap_or(backoff).min(MAX_RETRY_WAIT)).await;
+                }
+                _ => return result,
+            }
+        }
+    }
+}
+
+/// Next body chunk of a streamed response, failing if the server stalls longer than the
+/// configured read timeout.
+pub(crate) async fn next_chunk<S, B, E>(stream: &mut S) -> Option<anyhow::Result<B>>
+where
+    S: futures::Stream<Item = Result<B, E>> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    use futures::StreamExt;
+    let limit = config().read_timeout();
+    match tokio::time::timeout(limit, stream.next()).await {
+        Ok(item) => item.map(|result| result.map_err(anyhow::Error::from)),
+        Err(_) => Some(Err(anyhow::anyhow!(
+            "no data received for {} seconds",
+            limit.as_secs_f64()
+        ))),
+    }
+}
+
+fn retryable_status(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 502 | 503 | 504)
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Wait until `host` may be contacted again under its rate limit and reserve the slot.
+async fn throttle(host: &str, config: &HttpConfig) {
+    let Some(rate) = config.rate_for(host) else {
+        return;
+    };
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let start = {
+        let mut slots = NEXT_SLOT.lock();
+        let now = Instant::now();
+        let start = slots.get(host).copied().filter(|next| *next > now).unwrap_or(now);
+        slots.insert(host.to_string(), start + interval);
+        start
+    };
+    tokio::time::sleep_until(start.into()).await;
+}
+
+fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_FIELDS.iter().any(|secret| name.contains(secret))
+}
+
+/// `url` with secret-looking query values and any userinfo replaced.
+pub(crate) fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_password(None);
+    if url.query().is_some() {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(key, value)| {
+                let value = if is_secret(&key) {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (key.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+fn redact_headers(headers: &HeaderMap) -> Value {
+    let map = headers
+        .iter()
+        .map(|(name, value)| {
+            let shown = if SECRET_HEADERS.contains(&name.as_str()) || is_secret(name.as_str()) {
+                REDACTED.to_string()
+            } else {
+                value.to_str().unwrap_or("<binary>").to_string()
+            };
+            (name.as_str().to_string(), Value::String(shown))
+        })
+        .collect();
+    Value::Object(map)
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// Request body for the trace: JSON and form bodies with secret fields redacted, other
+/// bodies by size only.
+fn redact_body(body: &[u8]) -> Value {
+    if let Ok(mut value) = serde_json::from_slice::<Value>(body) {
+        redact_json(&mut value);
+        return value;
+    }
+    let text = String::from_utf8_lossy(body);
+    if !text.is_empty() && text.split('&').all(|pair| pair.contains('=')) {
+        let pairs = url::form_urlencoded::parse(body)
+            .map(|(key, value)| {
+                let value = if is_secret(&key) {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (key.into_owned(), Value::String(value))
+            })
+            .collect();
+        return Value::Object(pairs);
+    }
+    json!({ "bytes": body.len() })
+}
+
+struct TraceEntry {
+    started: Instant,
+    record: Value,
+}
+
+impl TraceEntry {
+    fn start(request: &Request, attempt: u32) -> Self {
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(redact_body);
+        Self {
+            started: Instant::now(),
+            record: json!({
+                "at": chrono::Utc::now().to_rfc3339(),
+                "attempt": attempt,
+                "method": request.method().as_str(),
+                "url": redact_url(request.url()),
+                "request_headers": redact_headers(request.headers()),
+                "request_body": body,
+            }),
+        }
+    }
+
+    fn finish(mut self, result: &Result<Response, HttpError>) -> Self {
+        let elapsed = self.started.elapsed().as_secs_f64() * 1000.0;
+        if let Value::Object(map) = &mut self.record {
+            map.insert("elapsed_ms".into(), json!(elapsed));
+            match result {
+                Ok(response) => {
+                    map.insert("status".into(), json!(response.status().as_u16()));
+                    map.insert("response_headers".into(), redact_headers(response.headers()));
+                }
+                Err(err) => {
+                    map.insert("error".into(), json!(err.to_string()));
+                }
+            }
+        }
+        self
+    }
+
+    /// Append the entry to the trace log on a blocking thread; the lock keeps
+    /// concurrent entries from interleaving.
+    async fn write(self, path: PathBuf) {
+        let line = format!("{}\n", self.record);
+        let _ = tokio::task::spawn_blocking(move || {
+            let _guard = TRACE_FILE.lock();
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = file.write_all(line.as_bytes());
+            }
+        })
+        .await;
+    }
+}
+
+/// Apply network settings (timeouts, proxy, rate limits, retries, trace log) to every
+/// client. Unknown keys are ignored and missing ones fall back to their defaults.
+#[pyfunction]
+pub fn configure_http(py: Python<'_>, settings: Option<&PyAny>) -> PyResult<PyObject> {
+    let config: HttpConfig = match settings {
+        Some(obj) if !obj.is_none() => extract_serde(obj)?,
+        _ => HttpConfig::default(),
+    };
+    if let Some(proxy) = config.proxy.as_deref().filter(|p| !p.trim().is_empty()) {
+        reqwest::Proxy::all(proxy.trim())
+            .map_err(|err| NetworkError::new_err(format!("Invalid proxy {}: {}", proxy, err)))?;
+    }
+    set_config(config);
+    get_http_config(py)
+}
+
+#[pyfunction]
+pub fn get_http_config(py: Python<'_>) -> PyResult<PyObject> {
+    let mut value = json!(*config());
+    if let Some(proxy) = value.get_mut("proxy") {
+        if let Some(url) = proxy.as_str().and_then(|p| Url::parse(p).ok()) {
+            *proxy = Value::String(redact_url(&url));
+        }
+    }
+    value_to_py(py, &value)
+}
+
+pub fn register(py: Python<'_>, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(configure_http, module)?)?;
+    module.add_function(wrap_pyfunction!(get_http_config, module)?)?;
+    module.add("NetworkError", py.get_type::<NetworkError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+    use std::sync::atomic::AtomicUsize;
+
+    fn quick_config() -> HttpConfig {
+        HttpConfig {
+            retry_backoff_ms: 1,
+            ..HttpConfig::default()
+        }
+    }
+
+    fn client() -> HttpClient {
+        HttpClient::new(ClientOptions::default()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn idempotent_requests_are_retried_on_unavailable() {
+        let server = MockServer::start().await;
+        let calls = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&calls);
+        server.on("GET", "/flaky", move |_| {
+            if seen.fetch_add(1, Ordering::SeqCst) < 2 {
+                MockResponse::status(503)
+            } else {
+                MockResponse::status(200).body("ok")
+            }
+        });
+        let http = client();
+
+        let request = http.get(format!("{}/flaky", server.url()));
+        let response = http.execute(request, &quick_config()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_the_configured_budget() {
+        let server = MockServer::start().await;
+        server.respond("GET", "/down", MockResponse::status(502));
+        let http = client();
+        let config = HttpConfig {
+            max_retries: 1,
+            ..quick_config()
+        };
+
+        let request = http.get(format!("{}/down", server.url()));
+        let response = http.execute(request, &config).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(server.requests_to("/down").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn posts_are_never_retried() {
+        let server = MockServer::start().await;
+        server.respond("POST", "/login", MockResponse::status(503));
+        let http = client();
+
+        let request = http.post(format!("{}/login", server.url())).body("a=b");
+        let response = http.execute(request, &quick_config()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests_to("/login").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_a_typed_error() {
+        // Bind and drop to get a port nothing listens on
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let http = client();
+        let config = HttpConfig {
+            max_retries: 0,
+            ..quick_config()
+        };
+
+        let request = http.get(format!("http://{}/?token=abc", addr));
+        let err = http.execute(request, &config).await.unwrap_err();
+        assert!(matches!(err, HttpError::Connect { .. }), "{:?}", err);
+        assert!(!err.to_string().contains("abc"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn host_rate_limit_spaces_requests() {
+        let server = MockServer::start().await;
+        server.respond("GET", "/limited", MockResponse::status(200));
+        let http = client();
+        // A host name of its own so other tests talking to 127.0.0.1 are unaffected
+        let config = HttpConfig {
+            host_rate_limits: HashMap::from([("localhost".to_string(), 20.0)]),
+            ..quick_config()
+        };
+        let url = server.url().replace("127.0.0.1", "localhost");
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            http.execute(http.get(format!("{}/limited", url)), &config)
+                .await
+                .unwrap();
+        }
+        // Three requests at 20/s need at least two 50ms gaps
+        assert!(started.elapsed() >= Duration::from_millis(95));
+    }
+
+    #[tokio::test]
+    async fn trace_log_redacts_secrets() {
+        let server = MockServer::start().await;
+        server.respond(
+            "POST",
+            "/auth",
+            MockResponse::status(200).header("set-cookie", "session=very-secret"),
+        );
+        let path = std::env::temp_dir()
+            .join(format!("vn_core_trace_{}.jsonl", uuid::Uuid::new_v4()));
+        let config = HttpConfig {
+            trace_log: Some(path.clone()),
+            ..quick_config()
+        };
+        let http = client();
+
+        let request = http
+            .post(format!("{}/auth?signature=s3cr3t&page=2", server.url()))
+            .bearer_auth("bearer-secret")
+            .json(&json!({ "email": "reader@example.com", "password": "hunter2" }));
+        http.execute(request, &config).await.unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        for secret in ["s3cr3t", "bearer-secret", "hunter2", "very-secret"] {
+            assert!(!log.contains(secret), "{} leaked into {}", secret, log);
+        }
+        let entry: Value = serde_json::from_str(log.lines().next().unwrap()).unwrap();
+        assert_eq!(entry["status"], 200);
+        assert_eq!(entry["request_body"]["email"], "reader@example.com");
+        assert!(entry["url"].as_str().unwrap().contains("page=2"));
+    }
+
+    #[tokio::test]
+    async fn pinned_clients_connect_to_the_given_address() {
+        let server = MockServer::start().await;
+        server.respond("GET", "/file", MockResponse::status(200).body("pinned"));
+        let addr: SocketAddr = server.url().trim_start_matches("http://").parse().unwrap();
+        let http = client().pinned("cdn.invalid", addr).unwrap();
+
+        let url = format!("http://cdn.invalid:{}/file", addr.port());
+        let response = http.execute(http.get(url), &quick_config()).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "pinned");
+        assert_eq!(server.requests_to("/file").len(), 1);
+    }
+
+    #[test]
+    fn form_bodies_are_redacted_field_by_field() {
+        let body = redact_body(b"login_id=reader&password=hunter2&_token=csrf");
+        assert_eq!(body["login_id"], "reader");
+        assert_eq!(body["password"], REDACTED);
+        assert_eq!(body["_token"], REDACTED);
+        assert_eq!(redact_body(&[0, 159, 146, 150]), json!({ "bytes": 4 }));
+    }
+}
@@ -0,0 +1,651 @@
+//! On-disk cache for store artwork (DLsite thumbnails, Hikari catalog covers).
+//!
+//! Files are named after the SHA-256 of their bytes, so works sharing artwork share a
+//! file. `index.json` maps every URL, and every Steam size derived from it, to its file
+//! and when it was last used; the least recently used entries go once the cache grows
+//! past its size limit.
+
+use crate::hikari::DEFAULT_USER_AGENT;
+use crate::http::{ClientOptions, HttpClient};
+use crate::util::{runtime_error, value_to_py};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::stream::{self, StreamExt};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use parking_lot::Mutex;
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const INDEX_FILE_NAME: &str = "index.json";
+/// Size limit when the plugin does not pick one.
+const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+/// Anything bigger is not artwork.
+const MAX_IMAGE_BYTES: usize = 32 * 1024 * 1024;
+const PREFETCH_CONCURRENCY: usize = 4;
+const ARTWORK_JPEG_QUALITY: u8 = 90;
+
+/// Artwork Steam shows for non-Steam shortcuts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SteamArtwork {
+    /// Portrait library capsule.
+    Grid,
+    /// Wide capsule, e.g. on the recent games shelf.
+    Banner,
+    /// Backdrop of the game page.
+    Hero,
+}
+
+impl SteamArtwork {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "grid" | "portrait" | "capsule" => Some(SteamArtwork::Grid),
+            "banner" | "wide" | "header" => Some(SteamArtwork::Banner),
+            "hero" => Some(SteamArtwork::Hero),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SteamArtwork::Grid => "grid",
+            SteamArtwork::Banner => "banner",
+            SteamArtwork::Hero => "hero",
+        }
+    }
+
+    fn dimensions(self) -> (u32, u32) {
+        match self {
+            SteamArtwork::Grid => (600, 900),
+            SteamArtwork::Banner => (920, 430),
+            SteamArtwork::Hero => (1920, 620),
+        }
+    }
+}
+
+fn parse_sizes(sizes: Option<Vec<String>>) -> Result<Vec<SteamArtwork>> {
+    sizes
+        .unwrap_or_default()
+        .iter()
+        .map(|size| {
+            SteamArtwork::from_str(size).ok_or_else(|| anyhow!("Unknown artwork size: {}", size))
+        })
+        .collect()
+}
+
+/// Index key of `url` cut to `size`.
+fn artwork_key(url: &str, size: SteamArtwork) -> String {
+    format!("{}#{}", url, size.as_str())
+}
+
+/// Cut `bytes` to fill `size` exactly, cropping what sticks out, and encode it as JPEG.
+fn render_artwork(bytes: &[u8], size: SteamArtwork) -> Result<Vec<u8>> {
+    let (width, height) = size.dimensions();
+    let source = image::load_from_memory(bytes).context("Unreadable image")?;
+    let resized = source.resize_to_fill(width, height, FilterType::Lanczos3);
+    let mut out = Vec::new();
+    DynamicImage::ImageRgb8(resized.to_rgb8())
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut out, ARTWORK_JPEG_QUALITY))
+        .context("Failed to encode artwork")?;
+    Ok(out)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    file: String,
+    size: u64,
+    /// Value of the index clock at the last lookup.
+    last_used: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheIndex {
+    clock: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl CacheIndex {
+    fn touch(&mut self, key: &str) -> Option<&CacheEntry> {
+        self.clock += 1;
+        let clock = self.clock;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used = clock;
+        Some(entry)
+    }
+
+    /// Bytes on disk; a file shared by several entries counts once.
+    fn total_bytes(&self) -> u64 {
+        self.files().values().sum()
+    }
+
+    fn files(&self) -> HashMap<&str, u64> {
+        self.entries
+            .values()
+            .map(|entry| (entry.file.as_str(), entry.size))
+            .collect()
+    }
+
+    /// Drop least recently used entries other than `keep` until at most `max_bytes`
+    /// are used. Returns the files no entry refers to any more.
+    fn evict(&mut self, max_bytes: u64, keep: &str) -> Vec<String> {
+        let mut total = self.total_bytes();
+        let mut orphaned = Vec::new();
+        while total > max_bytes {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(key, _)| key.as_str() != keep)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            let Some(entry) = oldest.and_then(|key| self.entries.remove(&key)) else {
+                break;
+            };
+            if !self.entries.values().any(|other| other.file == entry.file) {
+                total = total.saturating_sub(entry.size);
+                orphaned.push(entry.file);
+            }
+        }
+        orphaned
+    }
+}
+
+/// Counts of the prefetch runs so far.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+struct PrefetchProgress {
+    queued: usize,
+    cached: usize,
+    failed: usize,
+}
+
+struct ImageStore {
+    http: HttpClient,
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<CacheIndex>,
+    /// Held while the index is written so snapshots reach the disk in order.
+    saving: tokio::sync::Mutex<()>,
+    prefetch: Mutex<PrefetchProgress>,
+}
+
+impl ImageStore {
+    /// Open the cache in `dir`, forgetting index entries whose file is gone and deleting
+    /// files no entry points to. A corrupt index starts the cache over.
+    fn open(dir: PathBuf, max_bytes: u64, http: HttpClient) -> Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create image cache {}", dir.display()))?;
+        let mut index: CacheIndex = fs::read(dir.join(INDEX_FILE_NAME))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        index.entries.retain(|_, entry| dir.join(&entry.file).is_file());
+
+        // Left behind by a lost index, an interrupted write or an eviction that failed
+        let referenced = index.files();
+        for entry in fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stray = name != INDEX_FILE_NAME && !referenced.contains_key(name.as_str());
+            if stray && entry.path().is_file() {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+
+        Ok(Self {
+            http,
+            dir,
+            max_bytes,
+            index: Mutex::new(index),
+            saving: tokio::sync::Mutex::new(()),
+            prefetch: Mutex::new(PrefetchProgress::default()),
+        })
+    }
+
+    /// Local file of `key` when it is cached. A hit counts as a use for eviction; the
+    /// new order is saved with the next change to the index.
+    fn lookup(&self, key: &str) -> Option<PathBuf> {
+        let mut index = self.index.lock();
+        let path = self.dir.join(&index.touch(key)?.file);
+        if path.is_file() {
+            Some(path)
+        } else {
+            index.entries.remove(key);
+            None
+        }
+    }
+
+    /// Local copy of the image at `url`, downloaded on a miss.
+    async fn image(&self, url: &str) -> Result<PathBuf> {
+        if let Some(path) = self.lookup(url) {
+            return Ok(path);
+        }
+        let bytes = self.download(url).await?;
+        let format = image::guess_format(&bytes).map_err(|_| anyhow!("Not an image: {}", url))?;
+        let extension = format.extensions_str().first().copied().unwrap_or("img");
+        self.insert(url, bytes, extension).await
+    }
+
+    /// `url` cut to the size Steam expects for `size`, rendered from the cached original.
+    async fn artwork(&self, url: &str, size: SteamArtwork) -> Result<PathBuf> {
+        let key = artwork_key(url, size);
+        if let Some(path) = self.lookup(&key) {
+            return Ok(path);
+        }
+        let original = self.image(url).await?;
+        let bytes = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let source = fs::read(&original)
+                .with_context(|| format!("Failed to read {}", original.display()))?;
+            render_artwork(&source, size)
+        })
+        .await
+        .map_err(|err| anyhow!("Artwork task failed: {}", err))??;
+        self.insert(&key, bytes, "jpg").await
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let response = self
+            .http
+            .send(self.http.get(url))
+            .await
+            .with_context(|| format!("Image request failed: {}", url))?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("Image request failed (status {}): {}", status, url);
+        }
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_IMAGE_BYTES as u64)
+        {
+            bail!("Image too large: {}", url);
+        }
+        let bytes = response.bytes().await.context("Failed to read image")?;
+        if bytes.len() > MAX_IMAGE_BYTES {
+            bail!("Image too large: {}", url);
+        }
+        Ok(bytes.to_vec())
+    }
+
+    /// Write `bytes` under their hash, point `key` at them and evict what no longer fits.
+    async fn insert(&self, key: &str, bytes: Vec<u8>, extension: &str) -> Result<PathBuf> {
+        let file = format!("{:x}.{}", Sha256::digest(&bytes), extension);
+        let path = self.dir.join(&file);
+        let size = bytes.len() as u64;
+        let (partial, target) = (self.dir.join(format!("{}.part", file)), path.clone());
+        blocking(move || {
+            if !target.is_file() {
+                fs::write(&partial, bytes)
+                    .with_context(|| format!("Failed to write {}", partial.display()))?;
+                fs::rename(&partial, &target)
+                    .with_context(|| format!("Failed to write {}", target.display()))?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        let orphans = {
+            let mut index = self.index.lock();
+            index.clock += 1;
+            let entry = CacheEntry {
+                file,
+                size,
+                last_used: index.clock,
+            };
+            index.entries.insert(key.to_string(), entry);
+            index.evict(self.max_bytes, key)
+        };
+        self.save(orphans).await?;
+        Ok(path)
+    }
+
+    /// Delete `orphans` and write the index as it is now, off the async threads.
+    async fn save(&self, mut orphans: Vec<String>) -> Result<()> {
+        let _saving = self.saving.lock().await;
+        let data = {
+            let index = self.index.lock();
+            // An insert since the eviction may point at the same content again
+            let referenced = index.files();
+            orphans.retain(|file| !referenced.contains_key(file.as_str()));
+            serde_json::to_vec(&*index).context("Failed to encode image cache index")?
+        };
+        let dir = self.dir.clone();
+        blocking(move || {
+            for orphan in orphans {
+                let _ = fs::remove_file(dir.join(orphan));
+            }
+            write_index(&dir, &data)
+        })
+        .await
+    }
+
+    /// Cache every URL and the given Steam sizes of it. Failures are counted, not raised.
+    async fn prefetch(&self, urls: Vec<String>, sizes: &[SteamArtwork]) -> PrefetchProgress {
+        let mut urls: Vec<String> = urls.into_iter().filter(|url| !url.is_empty()).collect();
+        urls.sort();
+        urls.dedup();
+        self.prefetch.lock().queued += urls.len();
+
+        let mut results = stream::iter(urls)
+            .map(|url| async move {
+                self.image(&url).await?;
+                for size in sizes {
+                    self.artwork(&url, *size).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .buffer_unordered(PREFETCH_CONCURRENCY);
+        let mut run = PrefetchProgress::default();
+        while let Some(result) = results.next().await {
+            run.queued += 1;
+            let mut total = self.prefetch.lock();
+            if result.is_ok() {
+                run.cached += 1;
+                total.cached += 1;
+            } else {
+                run.failed += 1;
+                total.failed += 1;
+            }
+        }
+        run
+    }
+
+    fn clear(&self) -> Result<()> {
+        let mut index = self.index.lock();
+        for file in index.files().keys() {
+            let _ = fs::remove_file(self.dir.join(file));
+        }
+        *index = CacheIndex::default();
+        let data = serde_json::to_vec(&*index).context("Failed to encode image cache index")?;
+        write_index(&self.dir, &data)
+    }
+
+    fn stats(&self) -> Value {
+        let index = self.index.lock();
+        json!({
+            "entries": index.entries.len(),
+            "files": index.files().len(),
+            "bytes": index.total_bytes(),
+            "max_bytes": self.max_bytes,
+            "prefetch": *self.prefetch.lock(),
+        })
+    }
+}
+
+/// Replace `index.json` through a temp file, so a crash never leaves half an index.
+fn write_index(dir: &Path, data: &[u8]) -> Result<()> {
+    let path = dir.join(INDEX_FILE_NAME);
+    let partial = dir.join(format!("{}.part", INDEX_FILE_NAME));
+    fs::write(&partial, data).with_context(|| format!("Failed to write {}", partial.display()))?;
+    fs::rename(&partial, &path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+async fn blocking<T>(work: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T>
+where
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| anyhow!("Image cache task failed: {}", err))?
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+#[pyclass(module = "vn_core")]
+pub struct ImageCache {
+    store: Arc<ImageStore>,
+}
+
+#[pymethods]
+impl ImageCache {
+    /// Cache in `cache_dir`, kept under `max_size_mb` (default 256).
+    #[new]
+    pub fn new(cache_dir: String, max_size_mb: Option<u64>) -> PyResult<Self> {
+        let http = HttpClient::new(ClientOptions {
+            user_agent: DEFAULT_USER_AGENT.clone(),
+            ..ClientOptions::default()
+        })?;
+        let max_bytes = match max_size_mb {
+            Some(mb) => mb
+                .checked_mul(1024 * 1024)
+                .ok_or_else(|| runtime_error(format!("max_size_mb {} is too large", mb)))?,
+            None => DEFAULT_MAX_BYTES,
+        };
+        let store = ImageStore::open(PathBuf::from(cache_dir), max_bytes, http)
+            .map_err(|err| runtime_error(err.to_string()))?;
+        Ok(Self {
+            store: Arc::new(store),
+        })
+    }
+
+    /// Local path of the image at `url`, downloading it first when it is not cached.
+    pub fn get_image<'py>(&'py self, py: Python<'py>, url: String) -> PyResult<&'py PyAny> {
+        let store = Arc::clone(&self.store);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let path = store
+                .image(&url)
+                .await
+                .map_err(|err| runtime_error(err.to_string()))?;
+            Ok(path_string(&path))
+        })
+    }
+
+    /// Local path of `url` (or of its Steam `size`) if it is cached; never downloads.
+    pub fn get_cached_path(&self, url: String, size: Option<String>) -> PyResult<Option<String>> {
+        let key = match size {
+            Some(size) => {
+                let size = SteamArtwork::from_str(&size)
+                    .ok_or_else(|| runtime_error(format!("Unknown artwork size: {}", size)))?;
+                artwork_key(&url, size)
+            }
+            None => url,
+        };
+        Ok(self.store.lookup(&key).as_deref().map(path_string))
+    }
+
+    /// Local path of `url` cut to a Steam artwork size: `"grid"` (600x900), `"banner"`
+    /// (920x430) or `"hero"` (1920x620).
+    pub fn get_steam_artwork<'py>(
+        &'py self,
+        py: Python<'py>,
+        url: String,
+        size: String,
+    ) -> PyResult<&'py PyAny> {
+        let size = SteamArtwork::from_str(&size)
+            .ok_or_else(|| runtime_error(format!("Unknown artwork size: {}", size)))?;
+        let store = Arc::clone(&self.store);
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let path = store
+                .artwork(&url, size)
+                .await
+                .map_err(|err| runtime_error(err.to_string()))?;
+            Ok(path_string(&path))
+        })
+    }
+
+    /// Start caching `urls`, and the Steam `sizes` of each, in the background. Returns
+    /// right away; `stats()["prefetch"]` tracks how it goes.
+    pub fn prefetch(&self, urls: Vec<String>, sizes: Option<Vec<String>>) -> PyResult<()> {
+        let sizes = parse_sizes(sizes).map_err(|err| runtime_error(err.to_string()))?;
+        let store = Arc::clone(&self.store);
+        pyo3_asyncio::tokio::get_runtime().spawn(async move {
+            store.prefetch(urls, &sizes).await;
+        });
+        Ok(())
+    }
+
+    /// `entries`, `files`, `bytes`, `max_bytes` and `prefetch` counts (`queued`,
+    /// `cached`, `failed`).
+    pub fn stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        value_to_py(py, &self.store.stats())
+    }
+
+    pub fn clear(&self) -> PyResult<()> {
+        self.store
+            .clear()
+            .map_err(|err| runtime_error(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+    use image::{ImageBuffer, ImageFormat, Rgb};
+    use std::io::Cursor;
+
+    fn png(width: u32, height: u32, shade: u8) -> Vec<u8> {
+        let image = ImageBuffer::from_pixel(width, height, Rgb([shade, 64, 128]));
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    fn store_in(dir: &Path, max_bytes: u64) -> ImageStore {
+        let http = HttpClient::new(ClientOptions::default()).unwrap();
+        ImageStore::open(dir.to_path_buf(), max_bytes, http).unwrap()
+    }
+
+    fn temp_cache() -> PathBuf {
+        std::env::temp_dir().join(format!("vn_core_images_{}", uuid::Uuid::new_v4()))
+    }
+
+    fn serve(server: &MockServer, path: &str, bytes: Vec<u8>) {
+        server.respond(
+            "GET",
+            path,
+            MockResponse::status(200)
+                .header("content-type", "image/png")
+                .body(bytes),
+        );
+    }
+
+    #[tokio::test]
+    async fn images_are_downloaded_once_and_stored_by_content() {
+        let server = MockServer::start().await;
+        serve(&server, "/a.png", png(4, 4, 10));
+        serve(&server, "/copy-of-a.png", png(4, 4, 10));
+        let dir = temp_cache();
+        let store = store_in(&dir, DEFAULT_MAX_BYTES);
+
+        let first = store.image(&format!("{}/a.png", server.url())).await.unwrap();
+        let again = store.image(&format!("{}/a.png", server.url())).await.unwrap();
+        let copy = store
+            .image(&format!("{}/copy-of-a.png", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(first, again);
+        assert_eq!(first, copy);
+        assert!(first.extension().is_some_and(|ext| ext == "png"));
+        assert_eq!(server.requests_to("/a.png").len(), 1);
+
+        // The index survives a restart
+        let reopened = store_in(&dir, DEFAULT_MAX_BYTES);
+        assert_eq!(reopened.lookup(&format!("{}/a.png", server.url())), Some(first));
+        assert_eq!(reopened.stats()["files"], 1);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_images_are_evicted() {
+        let server = MockServer::start().await;
+        for (name, shade) in [("/1.png", 1), ("/2.png", 2), ("/3.png", 3)] {
+            serve(&server, name, png(16, 16, shade));
+        }
+        let url = |name: &str| format!("{}{}", server.url(), name);
+        let dir = temp_cache();
+        let size = png(16, 16, 1).len() as u64;
+        let store = store_in(&dir, size * 2);
+
+        let first = store.image(&url("/1.png")).await.unwrap();
+        let second = store.image(&url("/2.png")).await.unwrap();
+        assert!(store.lookup(&url("/1.png")).is_some());
+        store.image(&url("/3.png")).await.unwrap();
+
+        assert!(store.lookup(&url("/2.png")).is_none());
+        assert!(!second.exists());
+        assert_eq!(store.lookup(&url("/1.png")), Some(first));
+        assert!(store.stats()["bytes"].as_u64().unwrap() <= size * 2);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn steam_artwork_is_cut_to_size() {
+        let server = MockServer::start().await;
+        serve(&server, "/cover.png", png(300, 200, 90));
+        let dir = temp_cache();
+        let store = store_in(&dir, DEFAULT_MAX_BYTES);
+        let url = format!("{}/cover.png", server.url());
+
+        for size in [SteamArtwork::Grid, SteamArtwork::Banner, SteamArtwork::Hero] {
+            let path = store.artwork(&url, size).await.unwrap();
+            assert_eq!(image::image_dimensions(&path).unwrap(), size.dimensions());
+        }
+        // Every size is rendered from the one download
+        assert_eq!(server.requests_to("/cover.png").len(), 1);
+        assert!(store.lookup(&artwork_key(&url, SteamArtwork::Hero)).is_some());
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn unreferenced_files_are_removed_on_open() {
+        let server = MockServer::start().await;
+        serve(&server, "/kept.png", png(4, 4, 20));
+        let dir = temp_cache();
+        let kept = store_in(&dir, DEFAULT_MAX_BYTES)
+            .image(&format!("{}/kept.png", server.url()))
+            .await
+            .unwrap();
+        fs::write(dir.join("0123abcd.png"), b"stray").unwrap();
+        fs::write(dir.join("index.json.part"), b"{").unwrap();
+
+        let reopened = store_in(&dir, DEFAULT_MAX_BYTES);
+        assert!(kept.is_file());
+        assert!(!dir.join("0123abcd.png").exists());
+        assert!(!dir.join("index.json.part").exists());
+        assert_eq!(reopened.stats()["files"], 1);
+
+        // With the index lost, nothing is referenced any more
+        fs::write(dir.join(INDEX_FILE_NAME), b"not json").unwrap();
+        let restarted = store_in(&dir, DEFAULT_MAX_BYTES);
+        assert!(!kept.exists());
+        assert_eq!(restarted.stats()["bytes"], 0);
+        fs::remove_dir_all(dir).ok();
+    }
+
+    #[tokio::test]
+    async fn prefetch_counts_failures_instead_of_raising() {
+        let server = MockServer::start().await;
+        serve(&server, "/ok.png", png(8, 8, 200));
+        server.respond(
+            "GET",
+            "/page.html",
+            MockResponse::status(200).body("<html></html>"),
+        );
+        let dir = temp_cache();
+        let store = store_in(&dir, DEFAULT_MAX_BYTES);
+        let urls = ["/ok.png", "/page.html", "/missing.png", "/ok.png"]
+            .iter()
+            .map(|path| format!("{}{}", server.url(), path))
+            .collect();
+
+        let run = store.prefetch(urls, &[SteamArtwork::Grid]).await;
+        assert_eq!(
+            run,
+            PrefetchProgress {
+                queued: 3,
+                cached: 1,
+                failed: 2,
+            }
+        );
+        assert_eq!(store.stats()["entries"], 2);
+        assert!(parse_sizes(Some(vec!["poster".to_string()])).is_err());
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Md5,
+    Crc32,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value.to_lowercase().replace('-', "").as_str() {
+            "sha1" => Some(HashAlgorithm::Sha1),
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "md5" => Some(HashAlgorithm::Md5),
+            "crc32" => Some(HashAlgorithm::Crc32),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Guess the algorithm of a bare hex digest from its length.
+    /// 64 hex characters are taken as SHA-256; BLAKE3 must be named explicitly.
+    fn from_digest_len(len: usize) -> Option<Self> {
+        match len {
+            8 => Some(HashAlgorithm::Crc32),
+            32 => Some(HashAlgorithm::Md5),
+            40 => Some(HashAlgorithm::Sha1),
+            64 => Some(HashAlgorithm::Sha256),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(&self) -> StreamHasher {
+        match self {
+            HashAlgorithm::Sha1 => StreamHasher::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => StreamHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Md5 => StreamHasher::Md5(md5::Md5::new()),
+            HashAlgorithm::Crc32 => StreamHasher::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::Blake3 => StreamHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Incremental hasher over any supported algorithm.
+#[derive(Clone)]
+pub enum StreamHasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Md5(md5::Md5),
+    Crc32(crc32fast::Hasher),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl StreamHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            StreamHasher::Sha1(h) => h.update(data),
+            StreamHasher::Sha256(h) => h.update(data),
+            StreamHasher::Md5(h) => h.update(data),
+            StreamHasher::Crc32(h) => h.update(data),
+            StreamHasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub fn finalize_hex(self) -> String {
+        match self {
+            StreamHasher::Sha1(h) => format!("{:x}", h.finalize()),
+            StreamHasher::Sha256(h) => format!("{:x}", h.finalize()),
+            StreamHasher::Md5(h) => format!("{:x}", h.finalize()),
+            StreamHasher::Crc32(h) => format!("{:08x}", h.finalize()),
+            StreamHasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Expected hashes for a download: an optional whole-file digest plus an optional
+/// list of digests for consecutive `chunk_size` byte ranges.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntegritySpec {
+    pub algorithm: HashAlgorithm,
+    #[serde(default, alias = "file_hash")]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub chunk_size: Option<u64>,
+    #[serde(default)]
+    pub chunk_hashes: Vec<String>,
+}
+
+impl IntegritySpec {
+    /// Parse the legacy `integrity_hash` string: either `"<algorithm>:<hex>"` or a bare
+    /// hex digest whose algorithm is inferred from its length. Empty strings mean no check.
+    pub fn from_legacy(value: &str) -> Result<Option<Self>> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+
+        let (algorithm, digest) = match value.split_once(':') {
+            Some((name, digest)) => (
+                HashAlgorithm::from_str(name)
+                    .ok_or_else(|| anyhow!("Unsupported hash algorithm: {}", name))?,
+                digest,
+            ),
+            None => (
+                HashAlgorithm::from_digest_len(value.len())
+                    .ok_or_else(|| anyhow!("Cannot infer hash algorithm for {}", value))?,
+                value,
+            ),
+        };
+
+        Ok(Some(Self {
+            algorithm,
+            hash: Some(digest.to_lowercase()),
+            chunk_size: None,
+            chunk_hashes: Vec::new(),
+        }))
+    }
+
+    pub fn has_chunk_hashes(&self) -> bool {
+        !self.chunk_hashes.is_empty()
+    }
+
+    /// Check the spec is usable for a download of `total_size` bytes.
+    pub fn validate(&self, total_size: u64) -> Result<()> {
+        if !self.has_chunk_hashes() {
+            return Ok(());
+        }
+        let chunk_size = self
+            .chunk_size
+            .filter(|size| *size > 0)
+            .ok_or_else(|| anyhow!("chunk_size is required with chunk_hashes"))?;
+        if total_size == 0 {
+            return Err(anyhow!("expected_size is required with chunk_hashes"));
+        }
+        let expected_chunks = total_size.div_ceil(chunk_size);
+        if expected_chunks != self.chunk_hashes.len() as u64 {
+            return Err(anyhow!(
+                "Chunk hash list has {} entries but the file has {} chunks",
+                self.chunk_hashes.len(),
+                expected_chunks
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn chunk_hash(&self, index: usize) -> Option<&str> {
+        self.chunk_hashes.get(index).map(String::as_str)
+    }
+}
+
+pub fn digests_match(actual: &str, expected: &str) -> bool {
+    actual.eq_ignore_ascii_case(expected.trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn digest(algorithm: HashAlgorithm, data: &[u8]) -> String {
+        let mut hasher = algorithm.hasher();
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+
+    #[test]
+    fn every_algorithm_hashes_incrementally() {
+        let cases = [
+            (HashAlgorithm::Sha1, "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (HashAlgorithm::Sha256, SHA256_ABC),
+            (HashAlgorithm::Md5, "900150983cd24fb0d6963f7d28e17f72"),
+            (HashAlgorithm::Crc32, "352441c2"),
+        ];
+        for (algorithm, expected) in cases {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+            assert_eq!(hasher.finalize_hex(), expected, "{:?}", algorithm);
+        }
+        assert_eq!(digest(HashAlgorithm::Blake3, b"abc").len(), 64);
+        assert_eq!(HashAlgorithm::from_str("SHA-256"), Some(HashAlgorithm::Sha256));
+        assert_eq!(HashAlgorithm::from_str("sha512"), None);
+    }
+
+    #[test]
+    fn legacy_strings_name_or_imply_the_algorithm() {
+        let named = IntegritySpec::from_legacy("blake3:ABCDEF").unwrap().unwrap();
+        assert_eq!(named.algorithm, HashAlgorithm::Blake3);
+        assert_eq!(named.hash.as_deref(), Some("abcdef"));
+
+        let bare = IntegritySpec::from_legacy(&format!(" {} ", SHA256_ABC)).unwrap().unwrap();
+        assert_eq!(bare.algorithm, HashAlgorithm::Sha256);
+        let md5 = IntegritySpec::from_legacy("900150983cd24fb0d6963f7d28e17f72").unwrap();
+        assert_eq!(md5.unwrap().algorithm, HashAlgorithm::Md5);
+
+        assert!(IntegritySpec::from_legacy("").unwrap().is_none());
+        assert!(IntegritySpec::from_legacy("whirlpool:abc").is_err());
+        assert!(IntegritySpec::from_legacy("abc123").is_err());
+    }
+
+    #[test]
+    fn file_hash_is_accepted_as_the_whole_file_digest() {
+        let spec: IntegritySpec = serde_json::from_value(json!({
+            "algorithm": "sha256",
+            "file_hash": SHA256_ABC,
+        }))
+        .unwrap();
+        assert_eq!(spec.hash.as_deref(), Some(SHA256_ABC));
+        assert!(!spec.has_chunk_hashes());
+        assert!(spec.validate(0).is_ok());
+    }
+
+    #[test]
+    fn chunk_hashes_must_cover_the_file() {
+        let spec = |chunk_size: Option<u64>, count: usize| IntegritySpec {
+            algorithm: HashAlgorithm::Crc32,
+            hash: None,
+            chunk_size,
+            chunk_hashes: vec!["00000000".to_string(); count],
+        };
+        assert!(spec(Some(4), 3).validate(10).is_ok());
+        assert!(spec(Some(4), 3).validate(12).is_ok());
+        assert!(spec(Some(4), 2).validate(10).is_err());
+        assert!(spec(Some(4), 3).validate(0).is_err());
+        assert!(spec(None, 3).validate(10).is_err());
+        assert!(spec(Some(0), 3).validate(10).is_err());
+        assert_eq!(spec(Some(4), 3).chunk_hash(2), Some("00000000"));
+        assert_eq!(spec(Some(4), 3).chunk_hash(3), None);
+    }
+
+    #[test]
+    fn digests_compare_without_case_or_padding() {
+        assert!(digests_match(SHA256_ABC, &format!("{}\n", SHA256_ABC.to_uppercase())));
+        assert!(!digests_match(SHA256_ABC, "ba7816bf"));
+    }
+}
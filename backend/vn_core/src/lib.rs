@@ -4,14 +4,24 @@ mod dlsite;
 mod downloads;
 mod game_library;
 mod hikari;
+mod http;
+mod image_cache;
+mod integrity;
+#[cfg(test)]
+mod mock_server;
 mod performance;
 mod steam;
 mod util;
 
-use dlsite::{DlsiteClient, DlsiteProduct};
+use dlsite::{
+    DlsiteBrowserOnly, DlsiteCaptchaRequired, DlsiteClient, DlsiteInvalidCredentials,
+    DlsiteMaintenance, DlsiteNotPurchased, DlsiteProduct, DlsiteSearchPage, DlsiteSessionExpired,
+    DlsiteVerificationRequired,
+};
 use downloads::DownloadManager;
 use game_library::{GameLibrary, SortBy};
-use hikari::HikariClient;
+use hikari::{HikariApp, HikariBuild, HikariClient, HikariDlc, HikariSessionExpired};
+use image_cache::ImageCache;
 use performance::{PerformanceManager, StreamingFileHandler};
 use steam::SteamIntegration;
 use pyo3::prelude::*;
@@ -24,6 +34,10 @@ fn vn_core(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     pyo3_asyncio::tokio::init(builder);
 
     m.add_class::<HikariClient>()?;
+    m.add_class::<HikariApp>()?;
+    m.add_class::<HikariBuild>()?;
+    m.add_class::<HikariDlc>()?;
+    m.add("HikariSessionExpired", py.get_type::<HikariSessionExpired>())?;
     m.add_class::<DownloadManager>()?;
     m.add_class::<GameLibrary>()?;
     m.add_class::<SortBy>()?;
@@ -31,7 +45,17 @@ fn vn_core(py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<StreamingFileHandler>()?;
     m.add_class::<DlsiteClient>()?;
     m.add_class::<DlsiteProduct>()?;
+    m.add_class::<DlsiteSearchPage>()?;
+    m.add("DlsiteSessionExpired", py.get_type::<DlsiteSessionExpired>())?;
+    m.add("DlsiteNotPurchased", py.get_type::<DlsiteNotPurchased>())?;
+    m.add("DlsiteBrowserOnly", py.get_type::<DlsiteBrowserOnly>())?;
+    m.add("DlsiteInvalidCredentials", py.get_type::<DlsiteInvalidCredentials>())?;
+    m.add("DlsiteCaptchaRequired", py.get_type::<DlsiteCaptchaRequired>())?;
+    m.add("DlsiteVerificationRequired", py.get_type::<DlsiteVerificationRequired>())?;
+    m.add("DlsiteMaintenance", py.get_type::<DlsiteMaintenance>())?;
     m.add_class::<SteamIntegration>()?;
+    m.add_class::<ImageCache>()?;
+    http::register(py, m)?;
     util::register(py, m)?;
     Ok(())
 }
@@ -0,0 +1,269 @@
+//! In-process HTTP server for exercising the store clients against recorded responses.
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// A request as the server received it.
+#[derive(Clone, Debug)]
+pub(crate) struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn query(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(String::as_str)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn form(&self) -> HashMap<String, String> {
+        parse_pairs(&self.body)
+    }
+
+    /// Whether the `Cookie` header carries `name=value`.
+    pub fn has_cookie(&self, name: &str, value: &str) -> bool {
+        self.header("cookie")
+            .map(|cookies| {
+                cookies
+                    .split(';')
+                    .any(|pair| pair.trim() == format!("{}={}", name, value))
+            })
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(value: &Value) -> Self {
+        Self::status(200)
+            .header("content-type", "application/json")
+            .body(value.to_string())
+    }
+
+    /// Serve a recorded response from `tests/fixtures`.
+    pub fn fixture(name: &str) -> Self {
+        Self::json(&fixture(name))
+    }
+
+    /// Serve a recorded HTML page from `tests/fixtures`.
+    pub fn html_fixture(name: &str) -> Self {
+        Self::status(200)
+            .header("content-type", "text/html; charset=utf-8")
+            .body(fixture_text(name))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Load a recorded JSON response from `tests/fixtures`.
+pub(crate) fn fixture(name: &str) -> Value {
+    let text = fixture_text(name);
+    serde_json::from_str(&text).unwrap_or_else(|err| panic!("bad fixture {}: {}", name, err))
+}
+
+fn fixture_text(name: &str) -> String {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("missing fixture {}: {}", path.display(), err))
+}
+
+type Handler = Arc<dyn Fn(&RecordedRequest) -> MockResponse + Send + Sync>;
+
+struct Route {
+    method: String,
+    path: String,
+    handler: Handler,
+}
+
+#[derive(Default)]
+struct Shared {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// Routes are matched on method and exact path; anything else gets a 404.
+/// The server stops when dropped.
+pub(crate) struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Mutex<Shared>>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let service_shared = Arc::clone(&shared);
+        let make_service = make_service_fn(move |_| {
+            let shared = Arc::clone(&service_shared);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let shared = Arc::clone(&shared);
+                    async move { Ok::<_, Infallible>(handle(shared, req).await) }
+                }))
+            }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = stopped.await;
+        }));
+
+        Self {
+            addr,
+            shared,
+            _shutdown: shutdown,
+        }
+    }
+
+    /// Base URL of the server, without a trailing slash.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn on<F>(&self, method: &str, path: &str, handler: F)
+    where
+        F: Fn(&RecordedRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        self.shared.lock().unwrap().routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            handler: Arc::new(handler),
+        });
+    }
+
+    /// Always answer `method path` with `response`.
+    pub fn respond(&self, method: &str, path: &str, response: MockResponse) {
+        self.on(method, path, move |_| response.clone());
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.shared.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|req| req.path == path)
+            .collect()
+    }
+}
+
+async fn handle(shared: Arc<Mutex<Shared>>, req: Request<Body>) -> Response<Body> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let recorded = RecordedRequest {
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        query: parse_pairs(parts.uri.query().unwrap_or_default()),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    value.to_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect(),
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+
+    let handler = {
+        let mut guard = shared.lock().unwrap();
+        guard.requests.push(recorded.clone());
+        guard
+            .routes
+            .iter()
+            .rev()
+            .find(|route| route.method == recorded.method && route.path == recorded.path)
+            .map(|route| Arc::clone(&route.handler))
+    };
+    let mock = match handler {
+        Some(handler) => handler(&recorded),
+        None => MockResponse::status(404).body("no route"),
+    };
+
+    let mut builder = Response::builder().status(mock.status);
+    for (name, value) in &mock.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    builder.body(Body::from(mock.body)).unwrap()
+}
+
+fn parse_pairs(input: &str) -> HashMap<String, String> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
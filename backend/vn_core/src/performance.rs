@@ -1,9 +1,9 @@
+use crate::integrity::HashAlgorithm;
 use crate::util::runtime_error;
 use anyhow::Result;
 use parking_lot::RwLock;
 use pyo3::prelude::*;
 use pyo3::types::{PyAny, PyDict};
-use sha1::{Digest, Sha1};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -428,14 +428,13 @@ impl StreamingFileHandler {
         let chunk_size = self.chunk_size;
         let algorithm = hash_type.unwrap_or_else(|| "sha1".to_string());
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            if algorithm.to_lowercase() != "sha1" {
-                return Err(runtime_error("Unsupported hash type"));
-            }
+            let algorithm = HashAlgorithm::from_str(&algorithm)
+                .ok_or_else(|| runtime_error("Unsupported hash type"))?;
             let mut file = File::open(&file_path)
                 .await
                 .map_err(|err| runtime_error(err.to_string()))?;
             let mut buffer = vec![0u8; chunk_size];
-            let mut hasher = Sha1::new();
+            let mut hasher = algorithm.hasher();
             let mut processed = 0usize;
             loop {
                 let read = file
@@ -454,7 +453,7 @@ impl StreamingFileHandler {
                 }
                 tokio::task::yield_now().await;
             }
-            Ok(Some(format!("{:x}", hasher.finalize())))
+            Ok(Some(hasher.finalize_hex()))
         })
     }
 }
@@ -50,13 +50,9 @@ impl SteamIntegration {
             PathBuf::from("/opt/steam"),
         ];
 
-        for path in possible_paths {
-            if path.exists() && path.join("config").exists() {
-                return Some(path);
-            }
-        }
-
-        None
+        possible_paths
+            .into_iter()
+            .find(|path| path.exists() && path.join("config").exists())
     }
 
     fn get_primary_user_id(&self) -> Option<String> {
@@ -1,9 +1,10 @@
 use anyhow::{Context, Result};
 use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use pythonize::{pythonize, depythonize};
 use sha1::{Digest, Sha1};
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{BufReader, Read};
 use std::path::Path;
@@ -22,6 +23,108 @@ pub fn extract_serde<T: serde::de::DeserializeOwned>(obj: &PyAny) -> PyResult<T>
     depythonize(obj).map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))
 }
 
+/// Reads fields from a JSON object by any of their known names, remembering
+/// which keys were consumed so the rest can be kept, e.g. as `extra`.
+pub(crate) struct Fields<'a> {
+    map: &'a Map<String, Value>,
+    used: HashSet<&'static str>,
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(map: &'a Map<String, Value>) -> Self {
+        Self {
+            map,
+            used: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn get(&mut self, keys: &[&'static str]) -> Option<&'a Value> {
+        self.used.extend(keys.iter().copied());
+        keys.iter()
+            .filter_map(|key| self.map.get(*key))
+            .find(|value| !value.is_null())
+    }
+
+    pub(crate) fn string(&mut self, keys: &[&'static str]) -> Option<String> {
+        match self.get(keys)? {
+            Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn u64(&mut self, keys: &[&'static str]) -> u64 {
+        match self.get(keys) {
+            Some(Value::Number(n)) => n
+                .as_u64()
+                .or_else(|| n.as_f64().filter(|f| *f > 0.0).map(|f| f as u64))
+                .unwrap_or(0),
+            Some(Value::String(s)) => s.trim().parse().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    pub(crate) fn f64(&mut self, keys: &[&'static str]) -> Option<f64> {
+        match self.get(keys)? {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn object(&mut self, keys: &[&'static str]) -> Option<&'a Map<String, Value>> {
+        self.get(keys).and_then(Value::as_object)
+    }
+
+    pub(crate) fn bool(&mut self, keys: &[&'static str]) -> Option<bool> {
+        match self.get(keys)? {
+            Value::Bool(b) => Some(*b),
+            Value::Number(n) => Some(n.as_i64() != Some(0)),
+            Value::String(s) => Some(matches!(s.as_str(), "true" | "1" | "yes")),
+            _ => None,
+        }
+    }
+
+    /// A list of names given as strings, objects with a name/code, or a comma separated string.
+    pub(crate) fn strings(&mut self, keys: &[&'static str]) -> Vec<String> {
+        let names = match self.get(keys) {
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Object(obj) => ["name", "code", "language", "title"]
+                        .iter()
+                        .find_map(|key| obj.get(*key).and_then(Value::as_str))
+                        .map(str::to_string),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            Some(Value::String(s)) => s.split(',').map(str::to_string).collect(),
+            _ => Vec::new(),
+        };
+        names
+            .into_iter()
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    pub(crate) fn objects(&mut self, keys: &[&'static str]) -> Vec<&'a Map<String, Value>> {
+        match self.get(keys) {
+            Some(Value::Array(items)) => items.iter().filter_map(Value::as_object).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub(crate) fn rest(self) -> Map<String, Value> {
+        self.map
+            .iter()
+            .filter(|(key, _)| !self.used.contains(key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
 // Helper macro for async functions that return JSON
 #[macro_export]
 macro_rules! json_result {
@@ -29,7 +132,7 @@ macro_rules! json_result {
         {
             let result = serde_json::json!($($json)+);
             Python::with_gil(|py| {
-                crate::util::value_to_py(py, &result)
+                $crate::util::value_to_py(py, &result)
             })
         }
     };
@@ -60,36 +163,29 @@ pub fn sha1_file(path: &str) -> PyResult<String> {
     sha1_file_inner(path).map_err(|err| PyRuntimeError::new_err(err.to_string()))
 }
 
-#[pyfunction]
-pub fn extract_zip(zip_path: &str, destination: &str) -> PyResult<usize> {
+pub(crate) fn extract_zip_inner(zip_path: &Path, destination: &Path) -> Result<usize> {
     let file = File::open(zip_path)
-        .with_context(|| format!("Failed to open archive {}", zip_path))
-        .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        .with_context(|| format!("Failed to open archive {}", zip_path.display()))?;
     let mut archive = ZipArchive::new(file)
-        .with_context(|| format!("Failed to read archive {}", zip_path))
-        .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        .with_context(|| format!("Failed to read archive {}", zip_path.display()))?;
 
     let mut count = 0usize;
     for i in 0..archive.len() {
         let mut file = archive
             .by_index(i)
-            .with_context(|| format!("Failed to access entry {}", i))
-            .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
-        let outpath = Path::new(destination).join(file.mangled_name());
+            .with_context(|| format!("Failed to access entry {}", i))?;
+        let outpath = destination.join(file.mangled_name());
 
-        if (&*file.name()).ends_with('/') {
-            fs::create_dir_all(&outpath).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+        if file.name().ends_with('/') {
+            fs::create_dir_all(&outpath)?;
         } else {
             if let Some(parent) = outpath.parent() {
-                fs::create_dir_all(parent)
-                    .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+                fs::create_dir_all(parent)?;
             }
             let mut outfile = File::create(&outpath)
-                .with_context(|| format!("Failed to create {}", outpath.display()))
-                .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+                .with_context(|| format!("Failed to create {}", outpath.display()))?;
             std::io::copy(&mut file, &mut outfile)
-                .with_context(|| format!("Failed to write {}", outpath.display()))
-                .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+                .with_context(|| format!("Failed to write {}", outpath.display()))?;
         }
         count += 1;
     }
@@ -97,6 +193,12 @@ pub fn extract_zip(zip_path: &str, destination: &str) -> PyResult<usize> {
     Ok(count)
 }
 
+#[pyfunction]
+pub fn extract_zip(zip_path: &str, destination: &str) -> PyResult<usize> {
+    extract_zip_inner(Path::new(zip_path), Path::new(destination))
+        .map_err(|err| PyRuntimeError::new_err(err.to_string()))
+}
+
 #[pyfunction]
 pub fn remove_empty_directories(root: &str) -> PyResult<usize> {
     let mut removed = 0usize;
@@ -106,15 +208,14 @@ pub fn remove_empty_directories(root: &str) -> PyResult<usize> {
         .filter_map(|e| e.ok())
     {
         let path = entry.path();
-        if path.is_dir() {
-            if fs::read_dir(path)
+        if path.is_dir()
+            && fs::read_dir(path)
                 .map_err(|err| PyRuntimeError::new_err(err.to_string()))?
                 .next()
                 .is_none()
-            {
-                fs::remove_dir(path).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
-                removed += 1;
-            }
+        {
+            fs::remove_dir(path).map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+            removed += 1;
         }
     }
     Ok(removed)
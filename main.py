@@ -2,7 +2,6 @@ import os
 import json
 import asyncio
 import time
-import copy
 from pathlib import Path
 from typing import Dict, List, Any, Optional, Awaitable, Tuple
 
@@ -12,15 +11,30 @@ import decky
 # Import our Rust backend components
 from vn_core import (
     HikariClient,
+    HikariSessionExpired,
     DlsiteClient,
     DlsiteProduct,
+    DlsiteSessionExpired,
+    DlsiteNotPurchased,
+    DlsiteBrowserOnly,
+    DlsiteInvalidCredentials,
+    DlsiteCaptchaRequired,
+    DlsiteVerificationRequired,
+    DlsiteMaintenance,
     DownloadManager,
     GameLibrary,
     SortBy,
     PerformanceManager,
     SteamIntegration,
+    ImageCache,
+    configure_http,
+    get_http_config,
 )
 
+UPDATE_CHECK_INTERVAL_SEC = 6 * 60 * 60
+UPDATES_EVENT_NAME = "visual_novel_manager/updates-available"
+HTTP_TRACE_FILE_NAME = "http_trace.jsonl"
+
 class Plugin:
     async def _main(self):
         """Initialize the plugin"""
@@ -50,17 +64,22 @@ class Plugin:
         # Initialize preference defaults
         self._default_preferences = self._load_default_preferences()
         self.preferences = dict(self._default_preferences)
+        self.network_settings: Dict[str, Any] = {}
 
         # Initialize Rust backend modules
-        self.hikari_api = HikariClient()
-        self.dlsite_api = DlsiteClient()
+        self.hikari_api = HikariClient(data_dir=str(self.runtime_dir))
+        self.dlsite_api = DlsiteClient(data_dir=str(self.runtime_dir))
         self.download_manager = DownloadManager(str(self.games_dir))
         self.steam_integration = SteamIntegration(str(self.games_dir))
         self.game_library = GameLibrary(str(self.games_dir))
+        self.image_cache = ImageCache(str(self.runtime_dir / "image_cache"))
 
         # Load settings
         await self._load_settings()
 
+        # Periodic update check; each pass honours the autoUpdate preference
+        self._update_check_task: Optional[asyncio.Task] = asyncio.create_task(self._update_check_loop())
+
         # Initialize performance manager
         self.performance_manager = PerformanceManager()
         await self.performance_manager.initialize()
@@ -68,6 +87,11 @@ class Plugin:
         # Initialize modules
         await self.hikari_api.initialize()
         await self.dlsite_api.initialize()
+        # Check the DLsite session restored from the saved cookie jar
+        try:
+            await self.dlsite_api.test_authentication()
+        except Exception as err:
+            decky.logger.warning(f"Could not verify saved DLsite session: {err}")
         # Download manager initialization handled in Rust
 
 
@@ -80,6 +104,10 @@ class Plugin:
         # Save settings
         await self._save_settings()
 
+        update_task = getattr(self, "_update_check_task", None)
+        if update_task:
+            update_task.cancel()
+
         # Clean up modules with timeouts to avoid hanging the unload
         async def safe_wait(coro: Awaitable[Any], name: str):
             try:
@@ -121,11 +149,14 @@ class Plugin:
                     contents = await asyncio.to_thread(settings_file.read_text, encoding="utf-8")
                     settings = json.loads(contents)
 
-                # Restore API state
+                # Restore API state. The session itself lives in hikari_cache.json;
+                # tokens in settings.json only come from older versions.
                 if settings.get('hikari_token') or settings.get('current_server'):
                     await self.hikari_api.import_state(
                         settings.get('hikari_token'),
-                        settings.get('current_server')
+                        settings.get('current_server'),
+                        settings.get('hikari_refresh_token'),
+                        settings.get('hikari_token_expires_at'),
                     )
 
                 # Restore game configurations
@@ -134,6 +165,9 @@ class Plugin:
                     pass
 
                 self.preferences = self._normalize_preferences(settings.get('preferences'))
+                network = settings.get('network')
+                self.network_settings = network if isinstance(network, dict) else {}
+                self._apply_network_settings()
             else:
                 self.preferences = dict(self._default_preferences)
 
@@ -146,10 +180,10 @@ class Plugin:
             # Get Hikari state from Rust backend
             hikari_state = await self.hikari_api.export_state()
             settings = {
-                'hikari_token': hikari_state.get('token'),
                 'current_server': hikari_state.get('selected_cdn'),
                 'steam_games': [],  # Will be handled by Steam integration if needed
                 'preferences': self.preferences,
+                'network': self.network_settings,
             }
 
             settings_file = Path(decky.DECKY_PLUGIN_SETTINGS_DIR) / "settings.json"
@@ -166,6 +200,36 @@ class Plugin:
         except Exception as e:
             decky.logger.error(f"Failed to save settings: {e}")
 
+    def _http_config(self, network: Dict[str, Any]) -> Dict[str, Any]:
+        """Translate the saved network settings into the Rust HTTP config"""
+        config = {k: v for k, v in network.items() if k != 'trace'}
+        config['trace_log'] = str(self.log_dir / HTTP_TRACE_FILE_NAME) if network.get('trace') else None
+        return config
+
+    def _apply_network_settings(self) -> None:
+        """Push timeouts, proxy, rate limits and tracing to the Rust HTTP layer"""
+        try:
+            configure_http(self._http_config(self.network_settings))
+        except Exception as err:
+            decky.logger.warning(f"Invalid network settings, using defaults: {err}")
+            configure_http(None)
+
+    async def get_network_settings(self) -> Dict[str, Any]:
+        """Effective network settings (proxy credentials redacted)"""
+        return {**get_http_config(), 'trace': bool(self.network_settings.get('trace'))}
+
+    async def update_network_settings(self, updates: Dict[str, Any]) -> Dict[str, Any]:
+        """Change timeouts, proxy, rate limits or the redacted request trace (`trace: true`)"""
+        if isinstance(updates, dict):
+            merged = {**self.network_settings, **updates}
+            try:
+                configure_http(self._http_config(merged))
+            except Exception as err:
+                return {"success": False, "error": str(err)}
+            self.network_settings = merged
+            self.request_save_settings()
+        return {"success": True, "settings": await self.get_network_settings()}
+
     def _load_default_preferences(self) -> Dict[str, Any]:
         base_defaults: Dict[str, Any] = {
             "language": "en",
@@ -331,42 +395,108 @@ class Plugin:
             self.request_save_settings()
         return {"success": success}
 
+    async def _on_hikari_session_expired(self, err: Exception) -> None:
+        """Drop the stale token and tell the UI to show the login form again"""
+        decky.logger.warning(f"Hikari session expired: {err}")
+        self.request_save_settings()
+        await decky.emit("visual_novel_manager/hikari-session-expired", str(err))
+
+    async def benchmark_hikari_cdn_servers(self, sample_url: Optional[str] = None) -> Dict[str, Any]:
+        """Measure all CDN servers and switch to the fastest one"""
+        result = await self.hikari_api.benchmark_cdn_servers(sample_url)
+        if result.get("selected"):
+            self.request_save_settings()
+        return result
+
+    async def set_hikari_offline_mode(self, enabled: bool) -> Dict[str, Any]:
+        """Serve the cached Hikari catalog without touching the network"""
+        return await self.hikari_api.set_offline_mode(enabled)
+
+    async def get_hikari_cache_info(self) -> Dict[str, Any]:
+        """Age and staleness of the cached Hikari catalog and CDN list"""
+        return await self.hikari_api.get_cache_info()
+
+    async def list_hikari_profiles(self) -> List[Dict[str, Any]]:
+        """Saved Hikari account profiles and their login state"""
+        return await self.hikari_api.list_profiles()
+
+    async def switch_hikari_profile(self, name: str) -> Dict[str, Any]:
+        """Make another Hikari account profile active"""
+        result = await self.hikari_api.switch_profile(name)
+        self.request_save_settings()
+        return result
+
+    async def remove_hikari_profile(self, name: str) -> Dict[str, Any]:
+        """Forget an inactive Hikari account profile"""
+        return await self.hikari_api.remove_profile(name)
+
     async def get_hikari_game_list(self, force_refresh: bool = False) -> List[Dict[str, Any]]:
         """Get game library with local status"""
-        # If not logged in, return empty list
-        if not self.hikari_api.is_logged_in():
+        # If not logged in, return empty list (offline mode still serves the cached catalog)
+        if not self.hikari_api.is_logged_in() and not self.hikari_api.is_offline():
             return []
 
         # Let Rust-side cache handle reuse unless force_refresh is True
-        library_result = await self.hikari_api.fetch_library(force_refresh)
-        base_apps = library_result.get("apps", [])
+        try:
+            apps = await self.hikari_api.get_apps(force_refresh)
+        except HikariSessionExpired as err:
+            await self._on_hikari_session_expired(err)
+            return []
 
-        games = copy.deepcopy(base_apps)
+        self._prefetch_images([app.cover for app in apps])
 
         # Enrich list in Rust for installed/downloading/progress
         try:
-            enriched = self.game_library.enrich_games(games, self.download_manager)
+            games = self.game_library.enrich_games(apps, self.download_manager)
             # Update library cache for later metadata lookups
-            self.game_library.update_library_cache(enriched)
-            return enriched
+            self.game_library.update_library_cache(games)
         except Exception as err:
             decky.logger.warning(f"enrich_games failed, falling back: {err}")
-            # Fallback to raw list
-            return games
+            # Fallback to the plain catalog entries
+            games = [app.to_game() for app in apps]
+        for game in games:
+            game["thumbnail_path"] = self._cached_image_path(game.get("thumbnail"))
+        return games
 
-    async def check_game_updates(self) -> List[Dict[str, Any]]:
-        """Check for game updates using incremental API"""
-        if not self.hikari_api.is_logged_in():
+    async def check_game_updates(self, force_refresh: bool = False) -> List[Dict[str, Any]]:
+        """Compare installed Hikari builds against the catalog"""
+        if not self.hikari_api.is_logged_in() and not self.hikari_api.is_offline():
+            return []
+
+        try:
+            return await self.hikari_api.check_updates(self.game_library, force_refresh)
+        except HikariSessionExpired as err:
+            await self._on_hikari_session_expired(err)
             return []
 
-        # For now, return empty list as update checking will be handled differently in Rust
-        # TODO: Implement update checking in Rust backend
-        return []
+    async def _update_check_loop(self) -> None:
+        """Refresh the catalog periodically and report available updates to the UI"""
+        while True:
+            await asyncio.sleep(UPDATE_CHECK_INTERVAL_SEC)
+            if not self.preferences.get("autoUpdate", True):
+                continue
+            try:
+                updates = await self.check_game_updates(force_refresh=True)
+                if updates:
+                    await decky.emit(UPDATES_EVENT_NAME, updates)
+            except asyncio.CancelledError:
+                raise
+            except Exception as err:
+                decky.logger.warning(f"Background update check failed: {err}")
 
     # DLsite API methods
     async def dlsite_login(self, username: str, password: str) -> Dict[str, Any]:
         """Login to DLsite"""
-        result = await self.dlsite_api.login(username, password)
+        try:
+            result = await self.dlsite_api.login(username, password)
+        except DlsiteInvalidCredentials as err:
+            return {"success": False, "error": "invalid_credentials", "message": str(err)}
+        except DlsiteCaptchaRequired as err:
+            return {"success": False, "error": "captcha_required", "message": str(err)}
+        except DlsiteVerificationRequired as err:
+            return {"success": False, "error": "verification_required", "message": str(err)}
+        except DlsiteMaintenance as err:
+            return {"success": False, "error": "maintenance", "message": str(err)}
         if result["success"]:
             await self._save_settings()
         return result
@@ -377,17 +507,17 @@ class Plugin:
         await self._save_settings()
         return {"success": success}
 
-    async def get_dlsite_login_status(self) -> Dict[str, bool]:
+    async def get_dlsite_login_status(self) -> Dict[str, Any]:
         """Get DLsite login status"""
         try:
             is_logged_in = await self.dlsite_api.is_logged_in()
         except Exception as err:
             decky.logger.error(f"Failed to read DLsite login status: {err}")
             is_logged_in = False
-        return {"isLoggedIn": bool(is_logged_in)}
+        return {"isLoggedIn": bool(is_logged_in), "sessionState": self.dlsite_api.session_state()}
 
-    async def get_dlsite_game_list(self, force_refresh: bool = False) -> List[Dict[str, Any]]:
-        """Get DLsite library"""
+    async def get_dlsite_game_list(self, force_refresh: bool = False, categories: Optional[List[str]] = None) -> List[Dict[str, Any]]:
+        """Get DLsite library, optionally only some work categories (game, voice, comic, cg, tool, other)"""
         # Validate login; avoid serving stale cache when not logged in
         try:
             if not await self.dlsite_api.is_logged_in():
@@ -397,7 +527,17 @@ class Plugin:
             pass
 
         # Use Rust-side cached accessor with TTL and optional force
-        dlsite_products = await self.dlsite_api.get_library_cached(force_refresh, int(self._cache_ttl_sec))
+        try:
+            dlsite_products = await self.dlsite_api.get_library_cached(force_refresh, int(self._cache_ttl_sec), categories)
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return []
+
+        report = await self.dlsite_api.last_library_sync()
+        if report and report.get("missing_pages"):
+            decky.logger.warning(f"DLsite library is incomplete, missing purchase pages {report['missing_pages']}")
+
+        self._prefetch_images([product.thumbnail for product in dlsite_products])
 
         games: List[Dict[str, Any]] = []
         for product in dlsite_products:
@@ -407,6 +547,7 @@ class Plugin:
                 "developer": product.group_name,
                 "description": product.description,
                 "thumbnail": product.thumbnail,
+                "thumbnail_path": self._cached_image_path(product.thumbnail),
                 "tags": product.tags,
                 "size": self.format_file_size(product.file_size),
                 "expected_size": product.file_size,
@@ -414,6 +555,17 @@ class Plugin:
                 "age_rating": product.age_category,
                 "work_type": product.work_type,
                 "release_date": product.release_date,
+                "voice_actors": product.voice_actors,
+                "scenario": product.scenario,
+                "illustrators": product.illustrators,
+                "os": product.os,
+                "file_formats": product.file_formats,
+                "series": product.series,
+                "sample_images": product.sample_images,
+                "rating": product.rating,
+                "language_editions": product.language_editions,
+                "category": product.category,
+                "deck_verdict": product.deck_verdict,
                 "platform": "dlsite",
             })
 
@@ -425,44 +577,98 @@ class Plugin:
             decky.logger.warning(f"enrich_games failed: {err}")
             return games
 
+    async def sync_dlsite_library(self, full: bool = False) -> Dict[str, Any]:
+        """Sync new DLsite purchases (or everything with full) and report missing pages"""
+        try:
+            return await self.dlsite_api.sync_library(full)
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return {"success": False, "error": "session_expired", "message": str(err)}
+
+    async def _on_dlsite_session_expired(self, err: Exception) -> None:
+        """Tell the UI to show the DLsite login form again"""
+        decky.logger.warning(f"DLsite session expired: {err}")
+        await decky.emit("visual_novel_manager/dlsite-session-expired", str(err))
+
     async def download_dlsite_game(self, game_id: str) -> Dict[str, Any]:
         """Download a game from DLsite"""
-        # Get download URLs (supports multi-part downloads)
-        download_urls = await self.dlsite_api.get_download_urls(game_id)
-        if not download_urls:
+        # Resolve the authenticated download links to the final file URLs
+        try:
+            parts = await self.dlsite_api.resolve_download(game_id)
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return {"success": False, "error": "session_expired", "message": str(err)}
+        except DlsiteNotPurchased as err:
+            return {"success": False, "error": "not_purchased", "message": str(err)}
+        except DlsiteBrowserOnly as err:
+            return {"success": False, "error": "browser_only", "message": str(err)}
+        if not parts:
             return {"success": False, "message": "Failed to get download URLs"}
 
         # Get game info for display name
-        dlsite_products = await self.dlsite_api.get_library()
+        dlsite_products = await self.dlsite_api.get_library_cached(False, int(self._cache_ttl_sec))
         product_info = next((p for p in dlsite_products if p.id == game_id), None)
         game_name = product_info.title if product_info else f"DLsite Product {game_id}"
 
-        # Start download with multiple URLs if needed (DLsite supports multi-part)
-        url_list = [url for url in download_urls if isinstance(url, str)]
-        if not url_list:
-            return {"success": False, "message": "No valid download URLs returned"}
+        # Multi-part installs are not supported: DLsite splits large works into
+        # multi-volume RAR/EXE sets, which neither joining the parts nor the zip
+        # extractor can install. The resolved parts are returned so they can be
+        # fetched by hand.
+        if len(parts) > 1:
+            return {
+                "success": False,
+                "error": "split_download",
+                "message": f"{game_name} is split into {len(parts)} parts; "
+                           "multi-part works cannot be installed automatically yet",
+                "parts": parts,
+            }
 
+        part = parts[0]
         return await self.download_manager.start_download(
             game_id,
             game_name,
-            url_list,
-            product_info.file_size if product_info else None,
+            [part["url"]],
+            part.get("size") or (product_info.file_size if product_info else None),
             ""  # DLsite doesn't provide hashes typically
         )
 
-    async def search_dlsite_games(self, query: str, category: str = "all") -> List[Dict[str, Any]]:
-        """Search for games on DLsite"""
-        search_results = await self.dlsite_api.search_products(query, category)
+    async def get_dlsite_serial_keys(self, game_id: str, refresh: bool = False) -> Dict[str, Any]:
+        """Serial numbers for a DLsite work, from the game's metadata unless refresh is set"""
+        if not refresh:
+            stored = self.game_library.get_serial_keys(game_id)
+            if stored:
+                return {"success": True, "serial_keys": stored}
+
+        # Only installed games get the keys written to their metadata
+        library = self.game_library if self.game_library.is_game_installed(game_id) else None
+        try:
+            keys = await self.dlsite_api.get_serial_keys(game_id, library)
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return {"success": False, "error": "session_expired", "message": str(err)}
+        except DlsiteNotPurchased as err:
+            return {"success": False, "error": "not_purchased", "message": str(err)}
+        except Exception as err:
+            return {"success": False, "message": str(err)}
+        return {"success": True, "serial_keys": keys}
+
+    async def search_dlsite_games(self, query: str, category: str = "all", filters: Optional[Dict[str, Any]] = None) -> Dict[str, Any]:
+        """Search DLsite; filters take the typed query fields (genres, circle, age_category,
+        min_price, max_price, released_after, released_before, language, order, page, per_page)"""
+        search = dict(filters or {})
+        search["keyword"] = query
+        result = await self.dlsite_api.search_products(search, category)
 
         # Convert to common format
         games = []
-        for product in search_results:
+        for product in result.items:
             games.append({
                 "id": product.id,
                 "name": product.title,
                 "developer": product.group_name,
                 "description": product.description,
                 "thumbnail": product.thumbnail,
+                "thumbnail_path": self._cached_image_path(product.thumbnail),
                 "tags": product.tags,
                 "size": self.format_file_size(product.file_size),
                 "expected_size": product.file_size,
@@ -470,10 +676,103 @@ class Plugin:
                 "age_rating": product.age_category,
                 "work_type": product.work_type,
                 "release_date": product.release_date,
+                "category": product.category,
+                "deck_verdict": product.deck_verdict,
                 "platform": "dlsite"
             })
 
-        return games
+        return {
+            "games": games,
+            "total": result.total,
+            "page": result.page,
+            "per_page": result.per_page,
+            "has_next": result.has_next,
+        }
+
+    async def get_dlsite_wishlist(self, on_sale_only: bool = False) -> List[Dict[str, Any]]:
+        """Get the DLsite wishlist with sale prices, optionally only discounted works"""
+        try:
+            products = await self.dlsite_api.get_wishlist(on_sale_only)
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return []
+
+        return [{
+            "id": product.id,
+            "name": product.title,
+            "developer": product.group_name,
+            "thumbnail": product.thumbnail,
+            "thumbnail_path": self._cached_image_path(product.thumbnail),
+            "tags": product.tags,
+            "price": product.price,
+            "official_price": product.official_price,
+            "discount_rate": product.discount_rate,
+            "sale_ends_at": product.sale_ends_at,
+            "on_sale": product.on_sale,
+            "age_rating": product.age_category,
+            "release_date": product.release_date,
+            "category": product.category,
+            "deck_verdict": product.deck_verdict,
+            "platform": "dlsite",
+        } for product in products]
+
+    async def add_to_dlsite_wishlist(self, game_id: str) -> Dict[str, Any]:
+        """Add a DLsite work to the wishlist"""
+        return await self._update_dlsite_wishlist(self.dlsite_api.add_to_wishlist, game_id)
+
+    async def remove_from_dlsite_wishlist(self, game_id: str) -> Dict[str, Any]:
+        """Remove a DLsite work from the wishlist"""
+        return await self._update_dlsite_wishlist(self.dlsite_api.remove_from_wishlist, game_id)
+
+    async def _update_dlsite_wishlist(self, update, game_id: str) -> Dict[str, Any]:
+        try:
+            await update(game_id)
+            return {"success": True}
+        except DlsiteSessionExpired as err:
+            await self._on_dlsite_session_expired(err)
+            return {"success": False, "error": "session_expired", "message": str(err)}
+        except Exception as err:
+            return {"success": False, "message": str(err)}
+
+    def _prefetch_images(self, urls: List[str]) -> None:
+        """Cache library artwork in the background so it is there offline"""
+        try:
+            self.image_cache.prefetch([url for url in urls if url])
+        except Exception as err:
+            decky.logger.warning(f"Image prefetch failed to start: {err}")
+
+    def _cached_image_path(self, url: Optional[str]) -> Optional[str]:
+        """Local copy of a store image if it is cached, so the UI can show it offline"""
+        if not url:
+            return None
+        try:
+            return self.image_cache.get_cached_path(url)
+        except Exception:
+            return None
+
+    async def get_cached_image(self, url: str) -> Dict[str, Any]:
+        """Local path of a store image, downloading it when it is not cached yet"""
+        try:
+            return {"success": True, "path": await self.image_cache.get_image(url)}
+        except Exception as err:
+            return {"success": False, "path": self.image_cache.get_cached_path(url), "message": str(err)}
+
+    async def get_steam_artwork(self, url: str, size: str = "grid") -> Dict[str, Any]:
+        """Local path of a store image cut to a Steam size (grid, banner or hero)"""
+        try:
+            return {"success": True, "path": await self.image_cache.get_steam_artwork(url, size)}
+        except Exception as err:
+            return {"success": False, "message": str(err)}
+
+    async def get_image_cache_stats(self) -> Dict[str, Any]:
+        return self.image_cache.stats()
+
+    async def clear_image_cache(self) -> Dict[str, Any]:
+        try:
+            self.image_cache.clear()
+            return {"success": True}
+        except Exception as err:
+            return {"success": False, "message": str(err)}
 
     def format_file_size(self, size_bytes: int) -> str:
         """Format file size in bytes to human readable format"""
@@ -491,36 +790,26 @@ class Plugin:
     # Frontend API methods - Downloads
     async def download_hikari_game(self, game_id: str) -> Dict[str, Any]:
         """Start downloading a game with multi-source support using official API"""
+        # Sign the latest build and route it through the preferred CDNs
+        try:
+            prepared = await self.hikari_api.prepare_download(game_id)
+        except HikariSessionExpired as err:
+            await self._on_hikari_session_expired(err)
+            return {"success": False, "message": str(err), "sessionExpired": True}
+        except Exception as err:
+            return {"success": False, "message": f"Failed to get download URLs: {err}"}
 
-        # Get signed download URLs
-        download_result = await self.hikari_api.get_signed_urls(game_id, 0)
-        download_urls = download_result.get("result", [])
-        if not download_urls:
-            return {"success": False, "message": "Failed to get download URLs"}
-
-        # Extract URLs from the result
-        urls = []
-        if isinstance(download_urls, list):
-            urls = [url for url in download_urls if isinstance(url, str)]
-        elif isinstance(download_urls, dict) and "urls" in download_urls:
-            urls = download_urls["urls"]
-
-        if not urls:
-            return {"success": False, "message": "No download URLs found"}
-
-        # Get game info for display name and metadata (guard None)
-        game_info = self.game_library.get_cached_game_info(game_id)
-        game_name = game_info.get("name", f"Game {game_id}") if isinstance(game_info, dict) else f"Game {game_id}"
-        expected_size = game_info.get("expected_size") if isinstance(game_info, dict) else None
-        integrity_hash = game_info.get("integrity_hash") if isinstance(game_info, dict) else None
+        game_name = prepared.get("game_name") or f"Game {game_id}"
 
         # Start multi-source download
         return await self.download_manager.start_download(
             game_id,
             game_name,
-            urls,
-            expected_size,
-            integrity_hash,
+            prepared["sources"],
+            prepared.get("expected_size"),
+            prepared.get("integrity_hash"),
+            None,
+            prepared.get("install_metadata"),
         )
 
     async def pause_download(self, game_id: str) -> Dict[str, bool]:
@@ -533,11 +822,27 @@ class Plugin:
         result = await self.download_manager.resume_download(game_id)
         return {"success": result.get("success", False)}
 
-    async def cancel_download(self, game_id: str) -> Dict[str, bool]:
-        """Cancel a download"""
-        result = await self.download_manager.cancel_download(game_id)
+    async def cancel_download(self, game_id: str, keep_partial: bool = False) -> Dict[str, bool]:
+        """Cancel a download, optionally keeping partial data for a later restart"""
+        result = await self.download_manager.cancel_download(game_id, keep_partial)
         return {"success": result.get("success", False)}
 
+    async def pause_all_downloads(self) -> Dict[str, Any]:
+        """Pause every running download"""
+        return await self.download_manager.pause_all()
+
+    async def resume_all_downloads(self) -> Dict[str, Any]:
+        """Resume every paused download"""
+        return await self.download_manager.resume_all()
+
+    async def cancel_downloads(self, filter: Optional[Dict[str, Any]] = None, keep_partial: bool = False) -> Dict[str, Any]:
+        """Cancel downloads matching a status / game id / name filter"""
+        return await self.download_manager.cancel_downloads(filter, keep_partial)
+
+    async def retry_failed_downloads(self) -> Dict[str, Any]:
+        """Restart every failed download"""
+        return await self.download_manager.retry_failed()
+
     async def get_active_downloads(self) -> List[Dict[str, Any]]:
         """Get list of active downloads"""
         return self.download_manager.get_active_downloads()
@@ -1,4 +1,4 @@
-import { FC, memo, useMemo } from "react";
+import { FC, memo, useMemo, useState } from "react";
 import {
   ButtonItem,
   PanelSectionRow,
@@ -19,6 +19,8 @@ interface Game {
   circle?: string; // DLsite specific
   price?: number; // DLsite specific
   tags?: string[]; // DLsite specific
+  thumbnail?: string;
+  thumbnail_path?: string | null; // Local copy from the image cache
 }
 
 interface GameListProps {
@@ -29,6 +31,24 @@ interface GameListProps {
   t: (key: string) => string;
 }
 
+// Prefer the cached copy so artwork shows offline; fall back to the store URL
+const GameThumbnail: FC<{ game: Game }> = ({ game }) => {
+  const [useRemote, setUseRemote] = useState(false);
+  const src = game.thumbnail_path && !useRemote
+    ? `file://${game.thumbnail_path}`
+    : game.thumbnail;
+  if (!src) return null;
+
+  return (
+    <img
+      src={src}
+      alt=""
+      style={{ width: "48px", height: "48px", objectFit: "cover", borderRadius: "4px" }}
+      onError={() => setUseRemote(true)}
+    />
+  );
+};
+
 export const GameList: FC<GameListProps> = memo(({
   games,
   onDownload,
@@ -65,7 +85,8 @@ export const GameList: FC<GameListProps> = memo(({
       {memoizedGames.map((game) => (
         <PanelSectionRow key={`${game.platform}-${game.id}`}>
           <Focusable style={{ display: "flex", flexDirection: "column", gap: "8px" }}>
-            <div style={{ display: "flex", justifyContent: "space-between", alignItems: "center" }}>
+            <div style={{ display: "flex", justifyContent: "space-between", alignItems: "center", gap: "8px" }}>
+              <GameThumbnail game={game} />
               <div style={{ flex: 1 }}>
                 <div style={{ display: "flex", alignItems: "center", gap: "8px", marginBottom: "4px" }}>
                   {getPlatformIcon(game.platform)}
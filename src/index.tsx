@@ -10,7 +10,7 @@ import {
 } from "@decky/ui";
 import { FaBook, FaGamepad, FaCog, FaDownload, FaSteam, FaPlay } from "react-icons/fa";
 import { useState, FC, useEffect, useMemo, useCallback } from "react";
-import { call } from "@decky/api";
+import { call, addEventListener, removeEventListener } from "@decky/api";
 import { HikariLogin } from "./components/HikariLogin";
 import { DLsiteLogin } from "./components/DLsiteLogin";
 import { GameList, Platform } from "./components/GameList";
@@ -21,6 +21,8 @@ import { useTranslation } from "./hooks/useTranslation";
 import { Language } from "./locales";
 import { commonStyles } from "./utils/styles";
 
+const HIKARI_SESSION_EXPIRED_EVENT = "visual_novel_manager/hikari-session-expired";
+const DLSITE_SESSION_EXPIRED_EVENT = "visual_novel_manager/dlsite-session-expired";
 
 interface Game {
   id: string;
@@ -33,6 +35,8 @@ interface Game {
   circle?: string; // DLsite specific
   price?: number; // DLsite specific
   tags?: string[]; // DLsite specific
+  thumbnail?: string;
+  thumbnail_path?: string | null; // Local copy from the image cache
 }
 
 interface Preferences {
@@ -190,6 +194,25 @@ const Content: FC = () => {
     checkDLsiteLoginStatus();
   }, []);
 
+  useEffect(() => {
+    const onSessionExpired = () => {
+      setIsHikariLoggedIn(false);
+      setHikariGames([]);
+      setError(t("errors.session_expired"));
+    };
+    const onDLsiteSessionExpired = () => {
+      setIsDLsiteLoggedIn(false);
+      setDLsiteGames([]);
+      setError(t("errors.dlsite_session_expired"));
+    };
+    addEventListener(HIKARI_SESSION_EXPIRED_EVENT, onSessionExpired);
+    addEventListener(DLSITE_SESSION_EXPIRED_EVENT, onDLsiteSessionExpired);
+    return () => {
+      removeEventListener(HIKARI_SESSION_EXPIRED_EVENT, onSessionExpired);
+      removeEventListener(DLSITE_SESSION_EXPIRED_EVENT, onDLsiteSessionExpired);
+    };
+  }, [t]);
+
   useEffect(() => {
     if (isHikariLoggedIn) {
       refreshHikariGames();
@@ -107,6 +107,8 @@ export const en = {
     missingCredentials: "Please enter username and password",
     loginFailed: "Login failed",
     fetch_games_failed: "Failed to fetch game list",
+    session_expired: "Your Hikari Field session expired. Please log in again.",
+    dlsite_session_expired: "Your DLsite session expired. Please log in again.",
     download_failed: "Download failed",
     error_occurred: "Error"
   },
@@ -107,6 +107,8 @@ export const ja = {
     missingCredentials: "ユーザー名とパスワードを入力してください",
     loginFailed: "ログインに失敗しました",
     fetch_games_failed: "ゲームリストの取得に失敗しました",
+    session_expired: "Hikari Fieldのセッションが切れました。再度ログインしてください。",
+    dlsite_session_expired: "DLsiteのセッションが切れました。再度ログインしてください。",
     download_failed: "ダウンロードに失敗しました",
     error_occurred: "エラー"
   },
@@ -107,6 +107,8 @@ export const zh_CN = {
     missingCredentials: "请输入用户名和密码",
     loginFailed: "登录失败",
     fetch_games_failed: "获取游戏列表失败",
+    session_expired: "Hikari Field 会话已过期，请重新登录。",
+    dlsite_session_expired: "DLsite 会话已过期，请重新登录。",
     download_failed: "下载失败",
     error_occurred: "错误"
   },
@@ -107,6 +107,8 @@ export const zh_TW = {
     missingCredentials: "請輸入使用者名稱和密碼",
     loginFailed: "登入失敗",
     fetch_games_failed: "獲取遊戲清單失敗",
+    session_expired: "Hikari Field 工作階段已過期，請重新登入。",
+    dlsite_session_expired: "DLsite 工作階段已過期，請重新登入。",
     download_failed: "下載失敗",
     error_occurred: "錯誤"
   },